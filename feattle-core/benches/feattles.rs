@@ -0,0 +1,102 @@
+//! Measure the cost of reading and updating feattles.
+//!
+//! Run `cargo bench -p feattle-core` to measure the default storage, behind a `RwLock`, and
+//! `cargo bench -p feattle-core --features arc-swap` to measure the lock-free storage. The
+//! benchmarks are prefixed with the storage mode, so that both appear side by side in the reports.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use feattle_core::persist::{InMemoryPersistence, NoPersistence, Persist};
+use feattle_core::{feattles, Feattles};
+use serde_json::json;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use tokio::runtime::Runtime;
+
+const MODE: &str = if cfg!(feature = "arc-swap") {
+    "arc-swap"
+} else {
+    "rwlock"
+};
+
+feattles! {
+    struct Toggles {
+        is_enabled: bool,
+        max_items: i32 = 10,
+        blocked_actions: Vec<String>,
+    }
+}
+
+fn new_runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+}
+
+fn new_toggles(runtime: &Runtime, persistence: Arc<dyn Persist>) -> Arc<Toggles> {
+    let toggles = Arc::new(Toggles::new(persistence));
+    runtime.block_on(toggles.reload()).unwrap();
+    toggles
+}
+
+fn read(toggles: &Toggles) -> usize {
+    let mut result = *toggles.is_enabled() as usize;
+    result += *toggles.max_items() as usize;
+    result + toggles.blocked_actions().len()
+}
+
+fn reads(c: &mut Criterion) {
+    let runtime = new_runtime();
+    let toggles = new_toggles(&runtime, Arc::new(NoPersistence));
+    c.bench_function(&format!("{}/read", MODE), |b| {
+        b.iter(|| read(black_box(&toggles)))
+    });
+
+    // Keep updating the values in another thread, to measure the contention
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = thread::spawn({
+        let toggles = toggles.clone();
+        let stop = stop.clone();
+        move || {
+            let runtime = new_runtime();
+            let mut max_items = 0;
+            while !stop.load(Ordering::Relaxed) {
+                max_items += 1;
+                let update = toggles.update("max_items", json!(max_items), "bench".to_owned());
+                runtime.block_on(update).unwrap();
+            }
+        }
+    });
+    c.bench_function(&format!("{}/read_while_updating", MODE), |b| {
+        b.iter(|| read(black_box(&toggles)))
+    });
+    stop.store(true, Ordering::Relaxed);
+    writer.join().unwrap();
+}
+
+fn updates(c: &mut Criterion) {
+    let runtime = new_runtime();
+
+    // `NoPersistence` does not keep the history, so that the updates do not get slower over time
+    let toggles = new_toggles(&runtime, Arc::new(NoPersistence));
+    let mut max_items = 0;
+    c.bench_function(&format!("{}/update", MODE), |b| {
+        b.iter(|| {
+            max_items += 1;
+            let update = toggles.update("max_items", json!(max_items), "bench".to_owned());
+            runtime.block_on(update).unwrap();
+        })
+    });
+
+    let toggles = new_toggles(&runtime, Arc::new(InMemoryPersistence::new()));
+    let blocked_actions = json!(["delete", "rename", "share"]);
+    let update = toggles.update("blocked_actions", blocked_actions, "bench".to_owned());
+    runtime.block_on(update).unwrap();
+    c.bench_function(&format!("{}/reload", MODE), |b| {
+        b.iter(|| runtime.block_on(toggles.reload()).unwrap())
+    });
+}
+
+criterion_group!(benches, reads, updates);
+criterion_main!(benches);
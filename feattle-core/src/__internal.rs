@@ -2,15 +2,23 @@
 
 pub use crate::json_reading::FromJsonError;
 pub use crate::persist::{CurrentValue, Persist};
-pub use crate::{FeattleDefinition, Feattles, FeattlesPrivate};
+pub use crate::{
+    EvaluationContext, FeattleDefinition, FeattleValue, Feattles, FeattlesPrivate, ValueSource,
+};
+pub use chrono::{DateTime, Utc};
 pub use parking_lot::{MappedRwLockReadGuard, RwLockReadGuard, RwLockWriteGuard};
+pub use paste::paste;
+pub use serde_json::Value;
 
 use crate::last_reload::LastReload;
 use crate::persist::CurrentValues;
-use crate::FeattleValue;
+use crate::{ExpiryStatus, LayeredSource, ReloadPolicy, UpdateHook};
+use chrono::NaiveDate;
 use parking_lot::RwLock;
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::{Debug, Formatter};
+use std::ops::{Deref, DerefMut};
 pub use std::sync::Arc;
 use std::{fmt, mem};
 
@@ -19,6 +27,30 @@ use std::{fmt, mem};
 pub struct FeattlesImpl<FS> {
     pub persistence: Arc<dyn Persist>,
     pub inner_feattles: RwLock<InnerFeattles<FS>>,
+    /// A copy of the feattles struct, published at every write, that the getters read without
+    /// locking
+    #[cfg(feature = "arc-swap")]
+    pub snapshot: arc_swap::ArcSwap<FS>,
+}
+
+/// Give write access to the content of a `Feattles` instance. With the cargo feature `arc-swap`,
+/// the feattles struct is copied to [`FeattlesImpl::snapshot`] when this is dropped, if it was
+/// mutably accessed.
+pub struct WriteGuard<'a, FS: Clone> {
+    inner: RwLockWriteGuard<'a, InnerFeattles<FS>>,
+    #[cfg(feature = "arc-swap")]
+    snapshot: &'a arc_swap::ArcSwap<FS>,
+    /// Whether the content was mutably accessed, so that the snapshot must be updated
+    #[cfg(feature = "arc-swap")]
+    dirty: bool,
+}
+
+/// A reference to a value of [`FeattlesImpl::snapshot`], returned by the getters with the cargo
+/// feature `arc-swap`
+#[cfg(feature = "arc-swap")]
+pub struct SnapshotGuard<S, T: ?Sized> {
+    guard: arc_swap::Guard<Arc<S>>,
+    project: fn(&S) -> &T,
 }
 
 /// The main content of a `Feattles` instance, protected behind a lock
@@ -27,6 +59,19 @@ pub struct InnerFeattles<FS> {
     pub last_reload: LastReload,
     pub current_values: Option<CurrentValues>,
     pub feattles_struct: FS,
+    pub accept_unknown_keys: bool,
+    pub monotonic_history: bool,
+    pub staging_mode: bool,
+    pub reload_policy: ReloadPolicy,
+    pub update_hooks: Vec<Arc<dyn UpdateHook>>,
+    pub layered_source: Option<Arc<LayeredSource>>,
+    /// How many updates are in progress for each key
+    pub updates_in_flight: BTreeMap<String, usize>,
+    pub require_approval: bool,
+    /// Serialize the changes to the pending changes made by this instance
+    pub pending_lock: Arc<futures_util::lock::Mutex<()>>,
+    #[cfg(feature = "audit")]
+    pub audit_key: Option<crate::audit::AuditKey>,
 }
 
 /// The generic representation of each feattle inside the feattles struct
@@ -37,6 +82,12 @@ pub struct Feattle<T> {
     value: T,
     default: T,
     current_value: Option<CurrentValue>,
+    /// The value of the highest layer of the [`LayeredSource`], used instead of the default
+    layer: Option<(T, ValueSource)>,
+    expires: Option<NaiveDate>,
+    derived: bool,
+    tags: &'static [&'static str],
+    examples: Vec<&'static str>,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -51,26 +102,117 @@ impl fmt::Display for ParseError {
 impl Error for ParseError {}
 
 /// The auto-generated internal struct will implement this trait
-pub trait FeattlesStruct: 'static {
-    /// Try to update the given key, returning the previous value, if any.
+pub trait FeattlesStruct: Clone + 'static {
+    /// Try to update the given key, returning the previous value, if any. In staging mode, the
+    /// staging value is used, if any.
     fn try_update(
         &mut self,
         key: &str,
         value: Option<CurrentValue>,
+        staging_mode: bool,
     ) -> Result<Option<CurrentValue>, FromJsonError>;
+
+    /// Whether the given key is a derived feattle, that can't be updated
+    fn is_derived(&self, key: &str) -> bool;
+
+    /// Check that the value can be parsed for the given key, without updating it, returning it in
+    /// its canonical JSON representation
+    fn validate(&self, key: &str, value: &Value) -> Result<Value, FromJsonError>;
+
+    /// Set the value of the [`LayeredSource`] for the given key, used when there is no persisted
+    /// value
+    fn set_layer(
+        &mut self,
+        key: &str,
+        layer: Option<(Value, ValueSource)>,
+    ) -> Result<(), FromJsonError>;
 }
 
-impl<FS> FeattlesImpl<FS> {
+impl<FS: Clone> FeattlesImpl<FS> {
     pub fn new(persistence: Arc<dyn Persist>, feattles_struct: FS) -> Self {
         FeattlesImpl {
             persistence,
+            #[cfg(feature = "arc-swap")]
+            snapshot: arc_swap::ArcSwap::from_pointee(feattles_struct.clone()),
             inner_feattles: RwLock::new(InnerFeattles {
                 last_reload: LastReload::Never,
                 current_values: None,
                 feattles_struct,
+                accept_unknown_keys: false,
+                monotonic_history: false,
+                staging_mode: false,
+                reload_policy: ReloadPolicy::default(),
+                update_hooks: Vec::new(),
+                layered_source: None,
+                updates_in_flight: BTreeMap::new(),
+                require_approval: false,
+                pending_lock: Arc::new(futures_util::lock::Mutex::new(())),
+                #[cfg(feature = "audit")]
+                audit_key: None,
             }),
         }
     }
+
+    pub fn write(&self) -> WriteGuard<'_, FS> {
+        WriteGuard {
+            inner: self.inner_feattles.write(),
+            #[cfg(feature = "arc-swap")]
+            snapshot: &self.snapshot,
+            #[cfg(feature = "arc-swap")]
+            dirty: false,
+        }
+    }
+}
+
+impl<FS: Clone> Deref for WriteGuard<'_, FS> {
+    type Target = InnerFeattles<FS>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<FS: Clone> DerefMut for WriteGuard<'_, FS> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        #[cfg(feature = "arc-swap")]
+        {
+            self.dirty = true;
+        }
+        &mut self.inner
+    }
+}
+
+#[cfg(feature = "arc-swap")]
+impl<FS: Clone> Drop for WriteGuard<'_, FS> {
+    fn drop(&mut self) {
+        if self.dirty {
+            self.snapshot
+                .store(Arc::new(self.inner.feattles_struct.clone()));
+        }
+    }
+}
+
+#[cfg(feature = "arc-swap")]
+impl<S, T: ?Sized> SnapshotGuard<S, T> {
+    pub fn new(guard: arc_swap::Guard<Arc<S>>, project: fn(&S) -> &T) -> Self {
+        SnapshotGuard { guard, project }
+    }
+}
+
+#[cfg(feature = "arc-swap")]
+impl<S, T: ?Sized> Deref for SnapshotGuard<S, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        (self.project)(&self.guard)
+    }
+}
+
+#[cfg(feature = "arc-swap")]
+impl<S, T: ?Sized + Debug> Debug for SnapshotGuard<S, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
 }
 
 impl<T: Clone + FeattleValue> Feattle<T> {
@@ -81,40 +223,385 @@ impl<T: Clone + FeattleValue> Feattle<T> {
             value: default.clone(),
             default,
             current_value: None,
+            layer: None,
+            expires: None,
+            derived: false,
+            tags: &[],
+            examples: Vec::new(),
+        }
+    }
+
+    /// Set the date after which this feattle is considered overdue for removal. The date must be
+    /// in the format `YYYY-MM-DD`.
+    pub fn expires(mut self, date: &str) -> Self {
+        let date = date.parse().unwrap_or_else(|_| {
+            panic!(
+                "invalid expiry date {:?} for feattle {}, expected YYYY-MM-DD",
+                date, self.key
+            )
+        });
+        self.expires = Some(date);
+        self
+    }
+
+    /// Mark this feattle as derived, declared with `#[derived = path]`. Its value is computed from
+    /// the other feattles and can't be updated.
+    pub fn derived(mut self) -> Self {
+        self.derived = true;
+        self
+    }
+
+    /// Set the tags of this feattle, declared with `#[tags("a", "b")]`
+    pub fn tags(mut self, tags: &'static [&'static str]) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// Add an example value, in JSON, declared with `#[example = "..."]`. The example must be
+    /// a valid value for this feattle.
+    pub fn example(mut self, example: &'static str) -> Self {
+        let parsed = serde_json::from_str(example)
+            .map_err(|error| error.to_string())
+            .and_then(|value| T::try_from_json(&value).map_err(|error| error.to_string()));
+        if let Err(error) = parsed {
+            panic!(
+                "invalid example {:?} for feattle {}: {}",
+                example, self.key, error
+            );
         }
+        self.examples.push(example);
+        self
+    }
+
+    pub fn is_derived(&self) -> bool {
+        self.derived
     }
 
     pub fn definition(&self) -> FeattleDefinition {
+        self.definition_with(&self.value)
+    }
+
+    /// Describe this feattle, using the given value as its current value. This is used by derived
+    /// feattles, whose value is computed outside of this struct.
+    pub fn definition_with(&self, value: &T) -> FeattleDefinition {
         FeattleDefinition {
             key: self.key,
             description: self.description.to_owned(),
             format: T::serialized_format(),
-            value: self.value.as_json(),
-            value_overview: self.value.overview(),
+            value: value.as_json(),
+            value_overview: value.overview(),
             default: self.default.as_json(),
             modified_at: self.current_value.as_ref().map(|v| v.modified_at),
             modified_by: self.current_value.as_ref().map(|v| v.modified_by.clone()),
+            expires: self.expires,
+            expiry_status: self
+                .expires
+                .and_then(|expires| ExpiryStatus::new(expires, Utc::now().date_naive())),
+            read_only: self.derived,
+            tags: self.tags.iter().map(|&tag| tag.to_owned()).collect(),
+            examples: self
+                .examples
+                .iter()
+                .map(|&example| example.to_owned())
+                .collect(),
+            staging: self
+                .current_value
+                .as_ref()
+                .and_then(|value| value.staging.clone()),
+        }
+    }
+
+    /// Return the current value, where it comes from and when it was last modified. The given
+    /// value is used by derived feattles, whose value is computed outside of this struct.
+    pub fn value_and_source(
+        &self,
+        derived: Option<&T>,
+        staging_mode: bool,
+    ) -> (Value, ValueSource, Option<DateTime<Utc>>) {
+        if let Some(value) = derived {
+            return (value.as_json(), ValueSource::Derived, None);
         }
+        let source = match &self.current_value {
+            None => self
+                .layer
+                .as_ref()
+                .map_or(ValueSource::Default, |(_, source)| *source),
+            Some(current_value) if staging_mode && current_value.staging.is_some() => {
+                ValueSource::Staging
+            }
+            Some(_) => ValueSource::Persisted,
+        };
+        let modified_at = self.current_value.as_ref().map(|v| v.modified_at);
+        (self.value.as_json(), source, modified_at)
     }
 
-    /// Try to update this value, returning the previous value, if any.
+    /// Try to update this value, returning the previous value, if any. In staging mode, the
+    /// staging value is used, if any.
     pub fn try_update(
         &mut self,
         value: Option<CurrentValue>,
+        staging_mode: bool,
     ) -> Result<Option<CurrentValue>, FromJsonError> {
         // Note: we must call `try_from_json` to fail **before** updating anything
         self.value = match &value {
-            None => self.default.clone(),
-            Some(value) => FeattleValue::try_from_json(&value.value)?,
+            None => self.base_value().clone(),
+            Some(value) => match &value.staging {
+                Some(staging) if staging_mode => FeattleValue::try_from_json(staging)?,
+                _ => FeattleValue::try_from_json(&value.value)?,
+            },
         };
         Ok(mem::replace(&mut self.current_value, value))
     }
 
+    /// Set the value of the [`LayeredSource`], that is used when there is no persisted value. A
+    /// value that fails to parse removes the layer, so the default is used instead.
+    pub fn set_layer(&mut self, layer: Option<(Value, ValueSource)>) -> Result<(), FromJsonError> {
+        let (layer, result) = match layer {
+            None => (None, Ok(())),
+            Some((value, source)) => match T::try_from_json(&value) {
+                Ok(value) => (Some((value, source)), Ok(())),
+                Err(error) => (None, Err(error)),
+            },
+        };
+        self.layer = layer;
+        if self.current_value.is_none() {
+            self.value = self.base_value().clone();
+        }
+        result
+    }
+
+    /// The value used when there is no persisted value
+    fn base_value(&self) -> &T {
+        self.layer
+            .as_ref()
+            .map_or(&self.default, |(value, _)| value)
+    }
+
+    /// Check that the value can be parsed, without updating this feattle, returning it in its
+    /// canonical JSON representation
+    pub fn validate(&self, value: &Value) -> Result<Value, FromJsonError> {
+        T::try_from_json(value).map(|value| value.as_json())
+    }
+
+    pub fn evaluate(&self, context: &EvaluationContext) -> Value {
+        self.value.evaluate(context)
+    }
+
     pub fn value(&self) -> &T {
         &self.value
     }
 }
 
+/// With the cargo feature `unique-keys`, fail the compilation if two keys are the same once
+/// normalized like in [`crate::env_var_name()`], that is, in upper case and with every character
+/// that is not an ASCII letter, digit or `_` replaced by `_`. The keys are also compared once
+/// converted from camel case to snake case, so that `fooBar` and `foo_bar` collide too. For
+/// example, `database_timeout` and the key `database.timeout` of a group would be read from the
+/// same environment variable. This is called by [`feattles!`] in a constant, so that the panic is
+/// reported as a compilation error.
+///
+#[cfg_attr(feature = "unique-keys", doc = "```compile_fail,E0080")]
+#[cfg_attr(not(feature = "unique-keys"), doc = "```")]
+/// use feattle_core::feattles;
+///
+/// feattles! {
+///     struct Config {
+///         database_timeout: u64,
+///         database: Database {
+///             timeout: u64,
+///         },
+///     }
+/// }
+/// ```
+pub const fn check_unique_keys(keys: &[&str]) {
+    #[cfg(feature = "unique-keys")]
+    {
+        let mut i = 0;
+        while i < keys.len() {
+            let mut j = i + 1;
+            while j < keys.len() {
+                let (a, b) = (keys[i].as_bytes(), keys[j].as_bytes());
+                if normalized_eq(a, b, false) || normalized_eq(a, b, true) {
+                    let message = Message::new()
+                        .push(b"the feattle keys `")
+                        .push(a)
+                        .push(b"` and `")
+                        .push(b)
+                        .push(b"` collide once normalized to an environment variable name");
+                    panic!(
+                        "{}",
+                        message.as_str(
+                            "the feattle keys collide once normalized to an environment \
+                             variable name"
+                        )
+                    );
+                }
+                j += 1;
+            }
+            i += 1;
+        }
+    }
+    #[cfg(not(feature = "unique-keys"))]
+    let _ = keys;
+}
+
+/// Compare two keys once normalized like in [`crate::env_var_name()`], without allocating. With
+/// `split_camel_case`, a `_` is also inserted before each upper case letter that follows a lower
+/// case letter or a digit.
+#[cfg(feature = "unique-keys")]
+const fn normalized_eq(a: &[u8], b: &[u8], split_camel_case: bool) -> bool {
+    let mut a_cursor = (0, false);
+    let mut b_cursor = (0, false);
+    loop {
+        match (
+            next_normalized(a, a_cursor, split_camel_case),
+            next_normalized(b, b_cursor, split_camel_case),
+        ) {
+            (None, None) => return true,
+            (Some((a_byte, a_next)), Some((b_byte, b_next))) => {
+                if a_byte != b_byte {
+                    return false;
+                }
+                a_cursor = a_next;
+                b_cursor = b_next;
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// Return the normalized character of the key at the given cursor, with the cursor of the next
+/// one. The cursor is the position in the key and whether the `_` that splits a camel case word
+/// at this position was already returned. Every character that is not an ASCII letter, digit or
+/// `_`, including the multi-byte ones, becomes a single `_`.
+#[cfg(feature = "unique-keys")]
+const fn next_normalized(
+    key: &[u8],
+    (position, split): (usize, bool),
+    split_camel_case: bool,
+) -> Option<(u8, (usize, bool))> {
+    if position >= key.len() {
+        return None;
+    }
+    let byte = key[position];
+    if split_camel_case
+        && !split
+        && byte.is_ascii_uppercase()
+        && position > 0
+        && (key[position - 1].is_ascii_lowercase() || key[position - 1].is_ascii_digit())
+    {
+        return Some((b'_', (position, true)));
+    }
+    if byte.is_ascii_alphanumeric() || byte == b'_' {
+        return Some((byte.to_ascii_uppercase(), (position + 1, false)));
+    }
+    let mut next = position + 1;
+    while next < key.len() && key[next] & 0xC0 == 0x80 {
+        next += 1;
+    }
+    Some((b'_', (next, false)))
+}
+
+/// Fail the compilation if the date given to `#[expires = "..."]` is not a valid date in the
+/// format `YYYY-MM-DD`. This is called by [`feattles!`] in a constant, like
+/// [`check_unique_keys()`], so that a typo is not only found when the struct is created.
+///
+/// ```compile_fail,E0080
+/// use feattle_core::feattles;
+///
+/// feattles! {
+///     struct Config {
+///         #[expires = "2024-13-01"]
+///         new_checkout: bool,
+///     }
+/// }
+/// ```
+pub const fn check_expiry_date(date: &str) {
+    if !is_valid_date(date.as_bytes()) {
+        let message = Message::new()
+            .push(b"invalid expiry date `")
+            .push(date.as_bytes())
+            .push(b"`, expected a valid date in the format YYYY-MM-DD");
+        panic!(
+            "{}",
+            message.as_str("invalid expiry date, expected YYYY-MM-DD")
+        );
+    }
+}
+
+/// Check that the date is in the format `YYYY-MM-DD`, with a month between 1 and 12 and a day that
+/// exists in this month
+const fn is_valid_date(date: &[u8]) -> bool {
+    if date.len() != 10 || date[4] != b'-' || date[7] != b'-' {
+        return false;
+    }
+    let (year, month, day) = match (
+        parse_digits(date, 0, 4),
+        parse_digits(date, 5, 7),
+        parse_digits(date, 8, 10),
+    ) {
+        (Some(year), Some(month), Some(day)) => (year, month, day),
+        _ => return false,
+    };
+    let is_leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year => 29,
+        2 => 28,
+        _ => return false,
+    };
+    day >= 1 && day <= days_in_month
+}
+
+/// Parse the ASCII digits in `bytes[start..end]`
+const fn parse_digits(bytes: &[u8], start: usize, end: usize) -> Option<u32> {
+    let mut value = 0;
+    let mut i = start;
+    while i < end {
+        if !bytes[i].is_ascii_digit() {
+            return None;
+        }
+        value = value * 10 + (bytes[i] - b'0') as u32;
+        i += 1;
+    }
+    Some(value)
+}
+
+/// A fixed-size buffer to build a panic message in a constant
+struct Message {
+    bytes: [u8; 256],
+    len: usize,
+}
+
+impl Message {
+    const fn new() -> Self {
+        Message {
+            bytes: [0; 256],
+            len: 0,
+        }
+    }
+
+    /// Append the bytes, truncating them if the buffer is full
+    const fn push(mut self, bytes: &[u8]) -> Self {
+        let mut i = 0;
+        while i < bytes.len() && self.len < self.bytes.len() {
+            self.bytes[self.len] = bytes[i];
+            self.len += 1;
+            i += 1;
+        }
+        self
+    }
+
+    /// Return the message, or the fallback if the truncation split a multi-byte character
+    const fn as_str<'a>(&'a self, fallback: &'a str) -> &'a str {
+        match std::str::from_utf8(self.bytes.split_at(self.len).0) {
+            Ok(message) => message,
+            Err(_) => fallback,
+        }
+    }
+}
+
 impl<FS: Debug> Debug for FeattlesImpl<FS> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.debug_struct("FeattlesImpl")
@@ -123,3 +610,94 @@ impl<FS: Debug> Debug for FeattlesImpl<FS> {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "unique-keys")]
+    fn normalized_keys() {
+        assert!(normalized_eq(
+            b"database_timeout",
+            b"database.timeout",
+            false
+        ));
+        assert!(normalized_eq(b"fooBar", b"foobar", false));
+        assert!(normalized_eq("caf\u{e9}".as_bytes(), b"caf_", false));
+        assert!(!normalized_eq(b"fooBar", b"foo_bar", false));
+        assert!(!normalized_eq(b"foo", b"foo_bar", false));
+        assert!(!normalized_eq(b"a.foo_bar", b"b.foo_bar", false));
+
+        assert!(normalized_eq(b"fooBar", b"foo_bar", true));
+        assert!(normalized_eq(b"group.fooBar2", b"group.foo_bar2", true));
+        assert!(normalized_eq(b"v2Api", b"v2_api", true));
+        assert!(normalized_eq(b"HTTPServer", b"httpserver", true));
+        assert!(!normalized_eq(b"fooBar", b"foobar", true));
+
+        for key in ["foo_bar", "group.fooBar", "caf\u{e9}.x-y"] {
+            let env_var = crate::env_var_name("", key);
+            assert!(normalized_eq(key.as_bytes(), env_var.as_bytes(), false));
+        }
+
+        check_unique_keys(&[]);
+        check_unique_keys(&["foo", "foo_bar", "foo_baz", "group.foo"]);
+    }
+
+    #[test]
+    #[cfg(feature = "unique-keys")]
+    #[should_panic(
+        expected = "the feattle keys `database_timeout` and `database.timeout` collide once \
+        normalized to an environment variable name"
+    )]
+    fn colliding_keys() {
+        check_unique_keys(&["a", "database_timeout", "b", "database.timeout"]);
+    }
+
+    #[test]
+    #[cfg(feature = "unique-keys")]
+    #[should_panic(
+        expected = "the feattle keys `fooBar` and `foo_bar` collide once normalized to an \
+        environment variable name"
+    )]
+    fn colliding_camel_case_keys() {
+        check_unique_keys(&["fooBar", "foo", "foo_bar"]);
+    }
+
+    #[test]
+    fn expiry_dates() {
+        for date in [
+            "2024-12-31",
+            "2024-02-29",
+            "2000-02-29",
+            "0001-01-01",
+            "9999-12-31",
+        ] {
+            assert!(is_valid_date(date.as_bytes()), "{}", date);
+        }
+        for date in [
+            "2024-13-01",
+            "2024-00-10",
+            "2024-04-31",
+            "2023-02-29",
+            "1900-02-29",
+            "2024-01-00",
+            "2024-1-01",
+            "2024/01/01",
+            "2024-01-01T",
+            "2024-0a-01",
+            "",
+        ] {
+            assert!(!is_valid_date(date.as_bytes()), "{}", date);
+        }
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "invalid expiry date `2024-13-01`, expected a valid date in the format \
+        YYYY-MM-DD"
+    )]
+    fn invalid_expiry_date() {
+        check_expiry_date("2024-13-01");
+    }
+}
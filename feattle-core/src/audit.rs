@@ -0,0 +1,168 @@
+//! Tamper-evident signatures for the history of the feattles
+//!
+//! When an [`AuditKey`] is configured with [`crate::Feattles::set_audit_key()`], each new
+//! [`HistoryEntry`] is signed with an HMAC-SHA256 over its value, modification date, author,
+//! approver and touch and unset markers, together with a hash of the entry before it. The entries
+//! of a [`ValueHistory`] thus form a hash chain: editing, removing or reordering entries directly
+//! in the storage will be detected by [`ValueHistory::verify_chain()`].
+//!
+//! Entries created before the key was configured are not signed, and are reported as
+//! [`SignatureStatus::Unsigned`] instead of invalid. However, an unsigned entry that comes after a
+//! signed one is considered invalid, so that signatures can not simply be stripped. For the same
+//! reason, all instances that update the feattles should use the same key.
+//!
+//! This module is only available with the cargo feature `audit`.
+
+use crate::persist::{HistoryEntry, ValueHistory};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fmt::Write;
+
+/// The secret key used to sign and verify the history entries
+#[derive(Clone)]
+pub struct AuditKey(Vec<u8>);
+
+/// The result of verifying the signature of a single history entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureStatus {
+    /// The signature matches the entry and the previous one
+    Valid,
+    /// The entry was created before signatures were enabled
+    Unsigned,
+    /// The entry, or the one before it, was tampered with
+    Invalid,
+}
+
+impl AuditKey {
+    /// Create a key from the given secret bytes, that should be long and random, like 32 bytes
+    /// read from a secret store
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        AuditKey(key.into())
+    }
+
+    /// Sign the last entry of the history, chaining it to the one before it
+    pub(crate) fn sign_last(&self, history: &mut ValueHistory) {
+        if let Some((last, others)) = history.entries.split_last_mut() {
+            let signature = self.mac(last, others.last()).finalize().into_bytes();
+            last.signature = Some(to_hex(&signature));
+        }
+    }
+
+    fn mac(&self, entry: &HistoryEntry, previous: Option<&HistoryEntry>) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC can take key of any size");
+        let previous_hash = previous.map(entry_hash).unwrap_or_default();
+        for field in [
+            entry.value.to_string().as_bytes(),
+            entry.modified_at.to_rfc3339().as_bytes(),
+            entry.modified_by.as_bytes(),
+            previous_hash.as_bytes(),
+        ] {
+            mac.update(&(field.len() as u64).to_be_bytes());
+            mac.update(field);
+        }
+        // Only covered when present, so that the signatures of the other entries are unchanged
+        if let Some(approved_by) = &entry.approved_by {
+            mac.update(&(approved_by.len() as u64).to_be_bytes());
+            mac.update(approved_by.as_bytes());
+        }
+        if entry.touch {
+            mac.update(b"touch");
+        }
+        if entry.unset {
+            mac.update(b"unset");
+        }
+        mac
+    }
+
+    fn verify(&self, entry: &HistoryEntry, previous: Option<&HistoryEntry>) -> bool {
+        match entry.signature.as_deref().and_then(from_hex) {
+            None => false,
+            Some(signature) => self.mac(entry, previous).verify_slice(&signature).is_ok(),
+        }
+    }
+}
+
+impl fmt::Debug for AuditKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AuditKey(..)")
+    }
+}
+
+impl ValueHistory {
+    /// Check that no entry was tampered with, as described in the [module documentation](self).
+    /// Unsigned entries from before the signatures were enabled are accepted.
+    pub fn verify_chain(&self, key: &AuditKey) -> bool {
+        self.signature_statuses(key)
+            .into_iter()
+            .all(|status| status != SignatureStatus::Invalid)
+    }
+
+    /// Return the result of verifying the signature of each entry, in the same order as
+    /// [`ValueHistory::entries`].
+    pub fn signature_statuses(&self, key: &AuditKey) -> Vec<SignatureStatus> {
+        let mut seen_signed = false;
+        let mut previous = None;
+        let mut statuses = Vec::with_capacity(self.entries.len());
+        for entry in &self.entries {
+            let status = if entry.signature.is_none() && !seen_signed {
+                SignatureStatus::Unsigned
+            } else if key.verify(entry, previous) {
+                SignatureStatus::Valid
+            } else {
+                SignatureStatus::Invalid
+            };
+            seen_signed |= entry.signature.is_some();
+            statuses.push(status);
+            previous = Some(entry);
+        }
+        statuses
+    }
+}
+
+fn entry_hash(entry: &HistoryEntry) -> String {
+    let mut hasher = Sha256::new();
+    for field in [
+        entry.value.to_string().as_bytes(),
+        entry.modified_at.to_rfc3339().as_bytes(),
+        entry.modified_by.as_bytes(),
+        entry.signature.as_deref().unwrap_or_default().as_bytes(),
+    ] {
+        hasher.update((field.len() as u64).to_be_bytes());
+        hasher.update(field);
+    }
+    if let Some(approved_by) = &entry.approved_by {
+        hasher.update((approved_by.len() as u64).to_be_bytes());
+        hasher.update(approved_by.as_bytes());
+    }
+    if entry.touch {
+        hasher.update(b"touch");
+    }
+    if entry.unset {
+        hasher.update(b"unset");
+    }
+    to_hex(&hasher.finalize())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(2 * bytes.len());
+    for byte in bytes {
+        write!(hex, "{:02x}", byte).unwrap();
+    }
+    hex
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match *pair {
+            [high, low] => Some((hex_digit(high)? << 4) | hex_digit(low)?),
+            _ => None,
+        })
+        .collect()
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
@@ -0,0 +1,83 @@
+use crate::EvaluationContext;
+
+#[cfg(feature = "tokio")]
+tokio::task_local! {
+    static CURRENT: FeattleContext;
+}
+
+/// The request-level data used to evaluate feattles like [`crate::Rollout`], with
+/// [`crate::Feattles::is_enabled_ctx()`].
+///
+/// Unlike [`EvaluationContext`], it does not capture the moment of the evaluation, so it can be
+/// created once by the web layer (for example, by a middleware that authenticates the user) and
+/// then carried to the application handlers. It can be carried explicitly, stored in the request
+/// extensions (like `http::Request::extensions_mut().insert(context)`) or, with the feature
+/// **tokio**, in a task-local with [`FeattleContext::scope()`].
+///
+/// # Example
+/// ```
+/// use std::sync::Arc;
+/// use feattle_core::{feattles, FeattleContext, Feattles, Rollout};
+/// use feattle_core::persist::NoPersistence;
+///
+/// feattles! {
+///     struct MyFeattles {
+///         new_checkout: Rollout,
+///     }
+/// }
+///
+/// let my_feattles = MyFeattles::new(Arc::new(NoPersistence));
+/// let context = FeattleContext::new(Some("user-17".to_owned()));
+/// assert!(!my_feattles.is_enabled_ctx("new_checkout", &context));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeattleContext {
+    /// An identifier of the user, like its id
+    pub user_key: Option<String>,
+}
+
+impl FeattleContext {
+    /// Create a new context for the given user (if any)
+    pub fn new(user_key: Option<String>) -> Self {
+        FeattleContext { user_key }
+    }
+
+    /// Create the context used to evaluate a feattle at the current time
+    pub fn evaluation_context(&self) -> EvaluationContext {
+        EvaluationContext::new(self.user_key.clone())
+    }
+
+    /// Run the future with this context set as the current one, that can be read with
+    /// [`FeattleContext::current()`] from anywhere inside it.
+    ///
+    /// The context is stored in a tokio task-local, so it's only visible inside the given future:
+    /// it is not inherited by tasks created with `tokio::spawn()`, that must be scoped again if
+    /// needed. Nested scopes shadow the outer one until they finish.
+    ///
+    /// # Example
+    /// ```
+    /// use feattle_core::FeattleContext;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let context = FeattleContext::new(Some("user-17".to_owned()));
+    /// context
+    ///     .scope(async {
+    ///         let current = FeattleContext::current().unwrap();
+    ///         assert_eq!(current.user_key.as_deref(), Some("user-17"));
+    ///     })
+    ///     .await;
+    /// assert_eq!(FeattleContext::current(), None);
+    /// # }
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub async fn scope<F: std::future::Future>(self, future: F) -> F::Output {
+        CURRENT.scope(self, future).await
+    }
+
+    /// Return the context set by the innermost [`FeattleContext::scope()`], if any
+    #[cfg(feature = "tokio")]
+    pub fn current() -> Option<FeattleContext> {
+        CURRENT.try_with(|context| context.clone()).ok()
+    }
+}
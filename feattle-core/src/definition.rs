@@ -1,6 +1,6 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
 use serde::Serialize;
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::fmt;
 
 /// A precise description of a feattle type
@@ -29,9 +29,23 @@ pub enum SerializedFormatKind {
     Set(Box<SerializedFormatKind>),
     /// An unordered bag of homogenous keys and values
     Map(StringFormatKind, Box<SerializedFormatKind>),
+    /// An unordered bag of homogenous keys and values, represented as a list of `[key, value]`
+    /// pairs. This is used when the keys can't be represented as strings.
+    Entries(Box<SerializedFormatKind>, Box<SerializedFormatKind>),
+    /// A fixed-length list of possibly heterogeneous types
+    Tuple(Vec<SerializedFormatKind>),
+    /// An object with a fixed set of named fields
+    Object(Vec<ObjectField>),
     Optional(Box<SerializedFormatKind>),
 }
 
+/// A named field of [`SerializedFormatKind::Object`]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct ObjectField {
+    pub name: &'static str,
+    pub kind: SerializedFormatKind,
+}
+
 /// A precise description of a feattle string-type
 #[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 pub struct StringFormat {
@@ -54,7 +68,7 @@ pub enum StringFormatKind {
     /// implied at the start of the pattern and a `)$` at the end.
     Pattern(&'static str),
     /// Only one of the listed values is accepted.
-    Choices(&'static [&'static str]),
+    Choices(Vec<String>),
 }
 
 /// A data struct, describing a single feattle.
@@ -76,6 +90,136 @@ pub struct FeattleDefinition {
     pub modified_at: Option<DateTime<Utc>>,
     /// The user that last modified it
     pub modified_by: Option<String>,
+    /// The date after which this feattle is considered overdue for removal, as declared with
+    /// `#[expires = "YYYY-MM-DD"]`
+    pub expires: Option<NaiveDate>,
+    /// Whether this feattle is expired or about to be, based on `expires`
+    pub expiry_status: Option<ExpiryStatus>,
+    /// Whether this feattle is derived from the others, as declared with `#[derived = path]`, and
+    /// so can't be updated
+    pub read_only: bool,
+    /// The tags used to categorize this feattle, as declared with `#[tags("a", "b")]`
+    pub tags: Vec<String>,
+    /// Example values, in JSON, as declared with `#[example = "..."]`
+    pub examples: Vec<String>,
+    /// The value read by the instances in staging mode, if any, as set by
+    /// [`crate::Feattles::update_staging()`]
+    pub staging: Option<Value>,
+}
+
+/// Where the current value of a feattle comes from, as returned by
+/// [`crate::Feattles::value_and_source()`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+pub enum ValueSource {
+    /// The feattle was never modified, so its default value is used
+    Default,
+    /// The feattle was never modified and the bundled file of the [`crate::LayeredSource`] has a
+    /// value for it
+    File,
+    /// The feattle was never modified and the environment variable of the
+    /// [`crate::LayeredSource`] has a value for it
+    Env,
+    /// The persisted value is used
+    Persisted,
+    /// The staging value is used, since this instance is in staging mode
+    Staging,
+    /// The value is computed from the other feattles, as declared with `#[derived = path]`
+    Derived,
+}
+
+/// Indicate that a feattle declared with `#[expires = "YYYY-MM-DD"]` should be removed soon.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+pub enum ExpiryStatus {
+    /// The expiry date is in the next [`ExpiryStatus::SOON_DAYS`] days
+    ExpiresSoon,
+    /// The expiry date has passed
+    Expired,
+}
+
+impl SerializedFormatKind {
+    /// Describe this format as a [JSON Schema](https://json-schema.org/), so that external tools
+    /// can validate the values of a feattle, for example.
+    pub fn json_schema(&self) -> Value {
+        match self {
+            SerializedFormatKind::Bool => json!({ "type": "boolean" }),
+            SerializedFormatKind::Integer => json!({ "type": "integer" }),
+            SerializedFormatKind::Float => json!({ "type": "number" }),
+            SerializedFormatKind::String(kind) => kind.json_schema(),
+            SerializedFormatKind::List(items) => {
+                json!({ "type": "array", "items": items.json_schema() })
+            }
+            SerializedFormatKind::Set(items) => {
+                json!({ "type": "array", "items": items.json_schema(), "uniqueItems": true })
+            }
+            SerializedFormatKind::Map(keys, values) => json!({
+                "type": "object",
+                "propertyNames": keys.json_schema(),
+                "additionalProperties": values.json_schema(),
+            }),
+            SerializedFormatKind::Entries(keys, values) => json!({
+                "type": "array",
+                "items": tuple_schema(&[keys.json_schema(), values.json_schema()]),
+            }),
+            SerializedFormatKind::Tuple(items) => {
+                let items: Vec<_> = items.iter().map(|item| item.json_schema()).collect();
+                tuple_schema(&items)
+            }
+            SerializedFormatKind::Object(fields) => {
+                let properties: serde_json::Map<_, _> = fields
+                    .iter()
+                    .map(|field| (field.name.to_owned(), field.kind.json_schema()))
+                    .collect();
+                let required: Vec<_> = fields
+                    .iter()
+                    .filter(|field| !matches!(field.kind, SerializedFormatKind::Optional(_)))
+                    .map(|field| field.name)
+                    .collect();
+                json!({ "type": "object", "properties": properties, "required": required })
+            }
+            SerializedFormatKind::Optional(inner) => {
+                json!({ "anyOf": [inner.json_schema(), { "type": "null" }] })
+            }
+        }
+    }
+}
+
+impl StringFormatKind {
+    /// Describe this format as a [JSON Schema](https://json-schema.org/)
+    pub fn json_schema(&self) -> Value {
+        match self {
+            StringFormatKind::Any => json!({ "type": "string" }),
+            StringFormatKind::Pattern(pattern) => {
+                json!({ "type": "string", "pattern": format!("^(?:{})$", pattern) })
+            }
+            StringFormatKind::Choices(choices) => json!({ "type": "string", "enum": choices }),
+        }
+    }
+}
+
+fn tuple_schema(items: &[Value]) -> Value {
+    json!({
+        "type": "array",
+        "prefixItems": items,
+        "minItems": items.len(),
+        "maxItems": items.len(),
+    })
+}
+
+impl ExpiryStatus {
+    /// How many days before its expiry date a feattle is considered to expire soon
+    pub const SOON_DAYS: i64 = 14;
+
+    /// Determine the status of a feattle with the given expiry date, as seen at `today`. `None` is
+    /// returned when the expiry date is still far away.
+    pub fn new(expires: NaiveDate, today: NaiveDate) -> Option<Self> {
+        if today > expires {
+            Some(ExpiryStatus::Expired)
+        } else if today + Duration::days(Self::SOON_DAYS) > expires {
+            Some(ExpiryStatus::ExpiresSoon)
+        } else {
+            None
+        }
+    }
 }
 
 impl fmt::Display for SerializedFormat {
@@ -4,12 +4,21 @@ use crate::json_reading::{
     FromJsonError,
 };
 use crate::{SerializedFormatKind, StringFormatKind};
+use chrono::{DateTime, Utc};
+#[cfg(feature = "ordered-float")]
+use ordered_float::OrderedFloat;
+#[cfg(feature = "regex")]
+use regex::Regex;
 use serde_json::{Number, Value};
+#[cfg(feature = "smallvec")]
+use smallvec::{Array, SmallVec};
 use std::collections::{BTreeMap, BTreeSet};
 use std::convert::TryInto;
 use std::error::Error;
 use std::fmt::Debug;
 use std::fmt::Write;
+use std::iter::FromIterator;
+use std::ops::{Deref, DerefMut};
 use std::str::FromStr;
 #[cfg(feature = "uuid")]
 use uuid::Uuid;
@@ -35,6 +44,33 @@ pub trait FeattleValue: Debug + Sized {
     /// Return a precise description of a feattle type. This will be consumed, for example, by the
     /// UI code to show an appropriate HTML form in the admin panel.
     fn serialized_format() -> SerializedFormat;
+
+    /// Return the effective value in the given context, in JSON representation. For most types,
+    /// this is the value itself, which is what the default implementation returns. Types like
+    /// [`crate::Rollout`] depend on the user or on the time to decide their effective value.
+    fn evaluate(&self, context: &EvaluationContext) -> Value {
+        let _ = context;
+        self.as_json()
+    }
+}
+
+/// The context in which a feattle is evaluated, with [`FeattleValue::evaluate()`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvaluationContext {
+    /// An identifier of the user, like its id
+    pub user_key: Option<String>,
+    /// The moment of the evaluation
+    pub at: DateTime<Utc>,
+}
+
+impl EvaluationContext {
+    /// Create a new context for the given user (if any) at the current time
+    pub fn new(user_key: Option<String>) -> Self {
+        EvaluationContext {
+            user_key,
+            at: Utc::now(),
+        }
+    }
 }
 
 /// The base trait for string-types that can be used for feattles.
@@ -56,6 +92,15 @@ where
     fn serialized_string_format() -> StringFormat;
 }
 
+/// An `enum` created with [`crate::feattle_enum`], whose variants can be listed.
+pub trait FeattleEnum: FeattleStringValue + Copy + Ord
+where
+    <Self as FromStr>::Err: Error + Send + Sync + 'static,
+{
+    /// The name of each variant, in declaration order
+    const VARIANTS: &'static [&'static str];
+}
+
 impl<T> FeattleValue for T
 where
     T: FeattleStringValue,
@@ -177,6 +222,25 @@ impl FeattleValue for f64 {
     }
 }
 
+#[cfg(feature = "ordered-float")]
+impl FeattleValue for OrderedFloat<f64> {
+    fn as_json(&self) -> Value {
+        self.0.as_json()
+    }
+    fn overview(&self) -> String {
+        self.0.overview()
+    }
+    fn try_from_json(value: &Value) -> Result<Self, FromJsonError> {
+        extract_f64(value).map(OrderedFloat)
+    }
+    fn serialized_format() -> SerializedFormat {
+        SerializedFormat {
+            kind: SerializedFormatKind::Float,
+            tag: "OrderedFloat<f64>".to_owned(),
+        }
+    }
+}
+
 #[cfg(feature = "uuid")]
 impl FeattleStringValue for Uuid {
     fn serialized_string_format() -> StringFormat {
@@ -189,6 +253,20 @@ impl FeattleStringValue for Uuid {
     }
 }
 
+/// The regex is stored as its pattern. Invalid patterns are rejected when parsing.
+///
+/// Note that [`Regex`] does not implement [`Default`], so a default value must be given when
+/// declaring the feattle, like `log_filter: Regex = Regex::new("^ERROR").unwrap()`.
+#[cfg(feature = "regex")]
+impl FeattleStringValue for Regex {
+    fn serialized_string_format() -> StringFormat {
+        StringFormat {
+            kind: StringFormatKind::Any,
+            tag: "Regex".to_owned(),
+        }
+    }
+}
+
 impl FeattleStringValue for String {
     fn serialized_string_format() -> StringFormat {
         StringFormat {
@@ -221,6 +299,33 @@ impl<T: FeattleValue> FeattleValue for Vec<T> {
     }
 }
 
+#[cfg(feature = "smallvec")]
+impl<A: Array> FeattleValue for SmallVec<A>
+where
+    A::Item: FeattleValue,
+{
+    fn as_json(&self) -> Value {
+        Value::Array(self.iter().map(|item| item.as_json()).collect())
+    }
+    fn overview(&self) -> String {
+        format!("[{}]", iter_overview(self.iter()))
+    }
+    fn try_from_json(value: &Value) -> Result<Self, FromJsonError> {
+        let mut list = SmallVec::new();
+        for item in extract_array(value)? {
+            list.push(A::Item::try_from_json(item)?);
+        }
+        Ok(list)
+    }
+    fn serialized_format() -> SerializedFormat {
+        let f = A::Item::serialized_format();
+        SerializedFormat {
+            kind: SerializedFormatKind::List(Box::new(f.kind)),
+            tag: format!("SmallVec<[{}; {}]>", f.tag, A::size()),
+        }
+    }
+}
+
 impl<T: FeattleValue + Ord> FeattleValue for BTreeSet<T> {
     fn as_json(&self) -> Value {
         Value::Array(self.iter().map(|item| item.as_json()).collect())
@@ -256,18 +361,7 @@ where
         )
     }
     fn overview(&self) -> String {
-        // Group by value
-        let mut keys_by_value: BTreeMap<_, Vec<_>> = BTreeMap::new();
-        for (key, value) in self {
-            keys_by_value.entry(value.overview()).or_default().push(key);
-        }
-
-        let overview_by_value: Vec<_> = keys_by_value
-            .into_iter()
-            .map(|(value, keys)| format!("{}: {}", iter_overview(keys.into_iter()), value))
-            .collect();
-
-        format!("{{{}}}", iter_overview(overview_by_value.iter()))
+        map_overview(self.iter())
     }
     fn try_from_json(value: &Value) -> Result<Self, FromJsonError> {
         let mut map = BTreeMap::new();
@@ -289,6 +383,120 @@ where
     }
 }
 
+/// A map whose keys are not restricted to string-like types, like `EntriesMap<(i32, i32), String>`.
+///
+/// Since JSON objects only accept string keys, this map is represented as a JSON array of
+/// `[key, value]` pairs, like the output of JavaScript's `Object.entries()`, for example:
+/// `[[[1, 2], "a"], [[3, 4], "b"]]`.
+///
+/// This is a thin wrapper around [`BTreeMap`], to which it dereferences. A plain `BTreeMap<K, V>`
+/// is still the best choice when `K` implements [`FeattleStringValue`], since its JSON
+/// representation is more natural.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct EntriesMap<K, V>(pub BTreeMap<K, V>);
+
+impl<K: FeattleValue + Ord, V: FeattleValue> FeattleValue for EntriesMap<K, V> {
+    fn as_json(&self) -> Value {
+        Value::Array(
+            self.0
+                .iter()
+                .map(|(item_key, item_value)| {
+                    Value::Array(vec![item_key.as_json(), item_value.as_json()])
+                })
+                .collect(),
+        )
+    }
+    fn overview(&self) -> String {
+        map_overview(self.0.iter())
+    }
+    fn try_from_json(value: &Value) -> Result<Self, FromJsonError> {
+        let mut map = BTreeMap::new();
+        for item in extract_array(value)? {
+            let (item_key, item_value) = <(K, V)>::try_from_json(item)?;
+            map.insert(item_key, item_value);
+        }
+        Ok(EntriesMap(map))
+    }
+    fn serialized_format() -> SerializedFormat {
+        let fk = K::serialized_format();
+        let fv = V::serialized_format();
+        SerializedFormat {
+            kind: SerializedFormatKind::Entries(Box::new(fk.kind), Box::new(fv.kind)),
+            tag: format!("Map<{}, {}>", fk.tag, fv.tag),
+        }
+    }
+}
+
+impl<K, V> Default for EntriesMap<K, V> {
+    fn default() -> Self {
+        EntriesMap(BTreeMap::new())
+    }
+}
+
+impl<K, V> Deref for EntriesMap<K, V> {
+    type Target = BTreeMap<K, V>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<K, V> DerefMut for EntriesMap<K, V> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<K, V> From<BTreeMap<K, V>> for EntriesMap<K, V> {
+    fn from(map: BTreeMap<K, V>) -> Self {
+        EntriesMap(map)
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for EntriesMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        EntriesMap(iter.into_iter().collect())
+    }
+}
+
+macro_rules! impl_tuple {
+    ($len:expr; $($name:ident: $index:tt),+) => {
+        impl<$($name: FeattleValue),+> FeattleValue for ($($name,)+) {
+            fn as_json(&self) -> Value {
+                Value::Array(vec![$(self.$index.as_json()),+])
+            }
+            fn overview(&self) -> String {
+                let items = [$(self.$index.overview()),+];
+                format!("({})", items.join(", "))
+            }
+            fn try_from_json(value: &Value) -> Result<Self, FromJsonError> {
+                let items = extract_array(value)?;
+                if items.len() != $len {
+                    return Err(FromJsonError::WrongKind {
+                        actual: "Array",
+                        expected: concat!("Array of length ", $len),
+                    });
+                }
+                Ok(($($name::try_from_json(&items[$index])?,)+))
+            }
+            fn serialized_format() -> SerializedFormat {
+                let formats = vec![$($name::serialized_format()),+];
+                let tags: Vec<_> = formats.iter().map(|f| f.tag.as_str()).collect();
+                let tag = format!("({})", tags.join(", "));
+                SerializedFormat {
+                    kind: SerializedFormatKind::Tuple(
+                        formats.into_iter().map(|f| f.kind).collect(),
+                    ),
+                    tag,
+                }
+            }
+        }
+    };
+}
+
+impl_tuple! {2; A: 0, B: 1}
+impl_tuple! {3; A: 0, B: 1, C: 2}
+impl_tuple! {4; A: 0, B: 1, C: 2, D: 3}
+
 impl<T: FeattleValue> FeattleValue for Option<T> {
     fn as_json(&self) -> Value {
         match self {
@@ -317,6 +525,23 @@ impl<T: FeattleValue> FeattleValue for Option<T> {
     }
 }
 
+fn map_overview<'a, K: FeattleValue + 'a, V: FeattleValue + 'a>(
+    iter: impl Iterator<Item = (&'a K, &'a V)>,
+) -> String {
+    // Group by value
+    let mut keys_by_value: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for (key, value) in iter {
+        keys_by_value.entry(value.overview()).or_default().push(key);
+    }
+
+    let overview_by_value: Vec<_> = keys_by_value
+        .into_iter()
+        .map(|(value, keys)| format!("{}: {}", iter_overview(keys.into_iter()), value))
+        .collect();
+
+    format!("{{{}}}", iter_overview(overview_by_value.iter()))
+}
+
 fn iter_overview<'a, T: FeattleValue + 'a>(iter: impl Iterator<Item = &'a T>) -> String {
     const MAX_ITEMS: usize = 3;
     let mut overview = String::new();
@@ -444,6 +669,24 @@ mod tests {
         }
     }
 
+    #[test]
+    #[cfg(feature = "regex")]
+    fn regex() {
+        // `Regex` does not implement `PartialEq`, so compare by pattern
+        let parsed = Regex::try_from_json(&json!("^ERROR: .*$")).unwrap();
+        assert_eq!(parsed.as_str(), "^ERROR: .*$");
+        assert_eq!(parsed.as_json(), json!("^ERROR: .*$"));
+        assert_eq!(parsed.overview(), "^ERROR: .*$");
+        assert!(parsed.is_match("ERROR: disk is full"));
+
+        assert!(Regex::try_from_json(&json!("(unclosed")).is_err());
+        assert!(Regex::try_from_json(&json!(17)).is_err());
+        assert_eq!(
+            Regex::serialized_format().kind,
+            SerializedFormatKind::String(StringFormatKind::Any)
+        );
+    }
+
     #[test]
     fn string() {
         converts(json!("17"), "17".to_owned(), "17");
@@ -476,6 +719,53 @@ mod tests {
         )
     }
 
+    #[test]
+    #[cfg(feature = "smallvec")]
+    fn smallvec() {
+        use smallvec::smallvec;
+
+        converts(
+            json!([3, 14, 15]),
+            SmallVec::<[i32; 4]>::from_slice(&[3, 14, 15]),
+            "[3, 14, 15]",
+        );
+        let spilled: SmallVec<[i32; 2]> = smallvec![3, 14, 15, 92];
+        converts(json!([3, 14, 15, 92]), spilled, "[3, 14, 15, ... 1 more]");
+        fails::<SmallVec<[i32; 4]>>(json!([3, 14, "15", 92]));
+        fails::<SmallVec<[i32; 4]>>(json!({}));
+        let format = SmallVec::<[i32; 4]>::serialized_format();
+        assert_eq!(
+            format.kind,
+            SerializedFormatKind::List(Box::new(SerializedFormatKind::Integer))
+        );
+        assert_eq!(format.tag, "SmallVec<[i32; 4]>");
+    }
+
+    #[test]
+    #[cfg(feature = "ordered-float")]
+    fn ordered_float() {
+        converts(json!(17.5), OrderedFloat(17.5), "17.5");
+        converts(json!(-17.0), OrderedFloat(-17.0), "-17");
+        fails::<OrderedFloat<f64>>(json!("17.5"));
+        assert_eq!(
+            OrderedFloat::<f64>::serialized_format().kind,
+            SerializedFormatKind::Float
+        );
+
+        converts(
+            json!([-1.0, 0.5, 2.5]),
+            vec![OrderedFloat(-1.0), OrderedFloat(0.5), OrderedFloat(2.5)]
+                .into_iter()
+                .collect::<BTreeSet<_>>(),
+            "[-1, 0.5, 2.5]",
+        );
+        fails::<BTreeSet<OrderedFloat<f64>>>(json!([2.5, "0.5"]));
+        assert_eq!(
+            BTreeSet::<OrderedFloat<f64>>::serialized_format().kind,
+            SerializedFormatKind::Set(Box::new(SerializedFormatKind::Float))
+        );
+    }
+
     #[test]
     fn set() {
         converts(
@@ -527,6 +817,82 @@ mod tests {
         )
     }
 
+    #[test]
+    fn tuple() {
+        converts(json!([1, "a"]), (1, "a".to_owned()), "(1, a)");
+        converts(json!([1, 2, true]), (1, 2, true), "(1, 2, true)");
+        fails::<(i32, i32)>(json!([1]));
+        fails::<(i32, i32)>(json!([1, 2, 3]));
+        fails::<(i32, i32)>(json!([1, "2"]));
+        fails::<(i32, i32)>(json!({"0": 1, "1": 2}));
+        let format = <(i32, String)>::serialized_format();
+        assert_eq!(
+            format.kind,
+            SerializedFormatKind::Tuple(vec![
+                SerializedFormatKind::Integer,
+                SerializedFormatKind::String(StringFormatKind::Any)
+            ])
+        );
+        assert_eq!(format.tag, "(i32, String)");
+    }
+
+    #[test]
+    fn ordered_pairs() {
+        let pairs = |items: &[(&str, &str)]| -> Vec<(String, String)> {
+            items
+                .iter()
+                .map(|&(key, value)| (key.to_owned(), value.to_owned()))
+                .collect()
+        };
+        converts(
+            json!([["Via", "b"], ["Accept", "*/*"], ["Via", "a"], ["X-Id", "1"]]),
+            pairs(&[("Via", "b"), ("Accept", "*/*"), ("Via", "a"), ("X-Id", "1")]),
+            "[(Via, b), (Accept, */*), (Via, a), ... 1 more]",
+        );
+        converts(json!([]), pairs(&[]), "[]");
+        fails::<Vec<(String, String)>>(json!([["Via"]]));
+        fails::<Vec<(String, String)>>(json!({"Via": "a"}));
+        let format = Vec::<(String, String)>::serialized_format();
+        assert_eq!(
+            format.kind,
+            SerializedFormatKind::List(Box::new(SerializedFormatKind::Tuple(vec![
+                SerializedFormatKind::String(StringFormatKind::Any),
+                SerializedFormatKind::String(StringFormatKind::Any)
+            ])))
+        );
+        assert_eq!(format.tag, "Vec<(String, String)>");
+    }
+
+    #[test]
+    fn entries_map() {
+        converts2(
+            json!([[[3, 4], "b"], [[1, 2], "a"], [[5, 6], "a"]]),
+            vec![
+                ((1, 2), "a".to_owned()),
+                ((3, 4), "b".to_owned()),
+                ((5, 6), "a".to_owned()),
+            ]
+            .into_iter()
+            .collect::<EntriesMap<(i32, i32), _>>(),
+            "{(1, 2), (5, 6): a, (3, 4): b}",
+            json!([[[1, 2], "a"], [[3, 4], "b"], [[5, 6], "a"]]),
+        );
+        converts(json!([]), EntriesMap::<i32, i32>::default(), "{}");
+        fails::<EntriesMap<(i32, i32), String>>(json!([[[1, 2]]]));
+        fails::<EntriesMap<(i32, i32), String>>(json!([[[1, "2"], "a"]]));
+        fails::<EntriesMap<(i32, i32), String>>(json!({"1": "a"}));
+        assert_eq!(
+            EntriesMap::<(i32, i32), bool>::serialized_format().kind,
+            SerializedFormatKind::Entries(
+                Box::new(SerializedFormatKind::Tuple(vec![
+                    SerializedFormatKind::Integer,
+                    SerializedFormatKind::Integer
+                ])),
+                Box::new(SerializedFormatKind::Bool)
+            )
+        )
+    }
+
     #[test]
     fn option() {
         converts(json!(17), Some(17), "Some(17)");
@@ -547,7 +913,11 @@ mod tests {
         fails::<Choices>(json!("Black"));
         assert_eq!(
             Choices::serialized_format().kind,
-            SerializedFormatKind::String(StringFormatKind::Choices(&["Red", "Green", "Blue"]))
+            SerializedFormatKind::String(StringFormatKind::Choices(vec![
+                "Red".to_owned(),
+                "Green".to_owned(),
+                "Blue".to_owned()
+            ]))
         )
     }
 }
@@ -0,0 +1,103 @@
+use crate::ValueSource;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::env;
+
+/// Provide the values of the feattles that were never persisted, from layers that sit between the
+/// compiled defaults and the persistence layer. Set with [`crate::Feattles::set_layered_source()`].
+///
+/// Each key is resolved with this precedence, from the lowest to the highest:
+///
+/// 1. the default declared in [`crate::feattles!`], reported as [`ValueSource::Default`]
+/// 2. a bundled file, added with [`LayeredSource::with_file()`], reported as [`ValueSource::File`]
+/// 3. an environment variable, enabled with [`LayeredSource::with_env()`], reported as
+///    [`ValueSource::Env`]
+/// 4. the persisted value, reported as [`ValueSource::Persisted`]
+///
+/// The layers are resolved again at every [`crate::Feattles::reload()`], so that a change to the
+/// environment variables is picked up.
+///
+/// # Example
+/// ```
+/// use feattle_core::{feattles, Feattles, LayeredSource, ValueSource};
+/// use feattle_core::persist::NoPersistence;
+/// use std::sync::Arc;
+///
+/// feattles! {
+///     struct MyFeattles {
+///         max_items: i32,
+///     }
+/// }
+///
+/// let my_feattles = MyFeattles::new(Arc::new(NoPersistence));
+/// let layers = LayeredSource::new()
+///     .with_file(r#"{"max_items": 17}"#)
+///     .unwrap()
+///     .with_env("MY_APP_");
+/// my_feattles.set_layered_source(layers);
+/// assert_eq!(*my_feattles.max_items(), 17);
+/// let (_, source, _) = my_feattles.value_and_source("max_items").unwrap();
+/// assert_eq!(source, ValueSource::File);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LayeredSource {
+    file: BTreeMap<String, Value>,
+    env_prefix: Option<String>,
+}
+
+impl LayeredSource {
+    /// Create a source without any layer
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the values of a bundled file, as a JSON object with the keys and their values, like the
+    /// one accepted by [`crate::Feattles::seed_from_json_if_empty()`]. The file can be bundled with
+    /// the binary with `include_str!()`, for example.
+    pub fn with_file(mut self, doc: &str) -> Result<Self, serde_json::Error> {
+        self.file = serde_json::from_str(doc)?;
+        Ok(self)
+    }
+
+    /// Read the values from the environment variables named by [`env_var_name()`], like
+    /// `MY_APP_MAX_ITEMS` or `MY_APP_DATABASE_POOL_SIZE` for the key `"database.pool_size"`.
+    ///
+    /// The variables are parsed as JSON, so a string that looks like another JSON value must be
+    /// quoted, like `MY_APP_NAME='"17"'`. Variables that are not valid JSON are read as strings.
+    /// With the prefix `FEATTLE_`, this reads back the output of `AdminPanel::export_env()` from
+    /// `feattle-ui`.
+    pub fn with_env(mut self, prefix: impl Into<String>) -> Self {
+        self.env_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Return the value of the highest layer that provides one for the given key, if any
+    pub fn resolve(&self, key: &str) -> Option<(Value, ValueSource)> {
+        if let Some(prefix) = &self.env_prefix {
+            if let Ok(raw) = env::var(env_var_name(prefix, key)) {
+                let value = serde_json::from_str(&raw).unwrap_or(Value::String(raw));
+                return Some((value, ValueSource::Env));
+            }
+        }
+        self.file
+            .get(key)
+            .map(|value| (value.clone(), ValueSource::File))
+    }
+}
+
+/// Return the name of the environment variable for the given key: the prefix followed by the key
+/// in upper case, with every character that is not an ASCII letter, digit or `_` replaced by `_`.
+///
+/// # Example
+/// ```
+/// use feattle_core::env_var_name;
+///
+/// assert_eq!(env_var_name("MY_APP_", "database.pool_size"), "MY_APP_DATABASE_POOL_SIZE");
+/// ```
+pub fn env_var_name(prefix: &str, key: &str) -> String {
+    let key = key.chars().map(|c| match c {
+        'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => c.to_ascii_uppercase(),
+        _ => '_',
+    });
+    prefix.chars().chain(key).collect()
+}
@@ -48,6 +48,110 @@
 //! implements [`FeattleValue`] and optionally provide a default. If not provided, the default
 //! will be created with `Default::default()`.
 //!
+//! # Attributes
+//! Besides doc comments, each feattle accepts these attributes:
+//!
+//! - `#[expires = "YYYY-MM-DD"]`: marks a temporary feattle, that should be removed after the given
+//!   date. Expired feattles are listed by [`Feattles::expired_feattles()`] and highlighted in the
+//!   admin UI. A date that is not valid fails the compilation.
+//! - `#[tags("a", "b")]`: categorizes the feattle with any number of tags, exposed in
+//!   [`FeattleDefinition::tags`]. The admin UI can filter the feattles by tag.
+//! - `#[copy]`: for types that implement `Copy`, the method returns a copy of the value instead of
+//!   the read guard, so that no `*` is needed. The read guard is still returned by the method with
+//!   the suffix `_ref`, like `new_checkout_ref()`. This is not supported inside groups.
+//! - `#[example = "..."]`: documents an example value, in its JSON representation, exposed in
+//!   [`FeattleDefinition::examples`]. It can be repeated and the admin UI shows the examples as
+//!   buttons that fill the edit form. Creating the struct panics if an example is not a valid value
+//!   for the feattle.
+//!
+//! ```
+//! use std::collections::BTreeMap;
+//! use std::sync::Arc;
+//! use feattle_core::{feattles, Feattles};
+//! use feattle_core::persist::NoPersistence;
+//!
+//! feattles! {
+//!     struct MyFeattles {
+//!         /// Kill switch for the new checkout flow
+//!         #[expires = "2024-12-31"]
+//!         #[tags("checkout", "experimental")]
+//!         #[copy]
+//!         new_checkout: bool = true,
+//!         /// The maximum number of items per country
+//!         #[example = r#"{"FR": 10, "BR": 5}"#]
+//!         max_items: BTreeMap<String, i32>,
+//!     }
+//! }
+//!
+//! let my_feattles = MyFeattles::new(Arc::new(NoPersistence));
+//! let new_checkout: bool = my_feattles.new_checkout();
+//! assert!(new_checkout);
+//! assert!(*my_feattles.new_checkout_ref());
+//! let definition = my_feattles.definition("max_items").unwrap();
+//! assert_eq!(definition.examples, [r#"{"FR": 10, "BR": 5}"#]);
+//! ```
+//!
+//! # Groups
+//! Related feattles can be declared in a group, with the syntax `$group: $GroupType { ... }`. The
+//! feattles inside the group are declared as usual and are accessed through the group, like
+//! `my_feattles.database().pool_size()`. Their keys are prefixed with the group name, like
+//! `"database.pool_size"`, and that's how they are persisted and shown in the admin UI. The group
+//! type is generated with the given name and the same visibility as the main struct.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use feattle_core::{feattles, Feattles};
+//! use feattle_core::persist::NoPersistence;
+//!
+//! feattles! {
+//!     struct MyFeattles {
+//!         is_cool: bool,
+//!         /// Settings for the database connection
+//!         database: DatabaseFeattles {
+//!             pool_size: i32 = 10,
+//!             timeout_ms: u64 = 500,
+//!         },
+//!     }
+//! }
+//!
+//! let my_feattles = MyFeattles::new(Arc::new(NoPersistence));
+//! assert_eq!(*my_feattles.database().pool_size(), 10);
+//! assert_eq!(my_feattles.keys(), &["is_cool", "database.pool_size", "database.timeout_ms"]);
+//! ```
+//!
+//! Groups can not be nested and the feattles in groups are listed after the other ones.
+//!
+//! # Derived feattles
+//! A feattle can be computed from the other ones, by declaring it with `#[derived = path]`, where
+//! `path` is a function that receives the feattles struct and returns the value. Its getter returns
+//! the computed value, instead of a lock guard. Derived feattles are read-only: trying to update
+//! them fails with [`UpdateError::ReadOnly`] and the admin UI shows them without an edit form.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use feattle_core::{feattles, Feattles};
+//! use feattle_core::persist::NoPersistence;
+//!
+//! feattles! {
+//!     struct MyFeattles {
+//!         timeout_ms: u64 = 500,
+//!         max_timeout_ms: u64 = 300,
+//!         /// The timeout, limited by `max_timeout_ms`
+//!         #[derived = effective_timeout_ms]
+//!         effective_timeout_ms: u64,
+//!     }
+//! }
+//!
+//! fn effective_timeout_ms(feattles: &MyFeattles) -> u64 {
+//!     (*feattles.timeout_ms()).min(*feattles.max_timeout_ms())
+//! }
+//!
+//! let my_feattles = MyFeattles::new(Arc::new(NoPersistence));
+//! assert_eq!(my_feattles.effective_timeout_ms(), 300);
+//! ```
+//!
+//! Derived feattles are not supported inside groups.
+//!
 //! # Updating values
 //! This crate only disposes of low-level methods to load current feattles with [`Feattles::reload()`]
 //! and update their values with [`Feattles::update()`]. Please look for the crates
@@ -83,32 +187,62 @@
 //! # Optional features
 //!
 //! - **uuid**: will add support for [`uuid::Uuid`].
+//! - **regex**: will add support for [`regex::Regex`].
+//! - **smallvec**: will add support for [`smallvec::SmallVec`].
+//! - **ordered-float**: will add support for [`ordered_float::OrderedFloat<f64>`], that can be
+//!   used in a `BTreeSet`.
+//! - **audit**: will add support for signing the history entries, see the module `audit`.
+//! - **tokio**: will add support for carrying a [`FeattleContext`] in a tokio task-local, with
+//!   [`FeattleContext::scope()`].
+//! - **arc-swap**: will store a copy of the values in an [`arc_swap::ArcSwap`], so that the getters
+//!   read them without locking and return a [`SnapshotGuard`] instead of a
+//!   [`MappedRwLockReadGuard`]. Holding it does not block the updates, but every change copies all
+//!   the values. Compare both modes with `cargo bench -p feattle-core [--features arc-swap]`.
+//! - **strict-serde**: will fail to load persisted data with unknown fields, instead of ignoring
+//!   them, to detect corrupt or misformatted data. See the module [`persist`].
+//! - **unique-keys**: will fail the compilation if two keys are the same once normalized to an
+//!   environment variable name, like `database_timeout` and `database.timeout`, or once converted
+//!   to snake case, like `fooBar` and `foo_bar`, that would collide when exported to other systems.
 
 #[doc(hidden)]
 pub mod __internal;
+#[cfg(feature = "audit")]
+pub mod audit;
+mod context;
 mod definition;
 mod feattle_value;
 pub mod json_reading;
 pub mod last_reload;
+mod layers;
 /// This module only contains exported macros, that are documented at the root level.
 #[doc(hidden)]
 pub mod macros;
 pub mod persist;
+mod update_hook;
+mod value_types;
 
-use crate::__internal::{FeattlesStruct, InnerFeattles};
+use crate::__internal::{FeattlesStruct, InnerFeattles, WriteGuard};
 use crate::json_reading::FromJsonError;
 use crate::last_reload::LastReload;
 use async_trait::async_trait;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+pub use context::*;
 pub use definition::*;
 pub use feattle_value::*;
-use parking_lot::{MappedRwLockReadGuard, RwLockReadGuard, RwLockWriteGuard};
+pub use layers::*;
+use parking_lot::{MappedRwLockReadGuard, RwLockReadGuard};
 use persist::*;
 use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
 use std::fmt::Debug;
 use std::sync::Arc;
 use thiserror::Error;
+pub use update_hook::*;
+pub use value_types::*;
+
+#[cfg(feature = "arc-swap")]
+pub use __internal::SnapshotGuard;
 
 /// Represents a type-erased error that comes from some external source
 pub type BoxError = Box<dyn Error + Send + Sync>;
@@ -122,6 +256,34 @@ pub enum UpdateError {
     /// The key is unknown
     #[error("the key {0} is unknown")]
     UnknownKey(String),
+    /// The key is a derived feattle, that can't be updated
+    #[error("the key {0} is derived and can't be updated")]
+    ReadOnly(String),
+    /// The change was vetoed by an [`UpdateHook`]
+    #[error("the update was rejected")]
+    Rejected(
+        #[source]
+        #[from]
+        RejectReason,
+    ),
+    /// There is no pending change for the key
+    #[error("there is no pending change for the key {0}")]
+    NoPendingChange(String),
+    /// The pending change for the key can't be approved by the same user that proposed it
+    #[error("the pending change for the key {0} can't be approved by the user that proposed it")]
+    SelfApproval(String),
+    /// The change must be proposed and approved, as configured by
+    /// [`Feattles::set_require_approval()`]
+    #[error("the change of the key {0} must be proposed and approved")]
+    ApprovalRequired(String),
+    /// The change of multiple keys at once, like with [`Feattles::replace_all()`], is refused
+    /// because each change must be proposed and approved, as configured by
+    /// [`Feattles::set_require_approval()`]
+    #[error("the change of multiple keys at once is refused, since each change must be proposed and approved")]
+    BulkApprovalRequired,
+    /// There is no staging value for the key
+    #[error("there is no staging value for the key {0}")]
+    NoStagingValue(String),
     /// Failed to parse the value from JSON
     #[error("failed to parse the value from JSON")]
     Parsing(
@@ -145,6 +307,91 @@ pub enum HistoryError {
     Persistence(#[source] BoxError),
 }
 
+/// The error type returned by [`Feattles::seed_from_json_if_empty()`]
+#[derive(Error, Debug)]
+pub enum SeedError {
+    /// Failed to reload the current values
+    #[error("failed to reload the current values")]
+    Reload(#[source] BoxError),
+    /// The document is not a JSON object
+    #[error("the document is not a JSON object")]
+    Parsing(
+        #[source]
+        #[from]
+        serde_json::Error,
+    ),
+    /// Failed to apply the values
+    #[error("failed to apply the values")]
+    Update(
+        #[source]
+        #[from]
+        UpdateError,
+    ),
+}
+
+/// Unify the error types of this crate, so that application code that calls several methods, like
+/// [`Feattles::reload()`], [`Feattles::update()`] and [`Feattles::history()`], can use a single
+/// error type with `?`. The specific error types are kept for precise handling.
+#[derive(Error, Debug)]
+pub enum FeattleError {
+    /// Failed to load, parse or save persisted data, as returned by [`Feattles::reload()`]
+    #[error("failed to load, parse or save persisted data")]
+    Persistence(
+        #[source]
+        #[from]
+        BoxError,
+    ),
+    /// Failed to update a value
+    #[error("failed to update a value")]
+    Update(
+        #[source]
+        #[from]
+        UpdateError,
+    ),
+    /// Failed to recover history information
+    #[error("failed to recover history information")]
+    History(
+        #[source]
+        #[from]
+        HistoryError,
+    ),
+    /// Failed to seed the values
+    #[error("failed to seed the values")]
+    Seed(
+        #[source]
+        #[from]
+        SeedError,
+    ),
+    /// Failed to render the admin panel, in `feattle-ui`
+    #[error("failed to render the admin panel")]
+    Render(#[source] BoxError),
+}
+
+/// How [`Feattles::reload()`] handles persisted values that fail to be parsed, for example because
+/// they were written by an instance with a different type for the same key. Set with
+/// [`Feattles::set_reload_policy()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReloadPolicy {
+    /// Keep the previous value in memory for the keys that fail to be parsed and update the others.
+    /// This is the default.
+    #[default]
+    UseLastGood,
+    /// Ignore the values that fail to be parsed, resetting those keys to their default values, and
+    /// update the others
+    SkipBadKeys,
+    /// Return [`ReloadError`] if any value fails to be parsed, without updating anything
+    FailReload,
+}
+
+/// The error returned by [`Feattles::reload()`] with [`ReloadPolicy::FailReload`]
+#[derive(Error, Debug)]
+#[error("failed to parse the persisted value of {key}")]
+pub struct ReloadError {
+    pub key: String,
+    #[source]
+    pub source: FromJsonError,
+}
+
 /// The main trait of this crate.
 ///
 /// The struct created with [`feattles!`] will implement this trait in addition to a method for each
@@ -157,6 +404,37 @@ pub trait Feattles: FeattlesPrivate {
     /// with [`Feattles::update`].
     fn new(persistence: Arc<dyn Persist>) -> Self;
 
+    /// Create a new feattles instance, seeded with data that is already in hand, like values read
+    /// from an external cache or prepared by a test. The persistence layer is not read.
+    ///
+    /// The feattles will start with the given values, as if they were loaded by
+    /// [`Feattles::reload()`], and [`Feattles::last_reload()`] and [`Feattles::current_values()`]
+    /// will report the given data from the start, instead of [`LastReload::Never`] and `None`. A
+    /// value that fails to be parsed is logged and the feattle keeps its default value.
+    fn new_with_values(
+        persistence: Arc<dyn Persist>,
+        last_reload: LastReload,
+        current_values: CurrentValues,
+    ) -> Self
+    where
+        Self: Sized,
+    {
+        let feattles = Self::new(persistence);
+        {
+            let mut inner = feattles._write();
+            let staging_mode = inner.staging_mode;
+            for &key in feattles.keys() {
+                let value = current_values.feattles.get(key).cloned();
+                if let Err(error) = inner.feattles_struct.try_update(key, value, staging_mode) {
+                    log::error!("Failed to seed {}: {:?}", key, error);
+                }
+            }
+            inner.last_reload = last_reload;
+            inner.current_values = Some(current_values);
+        }
+        feattles
+    }
+
     /// Return a shared reference to the persistence layer.
     fn persistence(&self) -> &Arc<dyn Persist>;
 
@@ -167,6 +445,30 @@ pub trait Feattles: FeattlesPrivate {
     /// exist.
     fn definition(&self, key: &str) -> Option<FeattleDefinition>;
 
+    /// Return the current value of one specific feattle in JSON, where it comes from and when it
+    /// was last modified, returning `None` if the feattle with the given name does not exist.
+    ///
+    /// Unlike calling [`Feattles::definition()`] and [`Feattles::current_values()`] separately,
+    /// the three parts are read together and are always consistent with each other, even if an
+    /// update happens concurrently.
+    fn value_and_source(&self, key: &str) -> Option<(Value, ValueSource, Option<DateTime<Utc>>)>;
+
+    /// Return the effective value of one specific feattle in the given context, as described in
+    /// [`FeattleValue::evaluate()`], returning `None` if the feattle with the given name does not
+    /// exist.
+    fn evaluate(&self, key: &str, context: &EvaluationContext) -> Option<Value>;
+
+    /// Return whether the boolean-like feattle (like `bool`, [`Toggle`] or [`Rollout`]) with the
+    /// given name is enabled for the request described by `ctx`. This evaluates the feattle with
+    /// [`Feattles::evaluate()`] at the current time, so a [`Rollout`] is enabled according to the
+    /// context's user. Unknown keys and feattles that do not evaluate to a boolean are considered
+    /// disabled.
+    fn is_enabled_ctx(&self, key: &str, ctx: &FeattleContext) -> bool {
+        self.evaluate(key, &ctx.evaluation_context())
+            .and_then(|value| value.as_bool())
+            .unwrap_or(false)
+    }
+
     /// Return details of the last time the data was synchronized by calling [`Feattles::reload()`].
     fn last_reload(&self) -> LastReload {
         self._read().last_reload
@@ -186,11 +488,119 @@ pub trait Feattles: FeattlesPrivate {
         }
     }
 
+    /// Return the keys with an update in progress, in alphabetical order.
+    ///
+    /// As described in [`Feattles::update()`], the new value is already observable while it's
+    /// being persisted and will be rolled back if that fails. This can help diagnosing a value that
+    /// changes and then reverts. [`Feattles::replace_all()`] and
+    /// [`Feattles::snapshot_to_persist()`] mark every key that they change.
+    fn updates_in_flight(&self) -> Vec<String> {
+        self._read().updates_in_flight.keys().cloned().collect()
+    }
+
+    /// Allow [`Feattles::update()`] to persist values for keys that are not declared in this
+    /// struct. By default, those updates are rejected with [`UpdateError::UnknownKey`].
+    ///
+    /// This is meant for trusted automation during rolling deploys: an older instance can write a
+    /// value that only newer instances know about. The value is stored in
+    /// [`CurrentValues::feattles`] and will be picked up by instances that declare the key on their
+    /// next [`Feattles::reload()`].
+    ///
+    /// # Risks
+    ///
+    /// Since the key is unknown, the value can not be validated: a typo in the key will persist a
+    /// value that no instance will ever read, and a value with the wrong format will be persisted
+    /// anyway. Instances that fail to parse it will keep their previous value and log an error, as
+    /// described in [`Feattles::reload()`].
+    fn set_accept_unknown_keys(&self, accept: bool) {
+        self._write().accept_unknown_keys = accept;
+    }
+
+    /// Guarantee that each new entry added to the history by [`Feattles::update()`] is strictly
+    /// more recent than the previous ones. By default, this is disabled.
+    ///
+    /// The modification date is taken from the local clock, so an instance with a skewed clock may
+    /// produce a history that appears out of order relative to other instances. When enabled, if
+    /// the local clock is behind the latest history entry, the new modification date will be one
+    /// microsecond after it instead, and a [`log::warn!`] will be generated.
+    fn set_monotonic_history(&self, monotonic: bool) {
+        self._write().monotonic_history = monotonic;
+    }
+
+    /// Sign each new entry added to the history, so that tampering with the persisted history can
+    /// be detected with [`persist::ValueHistory::verify_chain()`]. By default, entries are not
+    /// signed. Read more at the [`audit`] module.
+    ///
+    /// This method is only available with the cargo feature `audit`.
+    #[cfg(feature = "audit")]
+    fn set_audit_key(&self, audit_key: Option<audit::AuditKey>) {
+        self._write().audit_key = audit_key;
+    }
+
+    /// Select whether this instance reads the staging values set with
+    /// [`Feattles::update_staging()`] instead of the live ones. By default, this is disabled.
+    ///
+    /// This is meant to test a change on some canary instances before making it live with
+    /// [`Feattles::promote_staging()`]. Feattles without a staging value read the live one. The
+    /// values in memory are switched immediately, based on the last synchronized data.
+    fn set_staging_mode(&self, staging_mode: bool) {
+        let mut inner = self._write();
+        inner.staging_mode = staging_mode;
+        if let Some(current_values) = inner.current_values.clone() {
+            for &key in self.keys() {
+                let value = current_values.feattles.get(key).cloned();
+                if let Err(error) = inner.feattles_struct.try_update(key, value, staging_mode) {
+                    log::error!("Failed to update {}: {:?}", key, error);
+                }
+            }
+        }
+    }
+
+    /// Select how [`Feattles::reload()`] handles persisted values that fail to be parsed. By
+    /// default, [`ReloadPolicy::UseLastGood`] is used.
+    fn set_reload_policy(&self, policy: ReloadPolicy) {
+        self._write().reload_policy = policy;
+    }
+
+    /// Read the values of the feattles that were never persisted from the layers of the given
+    /// source, instead of using their defaults. See [`LayeredSource`] for the precedence of each
+    /// layer. Values that fail to be parsed are logged and ignored.
+    fn set_layered_source(&self, source: LayeredSource) {
+        let mut inner = self._write();
+        inner.layered_source = Some(Arc::new(source));
+        apply_layers(&mut inner, self.keys());
+    }
+
+    /// Require every change to go through the approval workflow, described in
+    /// [`Feattles::propose_update()`]. By default, this is disabled.
+    ///
+    /// When enabled, [`Feattles::update()`], [`Feattles::unset()`] and
+    /// [`Feattles::promote_staging()`] fail with [`UpdateError::ApprovalRequired`] and
+    /// [`Feattles::replace_all()`] fails with [`UpdateError::BulkApprovalRequired`], so that the
+    /// live values only change with [`Feattles::approve_update()`]. Note that this is enforced by
+    /// each instance, so all the instances that can change the values should enable it.
+    fn set_require_approval(&self, require_approval: bool) {
+        self._write().require_approval = require_approval;
+    }
+
+    /// Return whether the changes must be approved, as set by [`Feattles::set_require_approval()`].
+    fn requires_approval(&self) -> bool {
+        self._read().require_approval
+    }
+
+    /// Register a hook to be called around every change made by [`Feattles::update()`] and
+    /// [`Feattles::replace_all()`]. Hooks are called in the order they were added and the first
+    /// one to veto a change interrupts the update. Read more at [`UpdateHook`].
+    fn add_update_hook(&self, hook: Arc<dyn UpdateHook>) {
+        self._write().update_hooks.push(hook);
+    }
+
     /// Reload the current feattles' data from the persistence layer, propagating any errors
     /// produced by it.
     ///
-    /// If any of the feattle values fail to be parsed from previously persisted values, their
-    /// updates will be skipped. Other feattles that parsed successfully will still be updated.
+    /// If any of the feattle values fail to be parsed from previously persisted values, the
+    /// behavior depends on the [`ReloadPolicy`]: by default, their updates will be skipped and
+    /// their previous values kept. Other feattles that parsed successfully will still be updated.
     /// In this case, a [`log::error!`] will be generated for each time it occurs.
     async fn reload(&self) -> Result<(), BoxError> {
         let current_values = self.persistence().load_current().await?;
@@ -198,15 +608,35 @@ pub trait Feattles: FeattlesPrivate {
         let now = Utc::now();
         match current_values {
             None => {
+                apply_layers(&mut inner, self.keys());
                 inner.last_reload = LastReload::NoData { reload_date: now };
                 let empty = CurrentValues {
                     version: 0,
                     date: now,
                     feattles: Default::default(),
+                    revision: None,
                 };
                 inner.current_values = Some(empty);
             }
             Some(current_values) => {
+                let staging_mode = inner.staging_mode;
+                let policy = inner.reload_policy;
+                if policy == ReloadPolicy::FailReload {
+                    for &key in self.keys() {
+                        if let Some(value) = current_values.feattles.get(key) {
+                            let value = match &value.staging {
+                                Some(staging) if staging_mode => staging,
+                                _ => &value.value,
+                            };
+                            if let Err(source) = inner.feattles_struct.validate(key, value) {
+                                let key = key.to_owned();
+                                return Err(Box::new(ReloadError { key, source }));
+                            }
+                        }
+                    }
+                }
+
+                apply_layers(&mut inner, self.keys());
                 inner.last_reload = LastReload::Data {
                     reload_date: now,
                     version: current_values.version,
@@ -215,8 +645,11 @@ pub trait Feattles: FeattlesPrivate {
                 for &key in self.keys() {
                     let value = current_values.feattles.get(key).cloned();
                     log::debug!("Will update {} with {:?}", key, value);
-                    if let Err(error) = inner.feattles_struct.try_update(key, value) {
+                    if let Err(error) = inner.feattles_struct.try_update(key, value, staging_mode) {
                         log::error!("Failed to update {}: {:?}", key, error);
+                        if policy == ReloadPolicy::SkipBadKeys {
+                            let _ = inner.feattles_struct.try_update(key, None, staging_mode);
+                        }
                     }
                 }
                 inner.current_values = Some(current_values);
@@ -241,119 +674,725 @@ pub trait Feattles: FeattlesPrivate {
         value: Value,
         modified_by: String,
     ) -> Result<(), UpdateError> {
-        use UpdateError::*;
+        update_entry(self, key, value, modified_by, UpdateKind::Direct).await
+    }
 
-        // The update operation is made of 4 steps, each of which may fail:
-        // 1. parse and update the inner generic struct
-        // 2. persist the new history entry
-        // 3. persist the new current values
-        // 4. update the copy of the current values
-        // If any step fails, the others will be rolled back
+    /// Persist the current value of a single feattle again, without changing it. The version of the
+    /// current values is bumped and a history entry is added, marked with
+    /// [`HistoryEntry::touch`] to distinguish it from a real change. The update hooks are not
+    /// called.
+    ///
+    /// This can be used to reset the expiry of the data in storages with a TTL or to force the
+    /// other instances to notice a new version. A feattle that was never modified is persisted with
+    /// its current value.
+    ///
+    /// # Consistency
+    ///
+    /// To avoid operating on stale data, before doing an update the caller should usually call
+    /// [`Feattles::reload()`] to ensure data is current.
+    async fn touch(&self, key: &str, modified_by: String) -> Result<(), UpdateError> {
+        let persisted = self
+            ._read()
+            .current_values
+            .as_ref()
+            .and_then(|current_values| current_values.feattles.get(key))
+            .map(|current_value| current_value.value.clone());
+        let value = match persisted {
+            Some(value) => value,
+            None => {
+                self.definition(key)
+                    .ok_or_else(|| UpdateError::UnknownKey(key.to_owned()))?
+                    .value
+            }
+        };
+        update_entry(self, key, value, modified_by, UpdateKind::Touch).await
+    }
 
-        // Assert the key exists
-        if !self.keys().contains(&key) {
-            return Err(UnknownKey(key.to_owned()));
-        }
+    /// Remove the persisted value of a single feattle, so that it's back to "unset": its default
+    /// value is used and [`Feattles::value_and_source()`] reports [`ValueSource::Default`], unless
+    /// a layer of the [`LayeredSource`] provides a value. Any
+    /// staging value is removed as well. A history entry is added with the default value, marked
+    /// with [`HistoryEntry::unset`].
+    ///
+    /// This is different from calling [`Feattles::update()`] with the default value, that persists
+    /// it as an explicit override: a later change of the default in the code would not be picked up.
+    ///
+    /// # Consistency
+    ///
+    /// To avoid operating on stale data, before doing an update the caller should usually call
+    /// [`Feattles::reload()`] to ensure data is current.
+    async fn unset(&self, key: &str, modified_by: String) -> Result<(), UpdateError> {
+        let default = self
+            .definition(key)
+            .map(|definition| definition.default)
+            .unwrap_or(Value::Null);
+        update_entry(self, key, default, modified_by, UpdateKind::Unset).await
+    }
+
+    /// Set the staging value of a single feattle, that is read only by the instances in staging
+    /// mode, as described in [`Feattles::set_staging_mode()`]. Passing `None` removes it. The live
+    /// value is not affected and no history entry is added.
+    ///
+    /// Unlike [`Feattles::update()`], the update hooks are not called, since the change is only
+    /// effective after [`Feattles::promote_staging()`].
+    ///
+    /// # Consistency
+    ///
+    /// To avoid operating on stale data, before doing an update the caller should usually call
+    /// [`Feattles::reload()`] to ensure data is current.
+    async fn update_staging(
+        &self,
+        key: &str,
+        value: Option<Value>,
+        modified_by: String,
+    ) -> Result<(), UpdateError> {
+        use UpdateError::*;
 
-        let new_value = CurrentValue {
-            modified_at: Utc::now(),
-            modified_by,
-            value,
+        let default = match self.definition(key) {
+            None => return Err(UnknownKey(key.to_owned())),
+            Some(definition) if definition.read_only => return Err(ReadOnly(key.to_owned())),
+            Some(definition) => definition.default,
         };
+        if let Some(value) = &value {
+            self._read().feattles_struct.validate(key, value)?;
+        }
 
-        let (new_values, old_value) = {
+        let (mut new_values, old_value) = {
             let mut inner = self._write();
-
-            // Check error condition for step 4 and prepare the new instance
             let mut new_values = inner.current_values.clone().ok_or(NeverReloaded)?;
+            let new_value = match new_values.feattles.get(key) {
+                Some(old_value) => CurrentValue {
+                    staging: value,
+                    ..old_value.clone()
+                },
+                None if value.is_none() => return Ok(()),
+                None => CurrentValue {
+                    modified_at: Utc::now(),
+                    modified_by,
+                    value: default,
+                    staging: value,
+                },
+            };
             new_values
                 .feattles
                 .insert(key.to_owned(), new_value.clone());
             new_values.version += 1;
 
-            // Step 1
+            let staging_mode = inner.staging_mode;
             let old_value = inner
                 .feattles_struct
-                .try_update(key, Some(new_value.clone()))?;
-
+                .try_update(key, Some(new_value), staging_mode)?;
             (new_values, old_value)
         };
 
-        log::debug!("new_values = {:?}", new_values);
+        match self
+            .persistence()
+            .save_current_with_revision(&new_values)
+            .await
+        {
+            Ok(revision) => new_values.revision = revision,
+            Err(err) => {
+                let mut inner = self._write();
+                let staging_mode = inner.staging_mode;
+                let _ = inner
+                    .feattles_struct
+                    .try_update(key, old_value, staging_mode);
+                return Err(Persistence(err));
+            }
+        }
 
-        let rollback_step_1 = || {
-            // Note that if the old value was failing to parse, then the update will be final.
-            let _ = self
-                ._write()
-                .feattles_struct
-                .try_update(key, old_value.clone());
-        };
+        self._write().current_values = Some(new_values);
+        Ok(())
+    }
+
+    /// Make the staging value set with [`Feattles::update_staging()`] live, applying it like
+    /// [`Feattles::update()`] does and removing the staging value. If there is no staging value,
+    /// this fails with [`UpdateError::NoStagingValue`].
+    ///
+    /// # Consistency
+    ///
+    /// To avoid operating on stale data, before doing an update the caller should usually call
+    /// [`Feattles::reload()`] to ensure data is current.
+    async fn promote_staging(&self, key: &str, modified_by: String) -> Result<(), UpdateError> {
+        let staging = self
+            ._read()
+            .current_values
+            .as_ref()
+            .ok_or(UpdateError::NeverReloaded)?
+            .feattles
+            .get(key)
+            .and_then(|value| value.staging.clone())
+            .ok_or_else(|| UpdateError::NoStagingValue(key.to_owned()))?;
+        update_entry(self, key, staging, modified_by, UpdateKind::Promotion).await
+    }
+
+    /// Propose a change to a single feattle, that will only be applied after another user approves
+    /// it with [`Feattles::approve_update()`]. The value is checked but not applied: it's stored in
+    /// the persistence layer with [`Persist::save_pending()`], replacing any previous proposal for
+    /// the same key.
+    ///
+    /// The persistence layer must support pending changes, otherwise this fails with
+    /// [`persist::PendingUnsupported`]. The pending changes are read, modified and saved back by
+    /// one operation at a time in this instance, but the persistence layer is responsible for
+    /// the concurrent changes made by other instances.
+    async fn propose_update(
+        &self,
+        key: &str,
+        value: Value,
+        proposed_by: String,
+    ) -> Result<(), UpdateError> {
+        use UpdateError::*;
+
+        if !self.keys().contains(&key) {
+            return Err(UnknownKey(key.to_owned()));
+        }
+        {
+            let inner = self._read();
+            if inner.feattles_struct.is_derived(key) {
+                return Err(ReadOnly(key.to_owned()));
+            }
+            inner.feattles_struct.validate(key, &value)?;
+        }
 
-        // Step 2: load + modify + save history
+        let pending_lock = self._read().pending_lock.clone();
+        let _pending_guard = pending_lock.lock().await;
         let persistence = self.persistence();
-        let old_history = persistence
-            .load_history(key)
+        let mut pending = persistence
+            .load_pending()
             .await
-            .map_err(|err| {
-                rollback_step_1();
-                Persistence(err)
-            })?
+            .map_err(Persistence)?
             .unwrap_or_default();
+        pending.changes.insert(
+            key.to_owned(),
+            PendingChange {
+                value,
+                proposed_at: Utc::now(),
+                proposed_by,
+            },
+        );
+        persistence
+            .save_pending(&pending)
+            .await
+            .map_err(Persistence)
+    }
 
-        // Prepare updated history
-        let new_definition = self
-            .definition(key)
-            .expect("the key is guaranteed to exist");
-        let mut new_history = old_history.clone();
-        new_history.entries.push(HistoryEntry {
-            value: new_value.value.clone(),
-            value_overview: new_definition.value_overview,
-            modified_at: new_value.modified_at,
-            modified_by: new_value.modified_by.clone(),
-        });
+    /// Approve the change proposed with [`Feattles::propose_update()`] and apply it like
+    /// [`Feattles::update()`] does. The history entry records who proposed the change in
+    /// [`HistoryEntry::modified_by`] and who approved it in [`HistoryEntry::approved_by`].
+    ///
+    /// The change must be approved by another user than the one that proposed it, otherwise this
+    /// fails with [`UpdateError::SelfApproval`].
+    ///
+    /// The pending change is removed before it's applied, so that it can't be approved twice. If
+    /// it fails to be applied, it's restored.
+    ///
+    /// # Consistency
+    ///
+    /// To avoid operating on stale data, before doing an update the caller should usually call
+    /// [`Feattles::reload()`] to ensure data is current.
+    async fn approve_update(&self, key: &str, approved_by: String) -> Result<(), UpdateError> {
+        use UpdateError::*;
 
+        let pending_lock = self._read().pending_lock.clone();
+        let _pending_guard = pending_lock.lock().await;
+        let persistence = self.persistence();
+        let mut pending = persistence
+            .load_pending()
+            .await
+            .map_err(Persistence)?
+            .unwrap_or_default();
+        let change = pending
+            .changes
+            .remove(key)
+            .ok_or_else(|| NoPendingChange(key.to_owned()))?;
+        if change.proposed_by == approved_by {
+            return Err(SelfApproval(key.to_owned()));
+        }
         persistence
-            .save_history(key, &new_history)
+            .save_pending(&pending)
             .await
-            .map_err(|err| {
-                rollback_step_1();
-                Persistence(err)
-            })?;
+            .map_err(Persistence)?;
 
-        // Step 3
-        if let Err(err) = persistence.save_current(&new_values).await {
-            rollback_step_1();
-            if let Err(err) = self.persistence().save_history(key, &old_history).await {
-                log::warn!("Failed to rollback history for {}: {:?}", key, err);
+        let result = update_entry(
+            self,
+            key,
+            change.value.clone(),
+            change.proposed_by.clone(),
+            UpdateKind::Approval(approved_by),
+        )
+        .await;
+        if result.is_err() {
+            pending.changes.insert(key.to_owned(), change);
+            if let Err(err) = persistence.save_pending(&pending).await {
+                log::warn!("Failed to restore the pending change of {}: {:?}", key, err);
             }
-            return Err(Persistence(err));
         }
+        result
+    }
 
-        // Step 4
-        self._write().current_values = Some(new_values);
+    /// Reject the change proposed with [`Feattles::propose_update()`], discarding it.
+    async fn reject_update(&self, key: &str, rejected_by: String) -> Result<(), UpdateError> {
+        use UpdateError::*;
 
-        Ok(())
+        let pending_lock = self._read().pending_lock.clone();
+        let _pending_guard = pending_lock.lock().await;
+        let persistence = self.persistence();
+        let mut pending = persistence
+            .load_pending()
+            .await
+            .map_err(Persistence)?
+            .unwrap_or_default();
+        let change = pending
+            .changes
+            .remove(key)
+            .ok_or_else(|| NoPendingChange(key.to_owned()))?;
+        log::info!(
+            "Pending change of {} proposed by {} was rejected by {}",
+            key,
+            change.proposed_by,
+            rejected_by
+        );
+        persistence
+            .save_pending(&pending)
+            .await
+            .map_err(Persistence)
     }
 
-    /// Return the definition for all the feattles.
-    fn definitions(&self) -> Vec<FeattleDefinition> {
-        self.keys()
-            .iter()
-            .map(|&key| {
-                self.definition(key)
-                    .expect("since we iterate over the list of known keys, this should always work")
-            })
-            .collect()
+    /// Return the changes proposed with [`Feattles::propose_update()`] that are waiting for
+    /// approval.
+    async fn pending_updates(&self) -> Result<PendingChanges, BoxError> {
+        Ok(self.persistence().load_pending().await?.unwrap_or_default())
     }
 
-    /// Return the history for a single feattle. It can be potentially empty (not entries).
-    async fn history(&self, key: &str) -> Result<ValueHistory, HistoryError> {
-        // Assert the key exists
-        if !self.keys().contains(&key) {
-            return Err(HistoryError::UnknownKey(key.to_owned()));
+    /// Replace the values of all feattles at once, passing the new values (in JSON representation)
+    /// and the user that is associated with this change. Feattles that are absent from `values`
+    /// are reset to their default values. Values persisted for keys that are not declared in this
+    /// struct, as described in [`Feattles::set_accept_unknown_keys()`], are kept unless they are
+    /// given in `values`.
+    ///
+    /// Unlike calling [`Feattles::update()`] for each key, all values are parsed before any of
+    /// them is applied and the new current values are persisted in a single write, with a single
+    /// version bump. A history entry is added for each feattle whose value changed.
+    ///
+    /// If any value fails to parse or any step fails to persist, all changes will be rolled back.
+    ///
+    /// # Consistency
+    ///
+    /// To avoid operating on stale data, before doing an update the caller should usually call
+    /// [`Feattles::reload()`] to ensure data is current.
+    async fn replace_all(
+        &self,
+        values: BTreeMap<String, Value>,
+        modified_by: String,
+    ) -> Result<(), UpdateError> {
+        use UpdateError::*;
+
+        if self._read().require_approval {
+            return Err(BulkApprovalRequired);
         }
 
-        let history = self
+        // Assert the keys exist
+        if !self._read().accept_unknown_keys {
+            if let Some(key) = values
+                .keys()
+                .find(|key| !self.keys().contains(&key.as_str()))
+            {
+                return Err(UnknownKey(key.clone()));
+            }
+        }
+        if let Some(key) = values
+            .keys()
+            .find(|key| self._read().feattles_struct.is_derived(key))
+        {
+            return Err(ReadOnly(key.clone()));
+        }
+
+        let old_values = self._read().current_values.clone().ok_or(NeverReloaded)?;
+
+        // Detect which keys changed: the given ones with a different value and the declared ones
+        // that will be reset to their default
+        let changed_keys: Vec<String> = values
+            .keys()
+            .map(String::as_str)
+            .chain(self.keys().iter().copied())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .filter(|&key| old_values.feattles.get(key).map(|v| &v.value) != values.get(key))
+            .map(str::to_owned)
+            .collect();
+
+        // Feattles that are not given are reset to their default values
+        let proposed_values: BTreeMap<&str, Value> = changed_keys
+            .iter()
+            .map(|key| {
+                let value = match values.get(key) {
+                    Some(value) => value.clone(),
+                    None => {
+                        self.definition(key)
+                            .expect("only known keys are reset")
+                            .default
+                    }
+                };
+                (key.as_str(), value)
+            })
+            .collect();
+        let update_hooks = self._read().update_hooks.clone();
+        for (key, proposed_value) in &proposed_values {
+            for hook in &update_hooks {
+                hook.before_update(key, proposed_value, &modified_by)?;
+            }
+        }
+
+        let persistence = self.persistence();
+        let mut old_histories = BTreeMap::new();
+        for key in &changed_keys {
+            let history = persistence
+                .load_history_tail(key)
+                .await
+                .map_err(Persistence)?
+                .unwrap_or_default();
+            old_histories.insert(key.clone(), history);
+        }
+
+        // Values persisted for unknown keys are kept, since this instance can't reset them
+        let unknown_values = old_values
+            .feattles
+            .iter()
+            .filter(|&(key, _)| !self.keys().contains(&key.as_str()) && !values.contains_key(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let monotonic = self._read().monotonic_history;
+        let mut new_values = CurrentValues {
+            version: old_values.version + 1,
+            date: old_values.date,
+            feattles: unknown_values,
+            revision: old_values.revision.clone(),
+        };
+        for (key, value) in values {
+            let new_value = match old_histories.get(&key) {
+                None => old_values.feattles[&key].clone(),
+                Some(history) => CurrentValue {
+                    modified_at: next_modified_at(&key, history, monotonic),
+                    modified_by: modified_by.clone(),
+                    value,
+                    staging: old_values
+                        .feattles
+                        .get(&key)
+                        .and_then(|old_value| old_value.staging.clone()),
+                },
+            };
+            new_values.feattles.insert(key, new_value);
+        }
+
+        log::debug!("new_values = {:?}", new_values);
+
+        // Step 1: parse and update the inner generic struct, restoring the previous values if any
+        // of them fails
+        let rollback = |old_struct_values: Vec<(&str, Option<CurrentValue>)>| {
+            let mut inner = self._write();
+            let staging_mode = inner.staging_mode;
+            for (key, old_value) in old_struct_values {
+                let _ = inner
+                    .feattles_struct
+                    .try_update(key, old_value, staging_mode);
+            }
+        };
+        let mut old_struct_values = Vec::new();
+        {
+            let mut inner = self._write();
+            let staging_mode = inner.staging_mode;
+            for &key in self.keys() {
+                let new_value = new_values.feattles.get(key).cloned();
+                match inner
+                    .feattles_struct
+                    .try_update(key, new_value, staging_mode)
+                {
+                    Ok(old_value) => old_struct_values.push((key, old_value)),
+                    Err(err) => {
+                        drop(inner);
+                        rollback(old_struct_values);
+                        return Err(Parsing(err));
+                    }
+                }
+            }
+            for key in &changed_keys {
+                *inner.updates_in_flight.entry(key.clone()).or_default() += 1;
+            }
+        }
+        let _in_flight = InFlightGuard {
+            feattles: self,
+            keys: changed_keys.iter().map(String::as_str).collect(),
+        };
+
+        // Step 2: save history
+        let mut saved_histories = Vec::new();
+        for key in &changed_keys {
+            let new_history_entry = match new_values.feattles.get(key) {
+                Some(new_value) => HistoryEntry {
+                    value_overview: match self.definition(key) {
+                        Some(new_definition) => new_definition.value_overview,
+                        None => new_value.value.to_string(),
+                    },
+                    value: new_value.value.clone(),
+                    modified_at: new_value.modified_at,
+                    modified_by: new_value.modified_by.clone(),
+                    approved_by: None,
+                    touch: false,
+                    unset: false,
+                    signature: None,
+                },
+                None => {
+                    let new_definition = self.definition(key).expect("only known keys are reset");
+                    HistoryEntry {
+                        value: new_definition.value,
+                        value_overview: new_definition.value_overview,
+                        modified_at: next_modified_at(key, &old_histories[key], monotonic),
+                        modified_by: modified_by.clone(),
+                        approved_by: None,
+                        touch: false,
+                        unset: false,
+                        signature: None,
+                    }
+                }
+            };
+            let mut new_history = old_histories[key].clone();
+            new_history.entries.push(new_history_entry);
+            #[cfg(feature = "audit")]
+            if let Some(audit_key) = &self._read().audit_key {
+                audit_key.sign_last(&mut new_history);
+            }
+
+            if let Err(err) = persistence.save_history(key, &new_history).await {
+                rollback(old_struct_values);
+                rollback_histories(persistence, &saved_histories, &old_histories).await;
+                return Err(Persistence(err));
+            }
+            saved_histories.push(key.as_str());
+        }
+
+        // Step 3: save current values
+        match persistence.save_current_with_revision(&new_values).await {
+            Ok(revision) => new_values.revision = revision,
+            Err(err) => {
+                rollback(old_struct_values);
+                rollback_histories(persistence, &saved_histories, &old_histories).await;
+                return Err(Persistence(err));
+            }
+        }
+
+        // Step 4
+        self._write().current_values = Some(new_values);
+
+        for (key, new_value) in &proposed_values {
+            for hook in &update_hooks {
+                hook.after_update(key, new_value, &modified_by);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Persist the values currently in memory, making them authoritative in the storage. This is
+    /// useful after the values in memory were fixed locally, for example when some persisted
+    /// values failed to be parsed by [`Feattles::reload()`] and the previous ones were kept.
+    ///
+    /// This works like [`Feattles::replace_all()`] with the current value of each feattle that is
+    /// not derived: the current values are persisted in a single write and a history entry is
+    /// added for each feattle whose persisted value changed.
+    ///
+    /// **This overwrites the persisted data**: changes made by other instances since the last
+    /// reload are lost. Values persisted for keys that are not declared in this struct are kept. In
+    /// staging mode, the staging values in use become the live ones.
+    async fn snapshot_to_persist(&self, modified_by: String) -> Result<(), UpdateError> {
+        let values = self
+            .keys()
+            .iter()
+            .filter(|&&key| !self._read().feattles_struct.is_derived(key))
+            .map(|&key| {
+                let definition = self.definition(key).expect(
+                    "since we iterate over the list of known keys, this should always work",
+                );
+                (key.to_owned(), definition.value)
+            })
+            .collect();
+        self.replace_all(values, modified_by).await
+    }
+
+    /// Return the definition for all the feattles.
+    fn definitions(&self) -> Vec<FeattleDefinition> {
+        self.keys()
+            .iter()
+            .map(|&key| {
+                self.definition(key)
+                    .expect("since we iterate over the list of known keys, this should always work")
+            })
+            .collect()
+    }
+
+    /// Return the definition of the feattles for which the predicate returns `true`.
+    ///
+    /// # Example
+    /// ```
+    /// use feattle_core::{feattles, Feattles, SerializedFormatKind};
+    /// use feattle_core::persist::NoPersistence;
+    /// use std::sync::Arc;
+    ///
+    /// feattles! {
+    ///     struct MyToggles {
+    ///         enabled: bool,
+    ///         max_connections: i32 = 10,
+    ///     }
+    /// }
+    ///
+    /// let my_toggles = MyToggles::new(Arc::new(NoPersistence));
+    /// let bools = my_toggles
+    ///     .definitions_matching(|definition| definition.format.kind == SerializedFormatKind::Bool);
+    /// assert_eq!(bools.len(), 1);
+    /// ```
+    fn definitions_matching(
+        &self,
+        f: impl Fn(&FeattleDefinition) -> bool,
+    ) -> Vec<FeattleDefinition> {
+        self.definitions()
+            .into_iter()
+            .filter(|definition| f(definition))
+            .collect()
+    }
+
+    /// Return the current value of one specific string feattle, replacing each `${other_key}`
+    /// placeholder by the current value of the string feattle `other_key`, itself interpolated.
+    /// Returns `None` if the feattle with the given name does not exist or is not a string.
+    ///
+    /// Placeholders that reference unknown or non-string feattles are kept as they are. If the
+    /// references form a cycle, an error is logged and the raw value is returned.
+    ///
+    /// # Example
+    /// ```
+    /// use std::sync::Arc;
+    /// use feattle_core::{feattles, Feattles};
+    /// use feattle_core::persist::NoPersistence;
+    ///
+    /// feattles! {
+    ///     struct MyFeattles {
+    ///         base_url: String = "https://example.com".to_owned(),
+    ///         api_url: String = "${base_url}/api".to_owned(),
+    ///     }
+    /// }
+    ///
+    /// let my_feattles = MyFeattles::new(Arc::new(NoPersistence));
+    /// assert_eq!(my_feattles.interpolated("api_url").unwrap(), "https://example.com/api");
+    /// ```
+    fn interpolated(&self, key: &str) -> Option<String> {
+        let mut stack = Vec::new();
+        match interpolate(self, key, &mut stack) {
+            Ok(value) => value,
+            Err(cycle) => {
+                log::error!(
+                    "Failed to interpolate {}, since the references form a cycle: {}",
+                    key,
+                    cycle.join(" -> ")
+                );
+                self.definition(key)?.value.as_str().map(str::to_owned)
+            }
+        }
+    }
+
+    /// Return a fingerprint of the current value of one specific feattle, returning `None` if the
+    /// feattle with the given name does not exist.
+    ///
+    /// The fingerprint is a 64-bit FNV-1a hash of the value in JSON representation, so it's cheap
+    /// to store and compare: a cache can use it to detect that a specific feattle changed, without
+    /// keeping a copy of its value. The hash is stable across instances and restarts, but
+    /// different values may have the same fingerprint, although that is unlikely.
+    fn key_fingerprint(&self, key: &str) -> Option<u64> {
+        let definition = self.definition(key)?;
+        Some(fingerprint(&definition.value))
+    }
+
+    /// Return the keys whose value in memory differs from the one in the last synchronized data,
+    /// as returned by [`Feattles::current_values()`]. In staging mode, the staging values are
+    /// considered instead, as described in [`Feattles::set_staging_mode()`].
+    ///
+    /// This happens when a persisted value could not be parsed by [`Feattles::reload()`], for
+    /// example after the type of a feattle changed: the error is logged and the feattle silently
+    /// keeps its previous (or default) value. The persisted values are compared once parsed, so
+    /// equivalent representations, like `1` and `1.0` for a `f64`, do not diverge. Keys that were
+    /// never persisted and derived feattles are not considered. If a successful synchronization
+    /// have never happened, the list is empty.
+    fn persisted_vs_memory_divergence(&self) -> Vec<&'static str> {
+        let staging_mode = self._read().staging_mode;
+        let persisted: Vec<_> = match self.current_values() {
+            None => return Vec::new(),
+            Some(current_values) => self
+                .keys()
+                .iter()
+                .filter_map(|&key| {
+                    let value = current_values.feattles.get(key)?;
+                    let value = match &value.staging {
+                        Some(staging) if staging_mode => staging,
+                        _ => &value.value,
+                    };
+                    Some((key, value.clone()))
+                })
+                .collect(),
+        };
+
+        persisted
+            .into_iter()
+            .filter(|&(key, ref persisted)| match self.definition(key) {
+                None => false,
+                Some(definition) => {
+                    let persisted = self._read().feattles_struct.validate(key, persisted);
+                    !definition.read_only && persisted.ok().as_ref() != Some(&definition.value)
+                }
+            })
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// Return the definition of the feattles that were declared with `#[expires = "YYYY-MM-DD"]`
+    /// and whose expiry date has already passed.
+    fn expired_feattles(&self) -> Vec<FeattleDefinition> {
+        self.definitions_matching(|definition| {
+            definition.expiry_status == Some(ExpiryStatus::Expired)
+        })
+    }
+
+    /// Seed the persistence layer with the values from the given JSON document, but only if it has
+    /// no data yet. This is meant for first-boot or air-gapped scenarios, where a default
+    /// configuration is bundled with the binary, for example with `include_str!()`.
+    ///
+    /// The document must be a JSON object, mapping each key to its value in JSON representation.
+    /// This method calls [`Feattles::reload()`] and, if no data was found, applies all the values
+    /// at once with [`Feattles::replace_all()`], so that other instances will read them from the
+    /// persistence layer. Otherwise, nothing is changed. The return indicates whether the values
+    /// were applied.
+    ///
+    /// Note that this check is not atomic: if multiple instances start at the same time with an
+    /// empty persistence layer, each of them may apply the document.
+    async fn seed_from_json_if_empty(
+        &self,
+        doc: &str,
+        modified_by: String,
+    ) -> Result<bool, SeedError> {
+        self.reload().await.map_err(SeedError::Reload)?;
+        if !matches!(self.last_reload(), LastReload::NoData { .. }) {
+            return Ok(false);
+        }
+
+        let values: BTreeMap<String, Value> = serde_json::from_str(doc)?;
+        self.replace_all(values, modified_by).await?;
+        Ok(true)
+    }
+
+    /// Return the history for a single feattle. It can be potentially empty (not entries).
+    async fn history(&self, key: &str) -> Result<ValueHistory, HistoryError> {
+        // Assert the key exists
+        if !self.keys().contains(&key) {
+            return Err(HistoryError::UnknownKey(key.to_owned()));
+        }
+
+        let history = self
             .persistence()
             .load_history(key)
             .await
@@ -363,13 +1402,307 @@ pub trait Feattles: FeattlesPrivate {
     }
 }
 
+/// Return the modification date for a new entry in the given history. See
+/// [`Feattles::set_monotonic_history()`].
+fn next_modified_at(key: &str, history: &ValueHistory, monotonic: bool) -> DateTime<Utc> {
+    let now = Utc::now();
+    let last_modified_at = history.entries.iter().map(|e| e.modified_at).max();
+    match last_modified_at {
+        Some(last_modified_at) if monotonic && now <= last_modified_at => {
+            log::warn!(
+                "Local clock ({}) is behind the last history entry of {} ({}), will use a later \
+                modification date",
+                now,
+                key,
+                last_modified_at
+            );
+            last_modified_at + chrono::Duration::microseconds(1)
+        }
+        _ => now,
+    }
+}
+
+/// Implement [`Feattles::interpolated()`], keeping the keys that are being interpolated in `stack`
+/// to detect cycles, which are returned as the error
+fn interpolate<F: Feattles + ?Sized>(
+    feattles: &F,
+    key: &str,
+    stack: &mut Vec<String>,
+) -> Result<Option<String>, Vec<String>> {
+    if stack.iter().any(|other| other == key) {
+        let mut cycle = stack.clone();
+        cycle.push(key.to_owned());
+        return Err(cycle);
+    }
+    let template = match feattles.definition(key).map(|definition| definition.value) {
+        Some(Value::String(template)) => template,
+        _ => return Ok(None),
+    };
+
+    stack.push(key.to_owned());
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template.as_str();
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            None => break,
+            Some(end) => start + end,
+        };
+        result.push_str(&rest[..start]);
+        match interpolate(feattles, &rest[start + 2..end], stack)? {
+            Some(value) => result.push_str(&value),
+            None => result.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    stack.pop();
+
+    Ok(Some(result))
+}
+
+/// Compute the 64-bit FNV-1a hash of the JSON representation of the value
+fn fingerprint(value: &Value) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    value.to_string().bytes().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(PRIME)
+    })
+}
+
+/// The operation that applies a change with [`update_entry()`]
+enum UpdateKind {
+    /// [`Feattles::update()`]
+    Direct,
+    /// [`Feattles::approve_update()`], by the given user
+    Approval(String),
+    /// [`Feattles::promote_staging()`], that removes the staging value
+    Promotion,
+    /// [`Feattles::touch()`], that does not call the update hooks
+    Touch,
+    /// [`Feattles::unset()`], that removes the persisted value
+    Unset,
+}
+
+/// Implement [`Feattles::update()`] and the other operations that apply a change to a single key
+async fn update_entry<F: Feattles + Sync + ?Sized>(
+    feattles: &F,
+    key: &str,
+    value: Value,
+    modified_by: String,
+    kind: UpdateKind,
+) -> Result<(), UpdateError> {
+    use UpdateError::*;
+
+    // The update operation is made of 4 steps, each of which may fail:
+    // 1. parse and update the inner generic struct
+    // 2. persist the new history entry (the previous history is loaded beforehand)
+    // 3. persist the new current values
+    // 4. update the copy of the current values
+    // If any step fails, the others will be rolled back
+
+    // Assert the key exists
+    let is_known = feattles.keys().contains(&key);
+    if !is_known && !feattles._read().accept_unknown_keys {
+        return Err(UnknownKey(key.to_owned()));
+    }
+    if is_known && feattles._read().feattles_struct.is_derived(key) {
+        return Err(ReadOnly(key.to_owned()));
+    }
+    let needs_approval = !matches!(kind, UpdateKind::Approval(_) | UpdateKind::Touch);
+    if needs_approval && feattles._read().require_approval {
+        return Err(ApprovalRequired(key.to_owned()));
+    }
+
+    let update_hooks = match kind {
+        UpdateKind::Touch => Vec::new(),
+        _ => feattles._read().update_hooks.clone(),
+    };
+    for hook in &update_hooks {
+        hook.before_update(key, &value, &modified_by)?;
+    }
+
+    let persistence = feattles.persistence();
+    let old_history = persistence
+        .load_history_tail(key)
+        .await
+        .map_err(Persistence)?
+        .unwrap_or_default();
+
+    let monotonic = feattles._read().monotonic_history;
+    let mut new_value = CurrentValue {
+        modified_at: next_modified_at(key, &old_history, monotonic),
+        modified_by,
+        value,
+        staging: None,
+    };
+
+    let (mut new_values, old_value) = {
+        let mut inner = feattles._write();
+
+        // Check error condition for step 4 and prepare the new instance
+        let mut new_values = inner.current_values.clone().ok_or(NeverReloaded)?;
+        if !matches!(kind, UpdateKind::Promotion) {
+            new_value.staging = new_values
+                .feattles
+                .get(key)
+                .and_then(|old_value| old_value.staging.clone());
+        }
+        let persisted_value = match kind {
+            UpdateKind::Unset => {
+                new_values.feattles.remove(key);
+                None
+            }
+            _ => {
+                new_values
+                    .feattles
+                    .insert(key.to_owned(), new_value.clone());
+                Some(new_value.clone())
+            }
+        };
+        new_values.version += 1;
+
+        // Step 1 (unknown keys are not part of the struct)
+        let old_value = if is_known {
+            let staging_mode = inner.staging_mode;
+            inner
+                .feattles_struct
+                .try_update(key, persisted_value, staging_mode)?
+        } else {
+            None
+        };
+        *inner.updates_in_flight.entry(key.to_owned()).or_default() += 1;
+
+        (new_values, old_value)
+    };
+    let _in_flight = InFlightGuard {
+        feattles,
+        keys: vec![key],
+    };
+
+    log::debug!("new_values = {:?}", new_values);
+
+    let rollback_step_1 = || {
+        // Note that if the old value was failing to parse, then the update will be final.
+        if is_known {
+            let mut inner = feattles._write();
+            let staging_mode = inner.staging_mode;
+            let _ = inner
+                .feattles_struct
+                .try_update(key, old_value.clone(), staging_mode);
+        }
+    };
+
+    // Step 2: modify + save history
+    let value_overview = match feattles.definition(key) {
+        Some(new_definition) => new_definition.value_overview,
+        None => new_value.value.to_string(),
+    };
+    let mut new_history = old_history.clone();
+    new_history.entries.push(HistoryEntry {
+        value: new_value.value.clone(),
+        value_overview,
+        modified_at: new_value.modified_at,
+        modified_by: new_value.modified_by.clone(),
+        touch: matches!(kind, UpdateKind::Touch),
+        unset: matches!(kind, UpdateKind::Unset),
+        approved_by: match kind {
+            UpdateKind::Approval(approved_by) => Some(approved_by),
+            _ => None,
+        },
+        signature: None,
+    });
+    #[cfg(feature = "audit")]
+    if let Some(audit_key) = &feattles._read().audit_key {
+        audit_key.sign_last(&mut new_history);
+    }
+
+    persistence
+        .save_history(key, &new_history)
+        .await
+        .map_err(|err| {
+            rollback_step_1();
+            Persistence(err)
+        })?;
+
+    // Step 3
+    match persistence.save_current_with_revision(&new_values).await {
+        Ok(revision) => new_values.revision = revision,
+        Err(err) => {
+            rollback_step_1();
+            if let Err(err) = feattles.persistence().save_history(key, &old_history).await {
+                log::warn!("Failed to rollback history for {}: {:?}", key, err);
+            }
+            return Err(Persistence(err));
+        }
+    }
+
+    // Step 4
+    feattles._write().current_values = Some(new_values);
+
+    for hook in &update_hooks {
+        hook.after_update(key, &new_value.value, &new_value.modified_by);
+    }
+
+    Ok(())
+}
+
+/// Remove an update of the given keys from [`InnerFeattles::updates_in_flight`] when dropped,
+/// however the update ends
+struct InFlightGuard<'a, F: FeattlesPrivate + ?Sized> {
+    feattles: &'a F,
+    keys: Vec<&'a str>,
+}
+
+impl<F: FeattlesPrivate + ?Sized> Drop for InFlightGuard<'_, F> {
+    fn drop(&mut self) {
+        let mut inner = self.feattles._write();
+        for &key in &self.keys {
+            if let Some(count) = inner.updates_in_flight.get_mut(key) {
+                *count -= 1;
+                if *count == 0 {
+                    inner.updates_in_flight.remove(key);
+                }
+            }
+        }
+    }
+}
+
+/// Resolve the layers of the [`LayeredSource`] again for all keys, if any was set
+fn apply_layers<FS: FeattlesStruct>(inner: &mut InnerFeattles<FS>, keys: &[&str]) {
+    let source = match &inner.layered_source {
+        None => return,
+        Some(source) => source.clone(),
+    };
+    for &key in keys {
+        if inner.feattles_struct.is_derived(key) {
+            continue;
+        }
+        if let Err(error) = inner.feattles_struct.set_layer(key, source.resolve(key)) {
+            log::error!("Failed to apply the layered value of {}: {:?}", key, error);
+        }
+    }
+}
+
+async fn rollback_histories(
+    persistence: &Arc<dyn Persist>,
+    keys: &[&str],
+    old_histories: &BTreeMap<String, ValueHistory>,
+) {
+    for &key in keys {
+        if let Err(err) = persistence.save_history(key, &old_histories[key]).await {
+            log::warn!("Failed to rollback history for {}: {:?}", key, err);
+        }
+    }
+}
+
 /// This struct is `pub` because the macro must have access to it, but should be otherwise invisible
 /// to the users of this crate.
 #[doc(hidden)]
 pub trait FeattlesPrivate {
     type FeattleStruct: FeattlesStruct;
     fn _read(&self) -> RwLockReadGuard<InnerFeattles<Self::FeattleStruct>>;
-    fn _write(&self) -> RwLockWriteGuard<InnerFeattles<Self::FeattleStruct>>;
+    fn _write(&self) -> WriteGuard<Self::FeattleStruct>;
 }
 
 #[cfg(test)]
@@ -379,6 +1712,7 @@ mod tests {
     use serde_json::json;
     use std::collections::BTreeMap;
     use std::sync::Arc;
+    use tokio::sync::Notify;
 
     #[derive(Debug, thiserror::Error)]
     #[error("Some error")]
@@ -508,4 +1842,1640 @@ mod tests {
         assert_eq!(&history.entries[0].value_overview, "27");
         assert_eq!(&history.entries[0].modified_by, "somebody");
     }
+
+    #[tokio::test]
+    async fn update_unknown_key() {
+        feattles! {
+            struct Config {
+                a: i32,
+            }
+        }
+
+        mod newer {
+            use crate::feattles;
+
+            feattles! {
+                pub struct Config {
+                    a: i32,
+                    b: i32,
+                }
+            }
+        }
+
+        let persistence = Arc::new(MockPersistence::default());
+        let config = Config::new(persistence.clone());
+        config.reload().await.unwrap();
+        assert_eq!(*config.a(), 0);
+
+        // Rejected by default
+        let error = config
+            .update("b", json!(17i32), "automation".to_owned())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, UpdateError::UnknownKey(key) if key == "b"));
+
+        // Accepted when configured
+        config.set_accept_unknown_keys(true);
+        config
+            .update("b", json!(17i32), "automation".to_owned())
+            .await
+            .unwrap();
+        let values = persistence.unwrap_current();
+        assert_eq!(values.version, 1);
+        assert_eq!(values.feattles.get("b").unwrap().value, json!(17i32));
+        let history = persistence.unwrap_history("b");
+        assert_eq!(&history.entries[0].value_overview, "17");
+
+        // A struct that knows the key reads the value
+        let newer_config = newer::Config::new(persistence.clone());
+        newer_config.reload().await.unwrap();
+        assert_eq!(*newer_config.a(), 0);
+        assert_eq!(*newer_config.b(), 17);
+
+        // Replacing all the values keeps the ones of unknown keys
+        config
+            .replace_all(
+                BTreeMap::from([("a".to_owned(), json!(1i32))]),
+                "somebody".to_owned(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            persistence.unwrap_current().feattles["b"].value,
+            json!(17i32)
+        );
+        assert_eq!(persistence.unwrap_history("b").entries.len(), 1);
+    }
+
+    #[cfg(feature = "arc-swap")]
+    #[tokio::test]
+    async fn arc_swap_snapshot() {
+        feattles! {
+            struct Config {
+                a: i32,
+                #[copy]
+                b: bool,
+            }
+        }
+
+        let config = Config::new(Arc::new(MockPersistence::default()));
+        config.reload().await.unwrap();
+
+        // Holding a value does not block the updates, that are seen by the next reads
+        let a = config.a();
+        config
+            .update("a", json!(17i32), "somebody".to_owned())
+            .await
+            .unwrap();
+        config
+            .update("b", json!(true), "somebody".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(*a, 0);
+        assert_eq!(*config.a(), 17);
+        assert!(config.b());
+
+        // Taking the write lock without mutating does not publish a new snapshot
+        let snapshot = config.0.snapshot.load_full();
+        drop(config._write());
+        assert!(Arc::ptr_eq(&snapshot, &config.0.snapshot.load_full()));
+        config._write().accept_unknown_keys = true;
+        assert!(!Arc::ptr_eq(&snapshot, &config.0.snapshot.load_full()));
+    }
+
+    #[tokio::test]
+    async fn layered_source() {
+        feattles! {
+            struct Config {
+                by_default: i32 = 1,
+                by_file: i32 = 1,
+                by_env: i32 = 1,
+                by_persisted: i32 = 1,
+                name: String,
+                code: String,
+                database: DatabaseConfig {
+                    pool_size: i32 = 1,
+                },
+            }
+        }
+
+        std::env::set_var("FEATTLE_LAYERED_BY_ENV", "3");
+        std::env::set_var("FEATTLE_LAYERED_BY_PERSISTED", "3");
+        std::env::set_var("FEATTLE_LAYERED_NAME", "plain text");
+        std::env::set_var("FEATTLE_LAYERED_CODE", r#""17""#);
+        std::env::set_var("FEATTLE_LAYERED_DATABASE_POOL_SIZE", "5");
+        let persistence = Arc::new(MockPersistence::default());
+        let config = Config::new(persistence.clone());
+        let layers = LayeredSource::new()
+            .with_file(r#"{"by_file": 2, "by_env": 2, "by_persisted": 2}"#)
+            .unwrap()
+            .with_env("FEATTLE_LAYERED_");
+        config.set_layered_source(layers);
+        config.reload().await.unwrap();
+        config
+            .update("by_persisted", json!(4i32), "somebody".to_owned())
+            .await
+            .unwrap();
+
+        let resolved = |key| {
+            let (value, source, _) = config.value_and_source(key).unwrap();
+            (value, source)
+        };
+        assert_eq!(resolved("by_default"), (json!(1i32), ValueSource::Default));
+        assert_eq!(resolved("by_file"), (json!(2i32), ValueSource::File));
+        assert_eq!(resolved("by_env"), (json!(3i32), ValueSource::Env));
+        assert_eq!(
+            resolved("by_persisted"),
+            (json!(4i32), ValueSource::Persisted)
+        );
+        assert_eq!(resolved("name"), (json!("plain text"), ValueSource::Env));
+        let values = (
+            *config.by_default(),
+            *config.by_file(),
+            *config.by_env(),
+            *config.by_persisted(),
+        );
+        assert_eq!(values, (1, 2, 3, 4));
+        assert_eq!(*config.name(), "plain text");
+        assert_eq!(*config.code(), "17");
+        assert_eq!(*config.database().pool_size(), 5);
+
+        // The layers are used again once unset and resolved again on reload
+        config
+            .unset("by_persisted", "somebody".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(resolved("by_persisted"), (json!(3i32), ValueSource::Env));
+        std::env::remove_var("FEATTLE_LAYERED_BY_ENV");
+        config.reload().await.unwrap();
+        assert_eq!(resolved("by_env"), (json!(2i32), ValueSource::File));
+
+        // A value that fails to parse is not used, nor the one that was used before
+        std::env::set_var("FEATTLE_LAYERED_DATABASE_POOL_SIZE", "not a number");
+        config.reload().await.unwrap();
+        assert_eq!(
+            resolved("database.pool_size"),
+            (json!(1i32), ValueSource::Default)
+        );
+    }
+
+    #[tokio::test]
+    #[allow(dead_code)]
+    async fn feattle_error() {
+        feattles! {
+            struct Config {
+                a: i32,
+            }
+        }
+
+        async fn reload(config: &Config) -> Result<(), FeattleError> {
+            config.reload().await?;
+            Ok(())
+        }
+
+        async fn update(config: &Config) -> Result<(), FeattleError> {
+            config.update("b", json!(17), "somebody".to_owned()).await?;
+            Ok(())
+        }
+
+        async fn history(config: &Config) -> Result<ValueHistory, FeattleError> {
+            Ok(config.history("b").await?)
+        }
+
+        async fn seed(config: &Config) -> Result<bool, FeattleError> {
+            Ok(config
+                .seed_from_json_if_empty("[]", "somebody".to_owned())
+                .await?)
+        }
+
+        let persistence = Arc::new(MockPersistence::default());
+        let config = Config::new(persistence.clone());
+        persistence.put_error();
+        assert!(matches!(
+            reload(&config).await,
+            Err(FeattleError::Persistence(_))
+        ));
+        assert!(matches!(
+            update(&config).await,
+            Err(FeattleError::Update(UpdateError::UnknownKey(_)))
+        ));
+        assert!(matches!(
+            history(&config).await,
+            Err(FeattleError::History(HistoryError::UnknownKey(_)))
+        ));
+        assert!(matches!(
+            seed(&config).await,
+            Err(FeattleError::Seed(SeedError::Parsing(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn unset() {
+        feattles! {
+            struct Config {
+                a: i32 = 5,
+            }
+        }
+
+        let persistence = Arc::new(MockPersistence::default());
+        let config = Config::new(persistence.clone());
+        config.reload().await.unwrap();
+        config
+            .update("a", json!(17i32), "somebody".to_owned())
+            .await
+            .unwrap();
+        config.unset("a", "operator".to_owned()).await.unwrap();
+
+        assert_eq!(*config.a(), 5);
+        let current = persistence.unwrap_current();
+        assert_eq!(current.version, 2);
+        assert!(!current.feattles.contains_key("a"));
+        let history = persistence.unwrap_history("a");
+        assert_eq!(history.entries.len(), 2);
+        assert_eq!(history.entries[1].value, json!(5i32));
+        assert_eq!(history.entries[1].modified_by, "operator");
+        assert!(history.entries[1].unset);
+        let (_, source, modified_at) = config.value_and_source("a").unwrap();
+        assert_eq!((source, modified_at), (ValueSource::Default, None));
+
+        // Other instances fall back to the default on reload
+        let other = Config::new(persistence.clone());
+        other.reload().await.unwrap();
+        assert_eq!(*other.a(), 5);
+        assert_eq!(other.definition("a").unwrap().modified_by, None);
+
+        // Updating to the default value persists it as an override instead
+        config
+            .update("a", json!(5i32), "somebody".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(*config.a(), 5);
+        let current = persistence.unwrap_current();
+        assert_eq!(current.feattles["a"].value, json!(5i32));
+        assert!(!persistence.unwrap_history("a").entries[2].unset);
+        let (_, source, _) = config.value_and_source("a").unwrap();
+        assert_eq!(source, ValueSource::Persisted);
+    }
+
+    #[tokio::test]
+    async fn updates_in_flight() {
+        /// Block the saving of history until resumed
+        #[derive(Default)]
+        struct SlowPersistence {
+            inner: MockPersistence,
+            started: Notify,
+            resume: Notify,
+        }
+
+        #[async_trait]
+        impl Persist for SlowPersistence {
+            async fn save_current(&self, value: &CurrentValues) -> Result<(), BoxError> {
+                self.inner.save_current(value).await
+            }
+
+            async fn load_current(&self) -> Result<Option<CurrentValues>, BoxError> {
+                self.inner.load_current().await
+            }
+
+            async fn save_history(&self, key: &str, value: &ValueHistory) -> Result<(), BoxError> {
+                self.started.notify_one();
+                self.resume.notified().await;
+                self.inner.save_history(key, value).await
+            }
+
+            async fn load_history(&self, key: &str) -> Result<Option<ValueHistory>, BoxError> {
+                self.inner.load_history(key).await
+            }
+        }
+
+        feattles! {
+            struct Config { a: i32, b: i32 }
+        }
+
+        let persistence = Arc::new(SlowPersistence::default());
+        let config = Config::new(persistence.clone());
+        config.reload().await.unwrap();
+        assert!(config.updates_in_flight().is_empty());
+
+        let update = config.update("a", json!(17i32), "somebody".to_owned());
+        let observe = async {
+            persistence.started.notified().await;
+            let in_flight = config.updates_in_flight();
+            let value = *config.a();
+            persistence.resume.notify_one();
+            (in_flight, value)
+        };
+        let (result, (in_flight, value)) = tokio::join!(update, observe);
+        result.unwrap();
+        assert_eq!(in_flight, ["a"]);
+        assert_eq!(value, 17);
+        assert!(config.updates_in_flight().is_empty());
+
+        // Also cleared when the update fails and is rolled back
+        let update = config.update("a", json!(27i32), "somebody".to_owned());
+        let fail = async {
+            persistence.started.notified().await;
+            assert_eq!(*config.a(), 27);
+            persistence.inner.put_error();
+            persistence.resume.notify_one();
+        };
+        let (result, ()) = tokio::join!(update, fail);
+        result.unwrap_err();
+        assert!(config.updates_in_flight().is_empty());
+        assert_eq!(*config.a(), 17);
+
+        // All the keys changed by a replacement are in flight
+        let values = vec![("a", json!(1i32)), ("b", json!(2i32))]
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value))
+            .collect();
+        let replace = config.replace_all(values, "somebody".to_owned());
+        let observe = async {
+            persistence.started.notified().await;
+            let in_flight = config.updates_in_flight();
+            persistence.resume.notify_one();
+            persistence.started.notified().await;
+            persistence.resume.notify_one();
+            in_flight
+        };
+        let (result, in_flight) = tokio::join!(replace, observe);
+        result.unwrap();
+        assert_eq!(in_flight, ["a", "b"]);
+        assert!(config.updates_in_flight().is_empty());
+        assert_eq!((*config.a(), *config.b()), (1, 2));
+    }
+
+    #[tokio::test]
+    async fn new_with_values() {
+        feattles! {
+            struct Config {
+                a: i32,
+                b: String,
+            }
+        }
+
+        let persistence = Arc::new(MockPersistence::default());
+        let source = Config::new(persistence.clone());
+        source.reload().await.unwrap();
+        source
+            .update("a", json!(17i32), "somebody".to_owned())
+            .await
+            .unwrap();
+        let current_values = persistence.unwrap_current();
+        let date = current_values.date;
+        let last_reload = LastReload::Data {
+            reload_date: date,
+            version: current_values.version,
+            version_date: date,
+        };
+
+        let config = Config::new_with_values(persistence.clone(), last_reload, current_values);
+        assert_eq!(config.last_reload(), last_reload);
+        assert_eq!(config.current_values().unwrap().version, 1);
+        assert_eq!(*config.a(), 17);
+        assert_eq!(*config.b(), "");
+        let definition = config.definition("a").unwrap();
+        assert_eq!(definition.modified_by.as_deref(), Some("somebody"));
+
+        // Updates are accepted without an initial reload
+        config
+            .update("b", json!("hello"), "somebody".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(persistence.unwrap_current().version, 2);
+    }
+
+    #[test]
+    fn json_schema() {
+        let kind = <BTreeMap<String, Option<(i32, Toggle)>>>::serialized_format().kind;
+        assert_eq!(
+            kind.json_schema(),
+            json!({
+                "type": "object",
+                "propertyNames": { "type": "string" },
+                "additionalProperties": {
+                    "anyOf": [{
+                        "type": "array",
+                        "prefixItems": [
+                            { "type": "integer" },
+                            {
+                                "type": "object",
+                                "properties": {
+                                    "enabled": { "type": "boolean" },
+                                    "note": { "anyOf": [{ "type": "string" }, { "type": "null" }] },
+                                },
+                                "required": ["enabled"],
+                            },
+                        ],
+                        "minItems": 2,
+                        "maxItems": 2,
+                    }, { "type": "null" }],
+                },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn copy() {
+        feattles! {
+            struct Config {
+                #[copy]
+                is_cool: bool,
+                /// Copied even with other attributes
+                #[tags("a")]
+                #[copy]
+                #[expires = "2099-01-01"]
+                max_blings: i32 = 17,
+                #[copy]
+                #[derived = double_blings]
+                double: i32,
+                names: Vec<String>,
+            }
+        }
+
+        fn double_blings(config: &Config) -> i32 {
+            2 * config.max_blings()
+        }
+
+        let config = Config::new(Arc::new(MockPersistence::default()));
+        config.reload().await.unwrap();
+        let is_cool: bool = config.is_cool();
+        assert!(!is_cool);
+        assert_eq!(config.max_blings(), 17);
+        assert_eq!(config.double(), 34);
+        assert!(config.names().is_empty());
+
+        config
+            .update("is_cool", json!(true), "somebody".to_owned())
+            .await
+            .unwrap();
+        let is_cool: bool = config.is_cool();
+        assert!(is_cool);
+
+        // The guard is still available
+        assert!(*config.is_cool_ref());
+        assert_eq!(*config.max_blings_ref(), 17);
+        let definition = config.definition("max_blings").unwrap();
+        assert_eq!(definition.tags, ["a"]);
+        assert!(definition.expires.is_some());
+    }
+
+    #[tokio::test]
+    async fn snapshot_to_persist() {
+        feattles! {
+            struct Config {
+                a: i32,
+                b: i32 = 17,
+                c: String,
+                #[derived = double_a]
+                d: i32,
+            }
+        }
+
+        fn double_a(config: &Config) -> i32 {
+            2 * *config.a()
+        }
+
+        let persistence = Arc::new(MockPersistence::default());
+        let config = Config::new(persistence.clone());
+        config.reload().await.unwrap();
+        config
+            .update("a", json!(27i32), "somebody".to_owned())
+            .await
+            .unwrap();
+
+        // A value that can not be parsed keeps the previous one in memory
+        persistence
+            .0
+            .lock()
+            .current
+            .as_mut()
+            .unwrap()
+            .feattles
+            .get_mut("a")
+            .unwrap()
+            .value = json!("not a number");
+        config.reload().await.unwrap();
+        assert_eq!(*config.a(), 27);
+
+        config
+            .snapshot_to_persist("fixer".to_owned())
+            .await
+            .unwrap();
+        let current = persistence.unwrap_current();
+        assert_eq!(current.version, 2);
+        assert_eq!(current.feattles["a"].value, json!(27));
+        assert_eq!(current.feattles["a"].modified_by, "fixer");
+        assert_eq!(current.feattles["b"].value, json!(17));
+        assert!(!current.feattles.contains_key("d"));
+        assert_eq!(persistence.unwrap_history("a").entries.len(), 2);
+
+        let fresh = Config::new(persistence.clone());
+        fresh.reload().await.unwrap();
+        assert_eq!(fresh.definitions().len(), 4);
+        for definition in config.definitions() {
+            let fresh_definition = fresh.definition(definition.key).unwrap();
+            assert_eq!(fresh_definition.value, definition.value);
+        }
+        assert!(fresh.persisted_vs_memory_divergence().is_empty());
+        assert_eq!((*fresh.b(), fresh.c().as_str(), fresh.d()), (17, "", 54));
+    }
+
+    #[tokio::test]
+    async fn replace_all() {
+        feattles! {
+            struct Config {
+                a: i32,
+                b: i32 = 17,
+                c: String,
+            }
+        }
+
+        let persistence = Arc::new(MockPersistence::default());
+        let config = Config::new(persistence.clone());
+        config
+            .replace_all(BTreeMap::new(), "somebody".to_owned())
+            .await
+            .unwrap_err();
+
+        config.reload().await.unwrap();
+        config
+            .update("a", json!(1i32), "somebody".to_owned())
+            .await
+            .unwrap();
+        config
+            .update("b", json!(2i32), "somebody".to_owned())
+            .await
+            .unwrap();
+        let a_modified_at = persistence.unwrap_current().feattles["a"].modified_at;
+
+        // Keep `a`, change `c` and reset `b`
+        let mut values = BTreeMap::new();
+        values.insert("a".to_owned(), json!(1i32));
+        values.insert("c".to_owned(), json!("new"));
+        config
+            .replace_all(values.clone(), "somebody else".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(*config.a(), 1);
+        assert_eq!(*config.b(), 17);
+        assert_eq!(*config.c(), "new");
+
+        let current = persistence.unwrap_current();
+        assert_eq!(current.version, 3);
+        assert_eq!(current.feattles.len(), 2);
+        assert_eq!(current.feattles["a"].modified_at, a_modified_at);
+        assert_eq!(current.feattles["a"].modified_by, "somebody");
+        assert_eq!(current.feattles["c"].modified_by, "somebody else");
+        assert_eq!(config.current_values().unwrap().version, 3);
+
+        assert_eq!(persistence.unwrap_history("a").entries.len(), 1);
+        let b_history = persistence.unwrap_history("b");
+        assert_eq!(b_history.entries.len(), 2);
+        assert_eq!(b_history.entries[1].value, json!(17i32));
+        assert_eq!(b_history.entries[1].modified_by, "somebody else");
+        let c_history = persistence.unwrap_history("c");
+        assert_eq!(c_history.entries.len(), 1);
+        assert_eq!(c_history.entries[0].value_overview, "new");
+
+        // Nothing is applied if any value is invalid
+        values.insert("a".to_owned(), json!(2i32));
+        values.insert("c".to_owned(), json!(3i32));
+        let err = config
+            .replace_all(values.clone(), "somebody else".to_owned())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UpdateError::Parsing(_)));
+        assert_eq!(*config.a(), 1);
+        assert_eq!(*config.c(), "new");
+        assert_eq!(persistence.unwrap_current().version, 3);
+
+        // Nor if the key is unknown
+        values.insert("c".to_owned(), json!("newer"));
+        values.insert("d".to_owned(), json!(4i32));
+        let err = config
+            .replace_all(values.clone(), "somebody else".to_owned())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UpdateError::UnknownKey(key) if key == "d"));
+
+        // Nor if the persistence fails
+        values.remove("d");
+        persistence.put_error();
+        let err = config
+            .replace_all(values, "somebody else".to_owned())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UpdateError::Persistence(_)));
+        assert_eq!(*config.a(), 1);
+        assert_eq!(*config.c(), "new");
+        assert_eq!(config.current_values().unwrap().version, 3);
+        assert_eq!(persistence.unwrap_current().version, 3);
+    }
+
+    #[tokio::test]
+    async fn groups() {
+        feattles! {
+            struct Config {
+                a: i32,
+                /// Database settings
+                database: DatabaseConfig {
+                    /// The pool size
+                    pool_size: i32 = 10,
+                    timeout: u64,
+                },
+                b: bool,
+            }
+        }
+
+        let persistence = Arc::new(MockPersistence::default());
+        let config = Config::new(persistence.clone());
+        assert_eq!(
+            config.keys(),
+            &["a", "b", "database.pool_size", "database.timeout"]
+        );
+        assert_eq!(*config.database().pool_size(), 10);
+        assert_eq!(*config.database().timeout(), 0);
+        assert!(!*config.b());
+        let definition = config.definition("database.pool_size").unwrap();
+        assert_eq!(definition.key, "database.pool_size");
+        assert_eq!(definition.description, "The pool size");
+        assert!(config.definition("pool_size").is_none());
+
+        config.reload().await.unwrap();
+        config
+            .update("database.pool_size", json!(20i32), "somebody".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(*config.database().pool_size(), 20);
+        assert_eq!(*config.a(), 0);
+        let values = persistence.unwrap_current();
+        assert_eq!(values.feattles["database.pool_size"].value, json!(20i32));
+        assert_eq!(
+            persistence
+                .unwrap_history("database.pool_size")
+                .entries
+                .len(),
+            1
+        );
+
+        // Load from a fresh instance
+        let config = Config::new(persistence.clone());
+        config.reload().await.unwrap();
+        assert_eq!(*config.database().pool_size(), 20);
+    }
+
+    #[tokio::test]
+    async fn derived() {
+        feattles! {
+            struct Config {
+                timeout: u32 = 30,
+                cap: u32 = 60,
+                /// The timeout, limited by the cap
+                #[derived = effective_timeout]
+                effective_timeout: u32,
+            }
+        }
+
+        fn effective_timeout(config: &Config) -> u32 {
+            (*config.timeout()).min(*config.cap())
+        }
+
+        let persistence = Arc::new(MockPersistence::default());
+        let config = Config::new(persistence.clone());
+        config.reload().await.unwrap();
+        assert_eq!(config.effective_timeout(), 30);
+
+        config
+            .update("cap", json!(10), "somebody".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(config.effective_timeout(), 10);
+        let definition = config.definition("effective_timeout").unwrap();
+        assert!(definition.read_only);
+        assert_eq!(definition.value, json!(10));
+        assert_eq!(definition.description, "The timeout, limited by the cap");
+        assert!(!config.definition("cap").unwrap().read_only);
+
+        let error = config
+            .update("effective_timeout", json!(5), "somebody".to_owned())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, UpdateError::ReadOnly(key) if key == "effective_timeout"));
+        let error = config
+            .replace_all(
+                BTreeMap::from([("effective_timeout".to_owned(), json!(5))]),
+                "somebody".to_owned(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(error, UpdateError::ReadOnly(_)));
+        assert_eq!(config.effective_timeout(), 10);
+        assert!(!persistence
+            .0
+            .lock()
+            .history
+            .contains_key("effective_timeout"));
+    }
+
+    #[tokio::test]
+    async fn seed_from_json_if_empty() {
+        feattles! {
+            struct Config {
+                a: i32,
+                b: bool,
+            }
+        }
+
+        let doc = r#"{"a": 17, "b": true}"#;
+
+        // Empty store: the document is applied and persisted
+        let persistence = Arc::new(MockPersistence::default());
+        let config = Config::new(persistence.clone());
+        assert!(config
+            .seed_from_json_if_empty(doc, "seed".to_owned())
+            .await
+            .unwrap());
+        assert_eq!(*config.a(), 17);
+        assert!(*config.b());
+        let values = persistence.unwrap_current();
+        assert_eq!(values.version, 1);
+        assert_eq!(values.feattles["a"].value, json!(17));
+        assert_eq!(values.feattles["a"].modified_by, "seed");
+
+        // Store with data: nothing is changed
+        config
+            .update("a", json!(3), "somebody".to_owned())
+            .await
+            .unwrap();
+        let config = Config::new(persistence.clone());
+        assert!(!config
+            .seed_from_json_if_empty(doc, "seed".to_owned())
+            .await
+            .unwrap());
+        assert_eq!(*config.a(), 3);
+        assert_eq!(persistence.unwrap_current().version, 2);
+
+        // Invalid documents are rejected
+        let config = Config::new(Arc::new(MockPersistence::default()));
+        let error = config
+            .seed_from_json_if_empty("[]", "seed".to_owned())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, SeedError::Parsing(_)));
+    }
+
+    #[tokio::test]
+    async fn revision() {
+        feattles! {
+            struct Config {
+                a: i32,
+            }
+        }
+
+        let persistence = Arc::new(InMemoryPersistence::new());
+        let config_1 = Config::new(persistence.clone());
+        let config_2 = Config::new(persistence.clone());
+        config_1.reload().await.unwrap();
+        config_1
+            .update("a", json!(1), "somebody".to_owned())
+            .await
+            .unwrap();
+        let stored = persistence.load_current().await.unwrap().unwrap();
+        assert_eq!(stored.revision.as_deref(), Some("1"));
+
+        // The new revision is kept in memory, so the instance can update again without reloading
+        assert_eq!(
+            config_1.current_values().unwrap().revision.as_deref(),
+            Some("1")
+        );
+        config_1
+            .update("a", json!(2), "somebody".to_owned())
+            .await
+            .unwrap();
+
+        // Both instances load the same revision, but only the first one to save wins
+        config_1.reload().await.unwrap();
+        config_2.reload().await.unwrap();
+        assert_eq!(
+            config_1.current_values().unwrap().revision.as_deref(),
+            Some("2")
+        );
+        config_2
+            .update("a", json!(4), "somebody".to_owned())
+            .await
+            .unwrap();
+        let error = config_1
+            .update("a", json!(3), "somebody".to_owned())
+            .await
+            .unwrap_err();
+        match error {
+            UpdateError::Persistence(error) => {
+                let conflict = error.downcast_ref::<RevisionConflict>().unwrap();
+                assert_eq!(conflict.expected.as_deref(), Some("2"));
+                assert_eq!(conflict.actual.as_deref(), Some("3"));
+            }
+            error => panic!("unexpected error {:?}", error),
+        }
+        assert_eq!(*config_1.a(), 2);
+
+        // After reloading, the update is accepted
+        config_1.reload().await.unwrap();
+        config_1
+            .update("a", json!(3), "somebody".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(
+            persistence.load_current().await.unwrap().unwrap().feattles["a"].value,
+            json!(3)
+        );
+    }
+
+    #[tokio::test]
+    async fn pending_updates() {
+        feattles! {
+            struct Config {
+                a: i32,
+                b: i32,
+            }
+        }
+
+        let persistence = Arc::new(InMemoryPersistence::new());
+        let config = Config::new(persistence.clone());
+        config.reload().await.unwrap();
+
+        // Propose -> approve
+        config
+            .propose_update("a", json!(17), "alice".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(*config.a(), 0);
+        let pending = config.pending_updates().await.unwrap();
+        assert_eq!(pending.changes["a"].value, json!(17));
+        assert_eq!(pending.changes["a"].proposed_by, "alice");
+
+        assert!(matches!(
+            config.approve_update("a", "alice".to_owned()).await,
+            Err(UpdateError::SelfApproval(_))
+        ));
+        config.approve_update("a", "bob".to_owned()).await.unwrap();
+        assert_eq!(*config.a(), 17);
+        assert!(config.pending_updates().await.unwrap().changes.is_empty());
+        let entry = config.history("a").await.unwrap().entries.pop().unwrap();
+        assert_eq!(entry.modified_by, "alice");
+        assert_eq!(entry.approved_by.as_deref(), Some("bob"));
+        assert!(matches!(
+            config.approve_update("a", "bob".to_owned()).await,
+            Err(UpdateError::NoPendingChange(_))
+        ));
+
+        // Propose -> reject
+        config
+            .propose_update("b", json!(3), "alice".to_owned())
+            .await
+            .unwrap();
+        config.reject_update("b", "bob".to_owned()).await.unwrap();
+        assert!(config.pending_updates().await.unwrap().changes.is_empty());
+        assert_eq!(*config.b(), 0);
+        assert!(config.history("b").await.unwrap().entries.is_empty());
+
+        // Invalid proposals are refused
+        assert!(matches!(
+            config
+                .propose_update("b", json!("3"), "alice".to_owned())
+                .await,
+            Err(UpdateError::Parsing(_))
+        ));
+        assert!(matches!(
+            config
+                .propose_update("c", json!(3), "alice".to_owned())
+                .await,
+            Err(UpdateError::UnknownKey(_))
+        ));
+
+        // Direct changes are refused when approval is required
+        config.set_require_approval(true);
+        assert!(matches!(
+            config.update("b", json!(3), "alice".to_owned()).await,
+            Err(UpdateError::ApprovalRequired(key)) if key == "b"
+        ));
+        for values in [
+            BTreeMap::new(),
+            BTreeMap::from([("b".to_owned(), json!(3))]),
+        ] {
+            assert!(matches!(
+                config.replace_all(values, "alice".to_owned()).await,
+                Err(UpdateError::BulkApprovalRequired)
+            ));
+        }
+        config
+            .propose_update("b", json!(3), "alice".to_owned())
+            .await
+            .unwrap();
+        config.approve_update("b", "bob".to_owned()).await.unwrap();
+        assert_eq!(*config.b(), 3);
+
+        // Backends without support for pending changes
+        let config = Config::new(Arc::new(MockPersistence::default()));
+        let error = config
+            .propose_update("a", json!(17), "alice".to_owned())
+            .await
+            .unwrap_err();
+        match error {
+            UpdateError::Persistence(error) => assert!(error.is::<PendingUnsupported>()),
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn update_hooks() {
+        feattles! {
+            struct Config {
+                a: i32,
+                b: i32,
+            }
+        }
+
+        #[derive(Default)]
+        struct Hook {
+            updated: Mutex<Vec<(String, Value)>>,
+        }
+
+        impl UpdateHook for Hook {
+            fn before_update(
+                &self,
+                key: &str,
+                proposed_value: &Value,
+                _modified_by: &str,
+            ) -> Result<(), RejectReason> {
+                if key == "a" && proposed_value.as_i64() > Some(10) {
+                    Err(RejectReason::new("a must be at most 10"))
+                } else {
+                    Ok(())
+                }
+            }
+
+            fn after_update(&self, key: &str, new_value: &Value, _modified_by: &str) {
+                self.updated
+                    .lock()
+                    .push((key.to_owned(), new_value.clone()));
+            }
+        }
+
+        let persistence = Arc::new(MockPersistence::default());
+        let config = Config::new(persistence.clone());
+        let hook = Arc::new(Hook::default());
+        config.add_update_hook(hook.clone());
+        config.reload().await.unwrap();
+
+        let error = config
+            .update("a", json!(11), "somebody".to_owned())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, UpdateError::Rejected(reason) if reason.message() == "a must be at most 10")
+        );
+        assert_eq!(*config.a(), 0);
+        assert!(!persistence.0.lock().history.contains_key("a"));
+
+        config
+            .update("a", json!(7), "somebody".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(*config.a(), 7);
+
+        // Vetoing one of the changes rejects all of them
+        let error = config
+            .replace_all(
+                BTreeMap::from([("a".to_owned(), json!(12)), ("b".to_owned(), json!(3))]),
+                "somebody".to_owned(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(error, UpdateError::Rejected(_)));
+        assert_eq!(*config.b(), 0);
+
+        assert_eq!(*hook.updated.lock(), vec![("a".to_owned(), json!(7))]);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "audit")]
+    async fn audit() {
+        use crate::audit::{AuditKey, SignatureStatus};
+
+        feattles! {
+            struct Config {
+                a: i32,
+            }
+        }
+
+        let persistence = Arc::new(MockPersistence::default());
+        let config = Config::new(persistence.clone());
+        config.reload().await.unwrap();
+        let key = AuditKey::new("secret");
+
+        // Entries from before the key was configured are accepted
+        config
+            .update("a", json!(1i32), "somebody".to_owned())
+            .await
+            .unwrap();
+        config.set_audit_key(Some(key.clone()));
+        for i in 2..5 {
+            config
+                .update("a", json!(i), "somebody".to_owned())
+                .await
+                .unwrap();
+        }
+        assert_eq!(*config.a(), 4);
+        let history = persistence.unwrap_history("a");
+        assert!(history.verify_chain(&key));
+        assert_eq!(
+            history.signature_statuses(&key),
+            vec![
+                SignatureStatus::Unsigned,
+                SignatureStatus::Valid,
+                SignatureStatus::Valid,
+                SignatureStatus::Valid
+            ]
+        );
+        assert!(!history.verify_chain(&AuditKey::new("other secret")));
+
+        // Tamper with the value
+        let mut tampered = history.clone();
+        tampered.entries[2].value = json!(17i32);
+        assert!(!tampered.verify_chain(&key));
+        assert_eq!(
+            tampered.signature_statuses(&key)[2..],
+            [SignatureStatus::Invalid, SignatureStatus::Invalid]
+        );
+
+        // Remove an entry
+        let mut tampered = history.clone();
+        tampered.entries.remove(2);
+        assert!(!tampered.verify_chain(&key));
+
+        // Strip a signature
+        let mut tampered = history.clone();
+        tampered.entries[3].signature = None;
+        assert!(!tampered.verify_chain(&key));
+
+        // Edit the first, unsigned, entry
+        let mut tampered = history;
+        tampered.entries[0].modified_by.push_str(" else");
+        assert!(!tampered.verify_chain(&key));
+    }
+
+    #[tokio::test]
+    async fn monotonic_history() {
+        feattles! {
+            struct Config {
+                a: i32,
+            }
+        }
+
+        // Another instance, with a clock one hour ahead, has already modified the feattle
+        let persistence = Arc::new(MockPersistence::default());
+        let future = Utc::now() + chrono::Duration::hours(1);
+        persistence.0.lock().history.insert(
+            "a".to_owned(),
+            ValueHistory {
+                entries: vec![HistoryEntry {
+                    value: json!(1i32),
+                    value_overview: "1".to_owned(),
+                    modified_at: future,
+                    modified_by: "somebody".to_owned(),
+                    approved_by: None,
+                    touch: false,
+                    unset: false,
+                    signature: None,
+                }],
+            },
+        );
+
+        let config = Config::new(persistence.clone());
+        config.reload().await.unwrap();
+
+        // Disabled by default
+        config
+            .update("a", json!(2i32), "somebody else".to_owned())
+            .await
+            .unwrap();
+        let history = persistence.unwrap_history("a");
+        assert!(history.entries[1].modified_at < future);
+
+        config.set_monotonic_history(true);
+        config
+            .update("a", json!(3i32), "somebody else".to_owned())
+            .await
+            .unwrap();
+        let expected = future + chrono::Duration::microseconds(1);
+        let history = persistence.unwrap_history("a");
+        assert_eq!(history.entries[2].modified_at, expected);
+        let values = persistence.unwrap_current();
+        assert_eq!(values.feattles.get("a").unwrap().modified_at, expected);
+        assert_eq!(config.definition("a").unwrap().modified_at, Some(expected));
+        assert_eq!(*config.a(), 3);
+    }
+
+    #[test]
+    #[allow(dead_code)]
+    fn tags() {
+        feattles! {
+            struct Config {
+                #[tags("billing", "experimental", "eu-only")]
+                a: bool,
+                b: bool,
+                group: Group {
+                    #[tags("billing")]
+                    c: bool,
+                }
+            }
+        }
+
+        let config = Config::new(Arc::new(NoPersistence));
+        assert_eq!(
+            config.definition("a").unwrap().tags,
+            ["billing", "experimental", "eu-only"]
+        );
+        assert!(config.definition("b").unwrap().tags.is_empty());
+        assert_eq!(config.definition("group.c").unwrap().tags, ["billing"]);
+    }
+
+    #[tokio::test]
+    #[allow(dead_code)]
+    async fn is_enabled_ctx() {
+        feattles! {
+            struct Config {
+                a: Rollout,
+                b: bool,
+                c: i32,
+            }
+        }
+
+        let config = Config::new(Arc::new(MockPersistence::default()));
+        config.reload().await.unwrap();
+        config
+            .update("a", json!(50), "somebody".to_owned())
+            .await
+            .unwrap();
+
+        let rollout = Rollout::new(50.0).unwrap();
+        let enabled_user = (0..)
+            .map(|i| format!("user-{}", i))
+            .find(|user| rollout.is_enabled_for(user))
+            .unwrap();
+        let disabled_user = (0..)
+            .map(|i| format!("user-{}", i))
+            .find(|user| !rollout.is_enabled_for(user))
+            .unwrap();
+        let enabled = FeattleContext::new(Some(enabled_user));
+        let disabled = FeattleContext::new(Some(disabled_user));
+
+        assert!(config.is_enabled_ctx("a", &enabled));
+        assert!(!config.is_enabled_ctx("a", &disabled));
+        assert!(!config.is_enabled_ctx("b", &enabled));
+        assert!(!config.is_enabled_ctx("c", &enabled));
+        assert!(!config.is_enabled_ctx("unknown", &enabled));
+
+        #[cfg(feature = "tokio")]
+        {
+            let read = || config.is_enabled_ctx("a", &FeattleContext::current().unwrap());
+            assert!(enabled.clone().scope(async { read() }).await);
+            assert!(!disabled.clone().scope(async { read() }).await);
+            assert_eq!(FeattleContext::current(), None);
+        }
+    }
+
+    #[test]
+    #[allow(dead_code)]
+    fn interpolated() {
+        feattles! {
+            struct Config {
+                base: String = "https://example.com".to_owned(),
+                api: String = "${base}/api".to_owned(),
+                users: String = "${api}/users?limit=${limit}&${unknown}${".to_owned(),
+                limit: i32 = 10,
+                a: String = "a-${b}".to_owned(),
+                b: String = "b-${a}".to_owned(),
+                c: String = "c-${c}".to_owned(),
+                d: String = "d-${a}".to_owned(),
+            }
+        }
+
+        let config = Config::new(Arc::new(NoPersistence));
+
+        assert_eq!(config.interpolated("base").unwrap(), "https://example.com");
+        assert_eq!(
+            config.interpolated("api").unwrap(),
+            "https://example.com/api"
+        );
+        // Non-string and unknown references are kept
+        assert_eq!(
+            config.interpolated("users").unwrap(),
+            "https://example.com/api/users?limit=${limit}&${unknown}${"
+        );
+        assert_eq!(config.interpolated("limit"), None);
+        assert_eq!(config.interpolated("unknown"), None);
+
+        // Cycles return the raw value
+        assert_eq!(config.interpolated("a").unwrap(), "a-${b}");
+        assert_eq!(config.interpolated("b").unwrap(), "b-${a}");
+        assert_eq!(config.interpolated("c").unwrap(), "c-${c}");
+        assert_eq!(config.interpolated("d").unwrap(), "d-${a}");
+    }
+
+    #[tokio::test]
+    #[allow(dead_code)]
+    async fn key_fingerprint() {
+        feattles! {
+            struct Config {
+                a: i32,
+                b: Vec<String>,
+            }
+        }
+
+        let config = Config::new(Arc::new(MockPersistence::default()));
+        config.reload().await.unwrap();
+        let a = config.key_fingerprint("a").unwrap();
+        let b = config.key_fingerprint("b").unwrap();
+        assert_eq!(config.key_fingerprint("a"), Some(a));
+        assert_eq!(config.key_fingerprint("c"), None);
+
+        // Only the fingerprint of the changed key changes
+        config
+            .update("a", json!(17), "somebody".to_owned())
+            .await
+            .unwrap();
+        let new_a = config.key_fingerprint("a").unwrap();
+        assert_ne!(new_a, a);
+        assert_eq!(config.key_fingerprint("b"), Some(b));
+
+        // Same value, same fingerprint
+        config.reload().await.unwrap();
+        assert_eq!(config.key_fingerprint("a"), Some(new_a));
+        config
+            .update("a", json!(0), "somebody".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(config.key_fingerprint("a"), Some(a));
+
+        // Known value, so that the hash is stable across versions
+        assert_eq!(fingerprint(&json!(17)), 0x07f8_9107_b4ba_06f1);
+    }
+
+    #[tokio::test]
+    async fn touch() {
+        feattles! {
+            struct Config {
+                a: i32,
+                b: i32 = 17,
+            }
+        }
+
+        let persistence = Arc::new(MockPersistence::default());
+        let config = Config::new(persistence.clone());
+        config.reload().await.unwrap();
+        config
+            .update("a", json!(27i32), "somebody".to_owned())
+            .await
+            .unwrap();
+        let version = persistence.unwrap_current().version;
+
+        config.touch("a", "operator".to_owned()).await.unwrap();
+        assert_eq!(*config.a(), 27);
+        let current = persistence.unwrap_current();
+        assert_eq!(current.version, version + 1);
+        assert_eq!(current.feattles["a"].value, json!(27i32));
+        let history = persistence.unwrap_history("a");
+        assert_eq!(history.entries.len(), 2);
+        assert!(!history.entries[0].touch);
+        assert!(history.entries[1].touch);
+        assert_eq!(history.entries[1].value, json!(27i32));
+        assert_eq!(history.entries[1].modified_by, "operator");
+
+        // A feattle that was never modified is persisted with its current value
+        config.touch("b", "operator".to_owned()).await.unwrap();
+        assert_eq!(*config.b(), 17);
+        let current = persistence.unwrap_current();
+        assert_eq!(current.version, version + 2);
+        assert_eq!(current.feattles["b"].value, json!(17i32));
+        assert!(persistence.unwrap_history("b").entries[0].touch);
+
+        assert!(matches!(
+            config.touch("c", "operator".to_owned()).await,
+            Err(UpdateError::UnknownKey(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn staging() {
+        feattles! {
+            struct Config {
+                a: i32,
+                b: i32,
+            }
+        }
+
+        let persistence = Arc::new(MockPersistence::default());
+        let live = Config::new(persistence.clone());
+        let staging = Config::new(persistence.clone());
+        staging.set_staging_mode(true);
+        live.reload().await.unwrap();
+        live.update("a", json!(1i32), "somebody".to_owned())
+            .await
+            .unwrap();
+
+        // Both instances read the live value until a staging value is set
+        live.update_staging("a", Some(json!(2i32)), "somebody".to_owned())
+            .await
+            .unwrap();
+        live.update_staging("b", Some(json!(3i32)), "somebody".to_owned())
+            .await
+            .unwrap();
+        staging.reload().await.unwrap();
+        assert_eq!((*live.a(), *live.b()), (1, 0));
+        assert_eq!((*staging.a(), *staging.b()), (2, 3));
+        assert_eq!(live.definition("a").unwrap().staging, Some(json!(2i32)));
+        assert!(matches!(
+            live.update_staging("a", Some(json!("2")), "somebody".to_owned())
+                .await,
+            Err(UpdateError::Parsing(_))
+        ));
+
+        // A live update keeps the staging value
+        live.update("a", json!(4i32), "somebody".to_owned())
+            .await
+            .unwrap();
+        staging.reload().await.unwrap();
+        assert_eq!((*live.a(), *staging.a()), (4, 2));
+
+        // Promotion copies the staging value to the live one
+        live.promote_staging("a", "somebody else".to_owned())
+            .await
+            .unwrap();
+        staging.reload().await.unwrap();
+        assert_eq!((*live.a(), *staging.a()), (2, 2));
+        assert_eq!(live.definition("a").unwrap().staging, None);
+        let history = live.history("a").await.unwrap();
+        assert_eq!(history.entries.last().unwrap().value, json!(2i32));
+        assert_eq!(history.entries.last().unwrap().modified_by, "somebody else");
+        assert!(matches!(
+            live.promote_staging("a", "somebody".to_owned()).await,
+            Err(UpdateError::NoStagingValue(_))
+        ));
+
+        // Removing the staging value and leaving staging mode
+        live.update_staging("b", None, "somebody".to_owned())
+            .await
+            .unwrap();
+        staging.reload().await.unwrap();
+        assert_eq!(*staging.b(), 0);
+        live.update_staging("b", Some(json!(5i32)), "somebody".to_owned())
+            .await
+            .unwrap();
+        staging.reload().await.unwrap();
+        assert_eq!(*staging.b(), 5);
+        assert!(staging.persisted_vs_memory_divergence().is_empty());
+        staging.set_staging_mode(false);
+        assert_eq!(*staging.b(), 0);
+    }
+
+    #[tokio::test]
+    async fn persisted_vs_memory_divergence() {
+        feattles! {
+            struct Config {
+                a: i32,
+                b: i32,
+                #[derived = double_a]
+                c: i32,
+                d: f64,
+                e: BTreeSet<i32>,
+            }
+        }
+
+        fn double_a(config: &Config) -> i32 {
+            2 * *config.a()
+        }
+
+        let persistence = Arc::new(MockPersistence::default());
+        let config = Config::new(persistence.clone());
+        assert!(config.persisted_vs_memory_divergence().is_empty());
+
+        config.reload().await.unwrap();
+        config
+            .update("a", json!(27i32), "somebody".to_owned())
+            .await
+            .unwrap();
+        assert!(config.persisted_vs_memory_divergence().is_empty());
+
+        // Equivalent representations do not diverge
+        config
+            .update("d", json!(1), "somebody".to_owned())
+            .await
+            .unwrap();
+        config
+            .update("e", json!([3, 1, 2]), "somebody".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(*config.d(), 1.0);
+        assert_eq!(config.e().len(), 3);
+        assert!(config.persisted_vs_memory_divergence().is_empty());
+
+        // A value that can not be parsed keeps the previous one in memory
+        persistence
+            .0
+            .lock()
+            .current
+            .as_mut()
+            .unwrap()
+            .feattles
+            .get_mut("a")
+            .unwrap()
+            .value = json!("not a number");
+        config.reload().await.unwrap();
+        assert_eq!(*config.a(), 27);
+        assert_eq!(*config.b(), 0);
+        assert_eq!(config.c(), 54);
+        assert_eq!(config.persisted_vs_memory_divergence(), ["a"]);
+
+        config
+            .update("a", json!(17i32), "somebody".to_owned())
+            .await
+            .unwrap();
+        assert!(config.persisted_vs_memory_divergence().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reload_policy() {
+        feattles! {
+            struct Config {
+                a: i32,
+                b: i32,
+            }
+        }
+
+        async fn reload_with_bad_key(policy: ReloadPolicy) -> (Config, Result<(), BoxError>) {
+            let persistence = Arc::new(MockPersistence::default());
+            let config = Config::new(persistence.clone());
+            config.set_reload_policy(policy);
+            config.reload().await.unwrap();
+            config
+                .update("a", json!(27i32), "somebody".to_owned())
+                .await
+                .unwrap();
+
+            {
+                let mut inner = persistence.0.lock();
+                let current = inner.current.as_mut().unwrap();
+                current.feattles.get_mut("a").unwrap().value = json!("not a number");
+                let mut b = current.feattles["a"].clone();
+                b.value = json!(17i32);
+                current.feattles.insert("b".to_owned(), b);
+            }
+            let result = config.reload().await;
+            (config, result)
+        }
+
+        let (config, result) = reload_with_bad_key(ReloadPolicy::UseLastGood).await;
+        result.unwrap();
+        assert_eq!((*config.a(), *config.b()), (27, 17));
+
+        let (config, result) = reload_with_bad_key(ReloadPolicy::SkipBadKeys).await;
+        result.unwrap();
+        assert_eq!((*config.a(), *config.b()), (0, 17));
+        assert_eq!(config.definition("a").unwrap().modified_by, None);
+
+        let (config, result) = reload_with_bad_key(ReloadPolicy::FailReload).await;
+        let error = result.unwrap_err().downcast::<ReloadError>().unwrap();
+        assert_eq!(error.key, "a");
+        assert_eq!((*config.a(), *config.b()), (27, 0));
+        assert_eq!(config.current_values().unwrap().version, 1);
+    }
+
+    #[tokio::test]
+    async fn value_and_source() {
+        feattles! {
+            struct Config {
+                a: i32,
+                b: i32,
+                #[derived = double_a]
+                c: i32,
+            }
+        }
+
+        fn double_a(config: &Config) -> i32 {
+            2 * *config.a()
+        }
+
+        let config = Config::new(Arc::new(MockPersistence::default()));
+        config.reload().await.unwrap();
+        assert_eq!(*config.b(), 0);
+        assert_eq!(
+            config.value_and_source("a").unwrap(),
+            (json!(0), ValueSource::Default, None)
+        );
+
+        config
+            .update("a", json!(17i32), "somebody".to_owned())
+            .await
+            .unwrap();
+        let (value, source, modified_at) = config.value_and_source("a").unwrap();
+        let definition = config.definition("a").unwrap();
+        assert_eq!(value, definition.value);
+        assert_eq!(value, json!(17));
+        assert_eq!(source, ValueSource::Persisted);
+        assert_eq!(modified_at, definition.modified_at);
+        assert!(modified_at.is_some());
+        assert_eq!(
+            config.value_and_source("c").unwrap(),
+            (json!(34), ValueSource::Derived, None)
+        );
+
+        config
+            .update_staging("a", Some(json!(23i32)), "somebody".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(config.value_and_source("a").unwrap().0, json!(17));
+        config.set_staging_mode(true);
+        assert_eq!(
+            config.value_and_source("a").unwrap(),
+            (json!(23), ValueSource::Staging, modified_at)
+        );
+        assert_eq!(config.c(), 46);
+        assert_eq!(config.value_and_source("unknown"), None);
+    }
+
+    #[test]
+    #[allow(dead_code)]
+    fn definitions_matching() {
+        feattles! {
+            struct Config {
+                a: bool,
+                b: i32,
+                c: Option<bool>,
+                d: bool,
+            }
+        }
+
+        let config = Config::new(Arc::new(NoPersistence));
+        let bools: Vec<_> = config
+            .definitions_matching(|definition| definition.format.kind == SerializedFormatKind::Bool)
+            .into_iter()
+            .map(|definition| definition.key)
+            .collect();
+        assert_eq!(bools, vec!["a", "d"]);
+        assert!(config.definitions_matching(|_| false).is_empty());
+    }
+
+    #[test]
+    fn expired_feattles() {
+        feattles! {
+            struct Config {
+                /// A temporary flag
+                #[expires = "2000-01-01"]
+                a: bool,
+                #[expires = "9999-12-31"]
+                b: bool,
+                c: bool,
+            }
+        }
+
+        let config = Config::new(Arc::new(NoPersistence));
+        // Expiry is only metadata, the values are still readable
+        assert!(!*config.a() && !*config.b() && !*config.c());
+        let expired: Vec<_> = config
+            .expired_feattles()
+            .into_iter()
+            .map(|definition| definition.key)
+            .collect();
+        assert_eq!(expired, vec!["a"]);
+
+        let a = config.definition("a").unwrap();
+        assert_eq!(a.description, "A temporary flag");
+        assert_eq!(a.expires, Some("2000-01-01".parse().unwrap()));
+        assert_eq!(config.definition("b").unwrap().expiry_status, None);
+        assert_eq!(config.definition("c").unwrap().expires, None);
+
+        let expires = "2024-12-31".parse().unwrap();
+        let status = |today: &str| ExpiryStatus::new(expires, today.parse().unwrap());
+        assert_eq!(status("2024-01-01"), None);
+        assert_eq!(status("2024-12-20"), Some(ExpiryStatus::ExpiresSoon));
+        assert_eq!(status("2024-12-31"), Some(ExpiryStatus::ExpiresSoon));
+        assert_eq!(status("2025-01-01"), Some(ExpiryStatus::Expired));
+    }
+
+    #[test]
+    #[allow(dead_code)]
+    #[should_panic(expected = "invalid example \"[1, 2.5]\" for feattle a")]
+    fn invalid_example() {
+        feattles! {
+            struct Config {
+                #[example = "[3]"]
+                #[example = "[1, 2.5]"]
+                a: Vec<i32>,
+            }
+        }
+
+        Config::new(Arc::new(NoPersistence));
+    }
 }
@@ -2,7 +2,8 @@
 ///
 /// The generated `enum` will have these standard traits: `Debug`, `Clone`, `Copy`, `Eq`,
 /// `PartialEq`, `PartialOrd`, `Ord`, `FromStr`, `Display`. And mainly, it will implement
-/// [`crate::FeattleStringValue`] so that it can be used a feattle type.
+/// [`crate::FeattleStringValue`] so that it can be used a feattle type. It also implements
+/// [`crate::FeattleEnum`], so that it can be used in an [`crate::EnumSet`].
 ///
 /// Only `enum`s whose variants do not carry any extra information are supported.
 ///
@@ -78,7 +79,7 @@ macro_rules! feattle_enum {
             }
         }
 
-        impl $name {
+        impl $crate::FeattleEnum for $name {
             const VARIANTS: &'static [&'static str] = &[
                 $(
                     stringify!($variant)
@@ -88,10 +89,12 @@ macro_rules! feattle_enum {
 
         impl $crate::FeattleStringValue for $name {
             fn serialized_string_format() -> $crate::StringFormat {
-                let variants = Self::VARIANTS.join(", ");
+                let variants = <Self as $crate::FeattleEnum>::VARIANTS;
                 $crate::StringFormat {
-                    kind: $crate::StringFormatKind::Choices(&Self::VARIANTS),
-                    tag: format!("enum {{{}}}", variants),
+                    kind: $crate::StringFormatKind::Choices(
+                        variants.iter().map(|&variant| variant.to_owned()).collect(),
+                    ),
+                    tag: format!("enum {{{}}}", variants.join(", ")),
                 }
             }
         }
@@ -109,6 +112,166 @@ macro_rules! __init_field {
     };
 }
 
+/// Concatenate all the `#[doc = "..."]` attributes of a feattle, ignoring the other ones
+#[macro_export]
+#[doc(hidden)]
+macro_rules! __feattle_description {
+    ([$($acc:tt)*]) => {
+        concat!($($acc,)* "")
+    };
+    ([$($acc:tt)*] [doc = $doc:tt] $($rest:tt)*) => {
+        $crate::__feattle_description!([$($acc)* $doc] $($rest)*)
+    };
+    ([$($acc:tt)*] [$($other:tt)*] $($rest:tt)*) => {
+        $crate::__feattle_description!([$($acc)*] $($rest)*)
+    };
+}
+
+/// Apply the attributes of a feattle (other than the doc comments) to its
+/// `__internal::Feattle` instance
+#[macro_export]
+#[doc(hidden)]
+macro_rules! __feattle_attrs {
+    ($feattle:expr; [group]) => {
+        $feattle
+    };
+    ($feattle:expr; [group] [derived = $($derived:tt)*] $($rest:tt)*) => {
+        ::std::compile_error!("derived feattles are not supported inside groups")
+    };
+    ($feattle:expr; [group] [copy] $($rest:tt)*) => {
+        ::std::compile_error!("#[copy] is not supported inside groups")
+    };
+    ($feattle:expr; [group] $attr:tt $($rest:tt)*) => {
+        $crate::__feattle_attrs!($crate::__feattle_attrs!($feattle; $attr); [group] $($rest)*)
+    };
+    ($feattle:expr;) => {
+        $feattle
+    };
+    ($feattle:expr; [doc = $($doc:tt)*] $($rest:tt)*) => {
+        $crate::__feattle_attrs!($feattle; $($rest)*)
+    };
+    ($feattle:expr; [expires = $date:literal] $($rest:tt)*) => {
+        $crate::__feattle_attrs!({
+            const _: () = $crate::__internal::check_expiry_date($date);
+            $feattle.expires($date)
+        }; $($rest)*)
+    };
+    ($feattle:expr; [derived = $derived:path] $($rest:tt)*) => {
+        $crate::__feattle_attrs!($feattle.derived(); $($rest)*)
+    };
+    ($feattle:expr; [copy] $($rest:tt)*) => {
+        $crate::__feattle_attrs!($feattle; $($rest)*)
+    };
+    ($feattle:expr; [tags($($tag:literal),* $(,)?)] $($rest:tt)*) => {
+        $crate::__feattle_attrs!($feattle.tags(&[$($tag),*]); $($rest)*)
+    };
+    ($feattle:expr; [example = $example:literal] $($rest:tt)*) => {
+        $crate::__feattle_attrs!($feattle.example($example); $($rest)*)
+    };
+    ($feattle:expr; [$($other:tt)*] $($rest:tt)*) => {
+        ::std::compile_error!(::std::concat!(
+            "unsupported feattle attribute: #[",
+            ::std::stringify!($($other)*),
+            "]"
+        ))
+    };
+}
+
+/// Generate the getter of a feattle, that computes its value when declared with
+/// `#[derived = path]` and copies it when declared with `#[copy]`. In the latter case, the getter
+/// that returns the read guard is kept with the suffix `_ref`.
+#[macro_export]
+#[doc(hidden)]
+macro_rules! __feattle_getter {
+    ($key:ident: $type:ty;) => {
+        $crate::__feattle_ref_getter!($key -> $type = |feattles| feattles.$key.value());
+    };
+    (@copy $key:ident: $type:ty;) => {
+        $crate::__feattle_ref_getter!(@copy $key -> $type = |feattles| feattles.$key.value());
+        $crate::__internal::paste! {
+            $crate::__feattle_ref_getter!(
+                #[doc = "Return the read guard of the value, instead of a copy"]
+                #[allow(dead_code)]
+                [<$key _ref>] -> $type = |feattles| feattles.$key.value()
+            );
+        }
+    };
+    ($(@$copy:ident)? $key:ident: $type:ty; [derived = $derived:path] $($rest:tt)*) => {
+        pub fn $key(&self) -> $type {
+            $derived(self)
+        }
+    };
+    ($(@$copy:ident)? $key:ident: $type:ty; [copy] $($rest:tt)*) => {
+        $crate::__feattle_getter!(@copy $key: $type; $($rest)*);
+    };
+    ($(@$copy:ident)? $key:ident: $type:ty; $attr:tt $($rest:tt)*) => {
+        $crate::__feattle_getter!($(@$copy)? $key: $type; $($rest)*);
+    };
+}
+
+/// Generate a getter that reads a value of the feattles struct, given as an expression of
+/// `$feattles`, behind the read lock
+#[cfg(not(feature = "arc-swap"))]
+#[macro_export]
+#[doc(hidden)]
+macro_rules! __feattle_ref_getter {
+    ($(#[$attr:meta])* $name:ident -> $type:ty = |$feattles:ident| $value:expr) => {
+        $(#[$attr])*
+        pub fn $name(&self) -> __internal::MappedRwLockReadGuard<$type> {
+            __internal::RwLockReadGuard::map(self.0.inner_feattles.read(), |inner| {
+                let $feattles = &inner.feattles_struct;
+                $value
+            })
+        }
+    };
+    (@copy $name:ident -> $type:ty = |$feattles:ident| $value:expr) => {
+        pub fn $name(&self) -> $type {
+            let $feattles = &self.0.inner_feattles.read().feattles_struct;
+            *$value
+        }
+    };
+}
+
+/// Generate a getter that reads a value of the feattles struct, given as an expression of
+/// `$feattles`, from the lock-free snapshot
+#[cfg(feature = "arc-swap")]
+#[macro_export]
+#[doc(hidden)]
+macro_rules! __feattle_ref_getter {
+    ($(#[$attr:meta])* $name:ident -> $type:ty = |$feattles:ident| $value:expr) => {
+        $(#[$attr])*
+        pub fn $name(
+            &self,
+        ) -> __internal::SnapshotGuard<<Self as __internal::FeattlesPrivate>::FeattleStruct, $type>
+        {
+            __internal::SnapshotGuard::new(self.0.snapshot.load(), |$feattles| $value)
+        }
+    };
+    (@copy $name:ident -> $type:ty = |$feattles:ident| $value:expr) => {
+        pub fn $name(&self) -> $type {
+            let snapshot = self.0.snapshot.load();
+            let $feattles = &**snapshot;
+            *$value
+        }
+    };
+}
+
+/// Compute the value of a feattle declared with `#[derived = path]`, returning `None` for the
+/// other ones
+#[macro_export]
+#[doc(hidden)]
+macro_rules! __feattle_derived_value {
+    ($feattles:expr;) => {
+        None
+    };
+    ($feattles:expr; [derived = $derived:path] $($rest:tt)*) => {
+        Some($derived($feattles))
+    };
+    ($feattles:expr; $attr:tt $($rest:tt)*) => {
+        $crate::__feattle_derived_value!($feattles; $($rest)*)
+    };
+}
+
 /// The main macro of this crate, used to generate a struct that will provide the Feattles
 /// functionalities.
 ///
@@ -119,14 +282,102 @@ macro_rules! feattles {
     $(#[$meta:meta])*
     $visibility:vis struct $name:ident {
         $(
-            $(#[doc=$description:tt])*
+            $(#[$($attr:tt)+])*
             $key:ident: $type:ty $(= $default:expr)?
         ),*
         $(,)?
     }
+) => {
+        $crate::__feattles_impl! {
+            $(#[$meta])*
+            $visibility struct $name;
+            [$([$(#[$($attr)+])*] $key: $type $(= $default)?;)*]
+            []
+        }
+    };
+    (
+    $(#[$meta:meta])*
+    $visibility:vis struct $name:ident {
+        $($fields:tt)*
+    }
+) => {
+        $crate::__feattles_fields! {
+            [$(#[$meta])* $visibility struct $name]
+            []
+            []
+            $($fields)*
+        }
+    };
+}
+
+/// Split the fields declared in [`feattles!`] into plain feattles and groups of feattles
+#[macro_export]
+#[doc(hidden)]
+macro_rules! __feattles_fields {
+    ([$($header:tt)*] [$($fields:tt)*] [$($groups:tt)*]) => {
+        $crate::__feattles_impl! {
+            $($header)*;
+            [$($fields)*]
+            [$($groups)*]
+        }
+    };
+    (
+        [$($header:tt)*] [$($fields:tt)*] [$($groups:tt)*]
+        $(#[doc = $doc:expr])*
+        $group:ident: $group_type:ident { $($group_fields:tt)* }
+        $(, $($rest:tt)*)?
+    ) => {
+        $crate::__feattles_fields! {
+            [$($header)*]
+            [$($fields)*]
+            [$($groups)* [$(#[doc = $doc])*] $group: $group_type { $($group_fields)* };]
+            $($($rest)*)?
+        }
+    };
+    (
+        [$($header:tt)*] [$($fields:tt)*] [$($groups:tt)*]
+        $(#[$($attr:tt)+])*
+        $key:ident: $type:ty $(= $default:expr)?
+        $(, $($rest:tt)*)?
+    ) => {
+        $crate::__feattles_fields! {
+            [$($header)*]
+            [$($fields)* [$(#[$($attr)+])*] $key: $type $(= $default)?;]
+            [$($groups)*]
+            $($($rest)*)?
+        }
+    };
+}
+
+/// Generate the struct declared in [`feattles!`], once its fields were normalized
+#[macro_export]
+#[doc(hidden)]
+macro_rules! __feattles_impl {
+    (
+    $(#[$meta:meta])*
+    $visibility:vis struct $name:ident;
+    [$(
+        [$(#[$($attr:tt)+])*]
+        $key:ident: $type:ty $(= $default:expr)?;
+    )*]
+    [$(
+        [$(#[doc = $group_doc:expr])*]
+        $group:ident: $group_type:ident {
+            $(
+                $(#[$($group_attr:tt)+])*
+                $group_key:ident: $group_field_type:ty $(= $group_default:expr)?
+            ),*
+            $(,)?
+        };
+    )*]
 ) => {
         use $crate::__internal;
 
+        const _: () = __internal::check_unique_keys(&[
+            $(stringify!($key),)*
+            $($(concat!(stringify!($group), ".", stringify!($group_key)),)*)*
+        ]);
+
         $(#[$meta])*
         #[derive(Debug)]
         $visibility struct $name(__internal::FeattlesImpl<__Feattles>);
@@ -141,11 +392,8 @@ macro_rules! feattles {
                 self.0.inner_feattles.read()
             }
 
-            fn _write(
-                &self,
-            ) -> __internal::RwLockWriteGuard<'_, __internal::InnerFeattles<Self::FeattleStruct>>
-            {
-                self.0.inner_feattles.write()
+            fn _write(&self) -> __internal::WriteGuard<'_, Self::FeattleStruct> {
+                self.0.write()
             }
         }
 
@@ -155,12 +403,31 @@ macro_rules! feattles {
                     persistence,
                     __Feattles {
                         $(
-                            $key: __internal::Feattle::new(
-                                stringify!($key),
-                                concat!($($description),*).trim(),
-                                $crate::__init_field!($($default)?),
-                            )
-                        ),*
+                            $key: $crate::__feattle_attrs!(
+                                __internal::Feattle::new(
+                                    stringify!($key),
+                                    $crate::__feattle_description!([] $([$($attr)+])*).trim(),
+                                    $crate::__init_field!($($default)?),
+                                );
+                                $([$($attr)+])*
+                            ),
+                        )*
+                        $(
+                            $group: $group_type {
+                                $(
+                                    $group_key: $crate::__feattle_attrs!(
+                                        __internal::Feattle::new(
+                                            concat!(stringify!($group), ".", stringify!($group_key)),
+                                            $crate::__feattle_description!(
+                                                [] $([$($group_attr)+])*
+                                            ).trim(),
+                                            $crate::__init_field!($($group_default)?),
+                                        );
+                                        [group] $([$($group_attr)+])*
+                                    ),
+                                )*
+                            },
+                        )*
                     },
                 ))
             }
@@ -170,14 +437,78 @@ macro_rules! feattles {
             }
 
             fn keys(&self) -> &'static [&'static str] {
-                &[$(stringify!($key)),*]
+                &[
+                    $(stringify!($key),)*
+                    $($(concat!(stringify!($group), ".", stringify!($group_key)),)*)*
+                ]
             }
 
             fn definition(&self, key: &str) -> Option<__internal::FeattleDefinition> {
                 use __internal::FeattlesPrivate;
-                let inner = self._read();
                 match key {
-                    $(stringify!($key) => Some(inner.feattles_struct.$key.definition()),)*
+                    $(stringify!($key) => {
+                        // Derived values must be computed before taking the lock
+                        let derived: Option<$type> = $crate::__feattle_derived_value!(self; $([$($attr)+])*);
+                        let feattle = &self._read().feattles_struct.$key;
+                        Some(match &derived {
+                            None => feattle.definition(),
+                            Some(value) => feattle.definition_with(value),
+                        })
+                    })*
+                    $($(
+                        concat!(stringify!($group), ".", stringify!($group_key)) => {
+                            Some(self._read().feattles_struct.$group.$group_key.definition())
+                        }
+                    )*)*
+                    _ => None,
+                }
+            }
+
+            fn value_and_source(
+                &self,
+                key: &str,
+            ) -> Option<(
+                __internal::Value,
+                __internal::ValueSource,
+                Option<__internal::DateTime<__internal::Utc>>,
+            )> {
+                use __internal::FeattlesPrivate;
+                match key {
+                    $(stringify!($key) => {
+                        // Derived values must be computed before taking the lock
+                        let derived: Option<$type> = $crate::__feattle_derived_value!(self; $([$($attr)+])*);
+                        let inner = self._read();
+                        Some(inner.feattles_struct.$key.value_and_source(derived.as_ref(), inner.staging_mode))
+                    })*
+                    $($(
+                        concat!(stringify!($group), ".", stringify!($group_key)) => {
+                            let inner = self._read();
+                            Some(inner.feattles_struct.$group.$group_key.value_and_source(None, inner.staging_mode))
+                        }
+                    )*)*
+                    _ => None,
+                }
+            }
+
+            fn evaluate(
+                &self,
+                key: &str,
+                context: &__internal::EvaluationContext,
+            ) -> Option<__internal::Value> {
+                use __internal::FeattlesPrivate;
+                match key {
+                    $(stringify!($key) => {
+                        let derived: Option<$type> = $crate::__feattle_derived_value!(self; $([$($attr)+])*);
+                        Some(match &derived {
+                            None => self._read().feattles_struct.$key.evaluate(context),
+                            Some(value) => __internal::FeattleValue::evaluate(value, context),
+                        })
+                    })*
+                    $($(
+                        concat!(stringify!($group), ".", stringify!($group_key)) => {
+                            Some(self._read().feattles_struct.$group.$group_key.evaluate(context))
+                        }
+                    )*)*
                     _ => None,
                 }
             }
@@ -185,17 +516,36 @@ macro_rules! feattles {
 
         impl $name {
             $(
-                pub fn $key(&self) -> __internal::MappedRwLockReadGuard<$type> {
-                    __internal::RwLockReadGuard::map(self.0.inner_feattles.read(), |inner| {
-                        inner.feattles_struct.$key.value()
-                    })
-                }
+                $crate::__feattle_getter!($key: $type; $([$($attr)+])*);
+            )*
+            $(
+                $crate::__feattle_ref_getter!(
+                    $(#[doc = $group_doc])*
+                    $group -> $group_type = |feattles| &feattles.$group
+                );
             )*
         }
 
-        #[derive(Debug)]
+        $(
+            $(#[doc = $group_doc])*
+            #[derive(Debug, Clone)]
+            $visibility struct $group_type {
+                $($group_key: __internal::Feattle<$group_field_type>,)*
+            }
+
+            impl $group_type {
+                $(
+                    pub fn $group_key(&self) -> &$group_field_type {
+                        self.$group_key.value()
+                    }
+                )*
+            }
+        )*
+
+        #[derive(Debug, Clone)]
         pub struct __Feattles {
-            $($key: __internal::Feattle<$type>),*
+            $($key: __internal::Feattle<$type>,)*
+            $($group: $group_type,)*
         }
 
         impl __internal::FeattlesStruct for __Feattles {
@@ -203,9 +553,54 @@ macro_rules! feattles {
                 &mut self,
                 key: &str,
                 value: Option<__internal::CurrentValue>,
+                staging_mode: bool,
             ) -> Result<Option<__internal::CurrentValue>, __internal::FromJsonError> {
                 match key {
-                    $(stringify!($key) => self.$key.try_update(value),)*
+                    $(stringify!($key) => self.$key.try_update(value, staging_mode),)*
+                    $($(
+                        concat!(stringify!($group), ".", stringify!($group_key)) => {
+                            self.$group.$group_key.try_update(value, staging_mode)
+                        }
+                    )*)*
+                    _ => unreachable!(),
+                }
+            }
+
+            fn is_derived(&self, key: &str) -> bool {
+                match key {
+                    $(stringify!($key) => self.$key.is_derived(),)*
+                    _ => false,
+                }
+            }
+
+            fn validate(
+                &self,
+                key: &str,
+                value: &__internal::Value,
+            ) -> Result<__internal::Value, __internal::FromJsonError> {
+                match key {
+                    $(stringify!($key) => self.$key.validate(value),)*
+                    $($(
+                        concat!(stringify!($group), ".", stringify!($group_key)) => {
+                            self.$group.$group_key.validate(value)
+                        }
+                    )*)*
+                    _ => unreachable!(),
+                }
+            }
+
+            fn set_layer(
+                &mut self,
+                key: &str,
+                layer: Option<(__internal::Value, __internal::ValueSource)>,
+            ) -> Result<(), __internal::FromJsonError> {
+                match key {
+                    $(stringify!($key) => self.$key.set_layer(layer),)*
+                    $($(
+                        concat!(stringify!($group), ".", stringify!($group_key)) => {
+                            self.$group.$group_key.set_layer(layer)
+                        }
+                    )*)*
                     _ => unreachable!(),
                 }
             }
@@ -4,13 +4,26 @@
 //! historical values for the feattles. Instead, it defines this extension point that can be
 //! used to create your own custom logic, however some implementors are available in the package
 //! `feattle-sync`.
+//!
+//! By default, unknown fields in the persisted data are ignored when it's deserialized, so that
+//! data written by newer versions can be read. With the cargo feature `strict-serde`, they are
+//! rejected instead, so that corrupt or misformatted data fails loudly, for example during a
+//! migration.
 
 use crate::BoxError;
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
+use futures_core::Stream;
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::BTreeMap;
+use std::pin::Pin;
+use thiserror::Error;
+
+/// A stream that yields an item each time the current values may have changed, returned by
+/// [`Persist::subscribe_changes()`]
+pub type ChangeStream = Pin<Box<dyn Stream<Item = ()> + Send>>;
 
 /// Responsible for storing and loading data from a permanent storage.
 ///
@@ -53,8 +66,27 @@ pub trait Persist: Send + Sync {
     /// Save current state of all feattles.
     async fn save_current(&self, value: &CurrentValues) -> Result<(), BoxError>;
 
+    /// Save current state of all feattles, returning the new revision. This is what the feattles
+    /// call when updating the values.
+    ///
+    /// Backends that support it should honor [`CurrentValues::revision`], failing with
+    /// [`RevisionConflict`] if it doesn't match the revision currently stored, and return the new
+    /// revision.
+    ///
+    /// The default implementation calls [`Persist::save_current()`] and returns `Ok(None)`, for
+    /// the backends that don't support revisions.
+    async fn save_current_with_revision(
+        &self,
+        value: &CurrentValues,
+    ) -> Result<Option<String>, BoxError> {
+        self.save_current(value).await?;
+        Ok(None)
+    }
+
     /// Load the current state of all feattles. With no previous state existed, `Ok(None)` should be
     /// returned.
+    ///
+    /// Backends that support it should populate [`CurrentValues::revision`].
     async fn load_current(&self) -> Result<Option<CurrentValues>, BoxError>;
 
     /// Save the full history of a single feattle.
@@ -63,10 +95,48 @@ pub trait Persist: Send + Sync {
     /// Load the full history of a single feattle. With the feattle has no history, `Ok(None)`
     /// should be returned.
     async fn load_history(&self, key: &str) -> Result<Option<ValueHistory>, BoxError>;
+
+    /// Load the part of the history of a single feattle that is needed to append a new entry to
+    /// it, used by [`crate::Feattles::update()`]. The history with the new entry is then saved with
+    /// [`Persist::save_history()`], so implementations that override this method must keep the
+    /// entries that were left out when saving it.
+    ///
+    /// The default implementation loads the full history, with [`Persist::load_history()`].
+    async fn load_history_tail(&self, key: &str) -> Result<Option<ValueHistory>, BoxError> {
+        self.load_history(key).await
+    }
+
+    /// Save the changes waiting for approval, used by [`crate::Feattles::propose_update()`].
+    ///
+    /// The default implementation fails with [`PendingUnsupported`].
+    async fn save_pending(&self, value: &PendingChanges) -> Result<(), BoxError> {
+        let _ = value;
+        Err(Box::new(PendingUnsupported))
+    }
+
+    /// Load the changes waiting for approval. With no previous state existed, `Ok(None)` should be
+    /// returned.
+    ///
+    /// The default implementation always returns `Ok(None)`.
+    async fn load_pending(&self) -> Result<Option<PendingChanges>, BoxError> {
+        Ok(None)
+    }
+
+    /// Subscribe to the notifications of changes to the current values, for backends that can
+    /// push them, like a pub/sub channel. `BackgroundSync`, from the package `feattle-sync`, uses
+    /// it to reload right away instead of waiting for the next poll. When the stream ends, it
+    /// falls back to polling.
+    ///
+    /// The default implementation returns `None`, meaning that changes can only be detected by
+    /// polling.
+    fn subscribe_changes(&self) -> Option<ChangeStream> {
+        None
+    }
 }
 
 /// Store the current values of all feattles
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "strict-serde", serde(deny_unknown_fields))]
 pub struct CurrentValues {
     /// A monotonically increasing version, that can be used to detect race conditions
     pub version: i32,
@@ -76,10 +146,34 @@ pub struct CurrentValues {
     /// modified. Also, some extra feattles may be present in this map because they were used in a
     /// previous invocation of feattles.
     pub feattles: BTreeMap<String, CurrentValue>,
+    /// An opaque token that identifies the stored revision, like an ETag, populated by the
+    /// persistence layers that support it when loading. When saving, those layers will only
+    /// accept the new values if the stored revision is still the same, implementing an optimistic
+    /// lock. A revision of `None` only matches an empty storage. Other layers ignore it, so
+    /// concurrent writers may overwrite each other's changes.
+    ///
+    /// After a successful save, the revision returned by [`Persist::save_current_with_revision()`]
+    /// is kept in memory, so the next update from the same instance doesn't need to reload the data
+    /// first.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+}
+
+/// The error returned by [`Persist::save_current_with_revision()`] when
+/// [`CurrentValues::revision`] does not match the stored revision, because another writer changed
+/// it in the meantime.
+#[derive(Debug, Clone, Error)]
+#[error("the current values were modified by another writer, since revision {expected:?}")]
+pub struct RevisionConflict {
+    /// The revision given when saving
+    pub expected: Option<String>,
+    /// The revision that is currently stored
+    pub actual: Option<String>,
 }
 
 /// Store the current value of a single featttle
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "strict-serde", serde(deny_unknown_fields))]
 pub struct CurrentValue {
     /// When this modification was made
     pub modified_at: DateTime<Utc>,
@@ -87,17 +181,26 @@ pub struct CurrentValue {
     pub modified_by: String,
     /// The value, expressed in JSON
     pub value: Value,
+    /// The value read by the instances in staging mode instead of `value`, if any, as set by
+    /// [`crate::Feattles::update_staging()`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub staging: Option<Value>,
 }
 
 /// Store the history of modification of a single feattle
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "strict-serde", serde(deny_unknown_fields))]
 pub struct ValueHistory {
     /// The entries are not necessarily stored in any specific order
     pub entries: Vec<HistoryEntry>,
 }
 
 /// Store the value at a given point in time of a single feattle
+///
+/// New fields may be added in minor versions, so entries are created with [`HistoryEntry::new()`].
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "strict-serde", serde(deny_unknown_fields))]
+#[non_exhaustive]
 pub struct HistoryEntry {
     /// The value, expressed in JSON
     pub value: Value,
@@ -105,10 +208,73 @@ pub struct HistoryEntry {
     pub value_overview: String,
     /// When this modification was made
     pub modified_at: DateTime<Utc>,
-    /// Who did that modification
+    /// Who did that modification. For changes that required an approval, this is who proposed it
     pub modified_by: String,
+    /// Who approved this modification, for changes made with
+    /// [`crate::Feattles::approve_update()`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approved_by: Option<String>,
+    /// Whether this entry only persisted the same value again, with [`crate::Feattles::touch()`]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub touch: bool,
+    /// Whether this entry removed the persisted value, with [`crate::Feattles::unset()`]. The
+    /// recorded value is the default one, that is used from then on.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unset: bool,
+    /// The signature of this entry, chained to the previous one, when the history is audited.
+    /// See the module `audit`, available with the cargo feature `audit`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+impl HistoryEntry {
+    /// Create an entry for a plain change: not approved, touched or unset, and not signed. The
+    /// other fields can be set afterwards.
+    pub fn new(
+        value: Value,
+        value_overview: String,
+        modified_at: DateTime<Utc>,
+        modified_by: String,
+    ) -> Self {
+        HistoryEntry {
+            value,
+            value_overview,
+            modified_at,
+            modified_by,
+            approved_by: None,
+            touch: false,
+            unset: false,
+            signature: None,
+        }
+    }
 }
 
+/// Store the changes proposed with [`crate::Feattles::propose_update()`] that are waiting for
+/// approval, by key
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "strict-serde", serde(deny_unknown_fields))]
+pub struct PendingChanges {
+    pub changes: BTreeMap<String, PendingChange>,
+}
+
+/// A change of a single feattle that is waiting for approval
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "strict-serde", serde(deny_unknown_fields))]
+pub struct PendingChange {
+    /// The proposed value, expressed in JSON
+    pub value: Value,
+    /// When this change was proposed
+    pub proposed_at: DateTime<Utc>,
+    /// Who proposed this change
+    pub proposed_by: String,
+}
+
+/// The error returned by the default implementation of [`Persist::save_pending()`], for the
+/// persistence layers that can't store pending changes
+#[derive(Debug, Clone, Copy, Error)]
+#[error("this persistence layer does not support pending changes")]
+pub struct PendingUnsupported;
+
 /// A mock implementation that does not store the information anywhere.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct NoPersistence;
@@ -130,4 +296,125 @@ impl Persist for NoPersistence {
     async fn load_history(&self, _key: &str) -> Result<Option<ValueHistory>, BoxError> {
         Ok(None)
     }
+
+    async fn save_pending(&self, _value: &PendingChanges) -> Result<(), BoxError> {
+        Ok(())
+    }
+}
+
+/// A simple implementation that stores the information in memory only, so it is lost when the
+/// process ends. This is mostly useful for tests.
+///
+/// It supports [`CurrentValues::revision`], with a counter that increases at each save.
+#[derive(Debug, Default)]
+pub struct InMemoryPersistence {
+    current: Mutex<Option<CurrentValues>>,
+    history: Mutex<BTreeMap<String, ValueHistory>>,
+    pending: Mutex<Option<PendingChanges>>,
+}
+
+impl InMemoryPersistence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remove all the stored data
+    pub fn clear(&self) {
+        *self.current.lock() = None;
+        self.history.lock().clear();
+        *self.pending.lock() = None;
+    }
+}
+
+#[async_trait]
+impl Persist for InMemoryPersistence {
+    async fn save_current(&self, value: &CurrentValues) -> Result<(), BoxError> {
+        self.save_current_with_revision(value).await?;
+        Ok(())
+    }
+
+    async fn save_current_with_revision(
+        &self,
+        value: &CurrentValues,
+    ) -> Result<Option<String>, BoxError> {
+        let mut current = self.current.lock();
+        let stored_revision = current
+            .as_ref()
+            .and_then(|current| current.revision.clone());
+        if value.revision != stored_revision {
+            return Err(Box::new(RevisionConflict {
+                expected: value.revision.clone(),
+                actual: stored_revision,
+            }));
+        }
+
+        let last_revision: u64 = stored_revision
+            .and_then(|revision| revision.parse().ok())
+            .unwrap_or(0);
+        let revision = Some((last_revision + 1).to_string());
+        let mut value = value.clone();
+        value.revision = revision.clone();
+        *current = Some(value);
+        Ok(revision)
+    }
+
+    async fn load_current(&self) -> Result<Option<CurrentValues>, BoxError> {
+        Ok(self.current.lock().clone())
+    }
+
+    async fn save_history(&self, key: &str, value: &ValueHistory) -> Result<(), BoxError> {
+        self.history.lock().insert(key.to_owned(), value.clone());
+        Ok(())
+    }
+
+    async fn load_history(&self, key: &str) -> Result<Option<ValueHistory>, BoxError> {
+        Ok(self.history.lock().get(key).cloned())
+    }
+
+    async fn save_pending(&self, value: &PendingChanges) -> Result<(), BoxError> {
+        *self.pending.lock() = Some(value.clone());
+        Ok(())
+    }
+
+    async fn load_pending(&self) -> Result<Option<PendingChanges>, BoxError> {
+        Ok(self.pending.lock().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn unknown_fields() {
+        let entry = json!({
+            "value": 17,
+            "value_overview": "17",
+            "modified_at": "2024-01-01T00:00:00Z",
+            "modified_by": "someone",
+        });
+        let history = json!({ "entries": [entry] });
+        assert!(serde_json::from_value::<ValueHistory>(history).is_ok());
+
+        let mut entry_with_unknown = entry.clone();
+        entry_with_unknown["unexpected"] = json!(true);
+        let history = json!({ "entries": [entry_with_unknown] });
+        let current = json!({
+            "version": 1,
+            "date": "2024-01-01T00:00:00Z",
+            "feattles": {},
+            "unexpected": true,
+        });
+        let history = serde_json::from_value::<ValueHistory>(history);
+        let current = serde_json::from_value::<CurrentValues>(current);
+
+        if cfg!(feature = "strict-serde") {
+            assert!(history.unwrap_err().to_string().contains("unexpected"));
+            assert!(current.unwrap_err().to_string().contains("unexpected"));
+        } else {
+            assert_eq!(history.unwrap().entries[0].value, json!(17));
+            assert_eq!(current.unwrap().version, 1);
+        }
+    }
 }
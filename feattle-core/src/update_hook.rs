@@ -0,0 +1,86 @@
+use serde_json::Value;
+use std::fmt;
+use thiserror::Error;
+
+/// A hook that is called around every change made by [`crate::Feattles::update()`] and
+/// [`crate::Feattles::replace_all()`], registered with [`crate::Feattles::add_update_hook()`].
+///
+/// This can be used for cross-cutting concerns, like authorization checks, enforcing business
+/// rules or emitting events.
+///
+/// # Example
+/// ```
+/// use std::sync::Arc;
+/// use feattle_core::{feattles, Feattles, RejectReason, UpdateHook};
+/// use feattle_core::persist::NoPersistence;
+/// use serde_json::Value;
+///
+/// feattles! {
+///     struct MyToggles {
+///         max_connections: i32 = 10,
+///     }
+/// }
+///
+/// struct OnlyAdmins;
+///
+/// impl UpdateHook for OnlyAdmins {
+///     fn before_update(&self, _key: &str, _value: &Value, modified_by: &str) -> Result<(), RejectReason> {
+///         if modified_by.ends_with("@admin") {
+///             Ok(())
+///         } else {
+///             Err(RejectReason::new(format!("{} is not an admin", modified_by)))
+///         }
+///     }
+/// }
+///
+/// let my_toggles = MyToggles::new(Arc::new(NoPersistence));
+/// my_toggles.add_update_hook(Arc::new(OnlyAdmins));
+/// ```
+pub trait UpdateHook: Send + Sync {
+    /// Called before a feattle is changed to the proposed value, by the given user. Returning an
+    /// error will veto the change and make the update fail with
+    /// [`crate::UpdateError::Rejected`].
+    ///
+    /// Note that the value was not yet parsed, so it may still fail to parse after this hook
+    /// accepts it.
+    fn before_update(
+        &self,
+        key: &str,
+        proposed_value: &Value,
+        modified_by: &str,
+    ) -> Result<(), RejectReason> {
+        let _ = (key, proposed_value, modified_by);
+        Ok(())
+    }
+
+    /// Called after a feattle was changed to the new value and persisted, by the given user.
+    fn after_update(&self, key: &str, new_value: &Value, modified_by: &str) {
+        let _ = (key, new_value, modified_by);
+    }
+}
+
+impl fmt::Debug for dyn UpdateHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "UpdateHook")
+    }
+}
+
+/// The reason given by [`UpdateHook::before_update()`] to veto a change
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+#[error("{message}")]
+pub struct RejectReason {
+    message: String,
+}
+
+impl RejectReason {
+    pub fn new(message: impl Into<String>) -> Self {
+        RejectReason {
+            message: message.into(),
+        }
+    }
+
+    /// The human-readable description of why the change was vetoed
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
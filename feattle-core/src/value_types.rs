@@ -0,0 +1,595 @@
+//! Ready-to-use feattle types, that go beyond the types from the std lib
+
+use crate::definition::SerializedFormat;
+use crate::json_reading::{extract_bool, extract_f64, extract_object, extract_str, FromJsonError};
+use crate::{
+    EvaluationContext, FeattleEnum, FeattleValue, ObjectField, SerializedFormatKind,
+    StringFormatKind,
+};
+use serde_json::{json, Number, Value};
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+/// Enable a feature for a percentage of the users.
+///
+/// Each user is deterministically assigned to a bucket, based on a hash of its key, so the same
+/// user will always get the same result for a given percentage, and increasing the percentage
+/// only enables the feature for more users, never disabling it for some of them.
+///
+/// It's represented in JSON as the percentage, a number between `0` and `100`. The effective value
+/// returned by [`FeattleValue::evaluate()`] is a boolean for the user in the context. Without a
+/// user, only a complete rollout is enabled.
+///
+/// # Example
+/// ```
+/// use feattle_core::Rollout;
+///
+/// let rollout = Rollout::new(25.0).unwrap();
+/// let enabled = (0..1000)
+///     .filter(|i| rollout.is_enabled_for(&format!("user-{}", i)))
+///     .count();
+/// assert!(200 < enabled && enabled < 300);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub struct Rollout {
+    percentage: f64,
+}
+
+impl Rollout {
+    /// Create a new rollout for the given percentage of users, returning `None` if it's not
+    /// between `0` and `100`.
+    pub fn new(percentage: f64) -> Option<Self> {
+        if (0.0..=100.0).contains(&percentage) {
+            Some(Rollout { percentage })
+        } else {
+            None
+        }
+    }
+
+    pub fn percentage(&self) -> f64 {
+        self.percentage
+    }
+
+    /// Return whether the feature is enabled for the given user
+    pub fn is_enabled_for(&self, user_key: &str) -> bool {
+        // Use a stable hash (FNV-1a), so that users keep their bucket across processes and versions
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in user_key.bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        let bucket = (hash % 10_000) as f64 / 100.0;
+        bucket < self.percentage
+    }
+}
+
+impl FeattleValue for Rollout {
+    fn as_json(&self) -> Value {
+        Value::Number(Number::from_f64(self.percentage).unwrap())
+    }
+    fn overview(&self) -> String {
+        format!("{}%", self.percentage)
+    }
+    fn try_from_json(value: &Value) -> Result<Self, FromJsonError> {
+        let percentage = extract_f64(value)?;
+        Rollout::new(percentage).ok_or(FromJsonError::WrongKind {
+            actual: "Number",
+            expected: "Number between 0 and 100",
+        })
+    }
+    fn serialized_format() -> SerializedFormat {
+        SerializedFormat {
+            kind: SerializedFormatKind::Float,
+            tag: "Rollout".to_owned(),
+        }
+    }
+    fn evaluate(&self, context: &EvaluationContext) -> Value {
+        let enabled = match &context.user_key {
+            None => self.percentage >= 100.0,
+            Some(user_key) => self.is_enabled_for(user_key),
+        };
+        Value::Bool(enabled)
+    }
+}
+
+/// A geographic coordinate, with latitude and longitude in degrees.
+///
+/// It's represented in JSON as an object, like `{"lat": -23.55, "lng": -46.63}`. The latitude must
+/// be between `-90` and `90` and the longitude between `-180` and `180`.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub struct LatLng {
+    lat: f64,
+    lng: f64,
+}
+
+impl LatLng {
+    /// Create a new coordinate, returning `None` if any of the values is out of range.
+    pub fn new(lat: f64, lng: f64) -> Option<Self> {
+        if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lng) {
+            Some(LatLng { lat, lng })
+        } else {
+            None
+        }
+    }
+
+    pub fn lat(&self) -> f64 {
+        self.lat
+    }
+
+    pub fn lng(&self) -> f64 {
+        self.lng
+    }
+}
+
+impl FeattleValue for LatLng {
+    fn as_json(&self) -> Value {
+        json!({ "lat": self.lat, "lng": self.lng })
+    }
+    fn overview(&self) -> String {
+        format!("({}, {})", self.lat, self.lng)
+    }
+    fn try_from_json(value: &Value) -> Result<Self, FromJsonError> {
+        let object = extract_object(value)?;
+        let read = |name| extract_f64(object.get(name).unwrap_or(&Value::Null));
+        let lat = read("lat")?;
+        if !(-90.0..=90.0).contains(&lat) {
+            return Err(FromJsonError::WrongKind {
+                actual: "Number",
+                expected: "Number between -90 and 90",
+            });
+        }
+        let lng = read("lng")?;
+        if !(-180.0..=180.0).contains(&lng) {
+            return Err(FromJsonError::WrongKind {
+                actual: "Number",
+                expected: "Number between -180 and 180",
+            });
+        }
+        Ok(LatLng { lat, lng })
+    }
+    fn serialized_format() -> SerializedFormat {
+        SerializedFormat {
+            kind: SerializedFormatKind::Object(vec![
+                ObjectField {
+                    name: "lat",
+                    kind: SerializedFormatKind::Float,
+                },
+                ObjectField {
+                    name: "lng",
+                    kind: SerializedFormatKind::Float,
+                },
+            ]),
+            tag: "LatLng".to_owned(),
+        }
+    }
+}
+
+/// A feature that is enabled or disabled, with an optional note for the operators explaining why,
+/// like "disabled because of incident X".
+///
+/// It's represented in JSON as an object, like `{"enabled": false, "note": "incident X"}`. The
+/// effective value returned by [`FeattleValue::evaluate()`] is the boolean `enabled`.
+///
+/// # Example
+/// ```
+/// use feattle_core::{FeattleValue, Toggle};
+///
+/// let toggle = Toggle::off("incident X");
+/// assert!(!toggle.enabled);
+/// assert_eq!(toggle.overview(), "off (incident X)");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Toggle {
+    pub enabled: bool,
+    pub note: Option<String>,
+}
+
+impl Toggle {
+    /// Create an enabled toggle, without a note
+    pub fn on() -> Self {
+        Toggle {
+            enabled: true,
+            note: None,
+        }
+    }
+
+    /// Create a disabled toggle, explaining why
+    pub fn off(note: impl Into<String>) -> Self {
+        Toggle {
+            enabled: false,
+            note: Some(note.into()),
+        }
+    }
+}
+
+impl FeattleValue for Toggle {
+    fn as_json(&self) -> Value {
+        json!({ "enabled": self.enabled, "note": self.note })
+    }
+    fn overview(&self) -> String {
+        match (self.enabled, &self.note) {
+            (true, _) => "on".to_owned(),
+            (false, None) => "off".to_owned(),
+            (false, Some(note)) => format!("off ({})", note),
+        }
+    }
+    fn try_from_json(value: &Value) -> Result<Self, FromJsonError> {
+        let object = extract_object(value)?;
+        let enabled = extract_bool(object.get("enabled").unwrap_or(&Value::Null))?;
+        let note = match object.get("note") {
+            None | Some(Value::Null) => None,
+            Some(note) => Some(extract_str(note)?.to_owned()),
+        };
+        Ok(Toggle { enabled, note })
+    }
+    fn serialized_format() -> SerializedFormat {
+        SerializedFormat {
+            kind: SerializedFormatKind::Object(vec![
+                ObjectField {
+                    name: "enabled",
+                    kind: SerializedFormatKind::Bool,
+                },
+                ObjectField {
+                    name: "note",
+                    kind: SerializedFormatKind::Optional(Box::new(SerializedFormatKind::String(
+                        StringFormatKind::Any,
+                    ))),
+                },
+            ]),
+            tag: "Toggle".to_owned(),
+        }
+    }
+    fn evaluate(&self, _context: &EvaluationContext) -> Value {
+        Value::Bool(self.enabled)
+    }
+}
+
+/// The allowed values of [`OneOf`], decided at runtime.
+pub trait OneOfChoices: 'static {
+    /// Return the allowed values. This is called every time a value is parsed or the format is
+    /// described, so it can read from another source, like a configuration loaded at startup.
+    fn choices() -> Vec<String>;
+}
+
+/// A string that must be one of the values allowed by `C`.
+///
+/// Unlike the types created with [`crate::feattle_enum`], the allowed values don't need to be
+/// known at compile time. It's represented in JSON as the string itself and it's described as
+/// [`StringFormatKind::Choices`], so the admin UI shows the allowed values.
+///
+/// # Example
+/// ```
+/// use feattle_core::{FeattleValue, OneOf, OneOfChoices};
+/// use serde_json::json;
+///
+/// struct Regions;
+///
+/// impl OneOfChoices for Regions {
+///     fn choices() -> Vec<String> {
+///         // This could come from a configuration file, for example
+///         vec!["eu-west-1".to_owned(), "us-east-1".to_owned()]
+///     }
+/// }
+///
+/// let region = OneOf::<Regions>::new("eu-west-1").unwrap();
+/// assert_eq!(region.value(), "eu-west-1");
+/// assert!(OneOf::<Regions>::try_from_json(&json!("sa-east-1")).is_err());
+/// ```
+pub struct OneOf<C> {
+    value: String,
+    _choices: PhantomData<fn() -> C>,
+}
+
+impl<C: OneOfChoices> OneOf<C> {
+    /// Create a new value, returning `None` if it's not one of the allowed values.
+    pub fn new(value: impl Into<String>) -> Option<Self> {
+        let value = value.into();
+        if C::choices().contains(&value) {
+            Some(OneOf {
+                value,
+                _choices: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl<C> fmt::Debug for OneOf<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("OneOf").field(&self.value).finish()
+    }
+}
+
+impl<C> Clone for OneOf<C> {
+    fn clone(&self) -> Self {
+        OneOf {
+            value: self.value.clone(),
+            _choices: PhantomData,
+        }
+    }
+}
+
+impl<C> PartialEq for OneOf<C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<C> Eq for OneOf<C> {}
+
+impl<C: OneOfChoices> FeattleValue for OneOf<C> {
+    fn as_json(&self) -> Value {
+        Value::String(self.value.clone())
+    }
+    fn overview(&self) -> String {
+        self.value.clone()
+    }
+    fn try_from_json(value: &Value) -> Result<Self, FromJsonError> {
+        OneOf::new(extract_str(value)?).ok_or(FromJsonError::WrongKind {
+            actual: "String",
+            expected: "one of the allowed values",
+        })
+    }
+    fn serialized_format() -> SerializedFormat {
+        let choices = C::choices();
+        let tag = format!("OneOf {{{}}}", choices.join(", "));
+        SerializedFormat {
+            kind: SerializedFormatKind::String(StringFormatKind::Choices(choices)),
+            tag,
+        }
+    }
+}
+
+/// A set of variants of an `enum` created with [`crate::feattle_enum`], like a list of
+/// permissions.
+///
+/// It's represented in JSON as an array with the variants, like `["Read", "Write"]`. For
+/// convenience, the strings `"all"` and `"none"` are also accepted when parsing, as a shortcut for
+/// all the variants and for the empty set. The set is always represented with its explicit
+/// variants afterwards.
+///
+/// # Example
+/// ```
+/// use feattle_core::{feattle_enum, EnumSet, FeattleValue};
+/// use serde_json::json;
+///
+/// feattle_enum! {
+///     enum Permission { Read, Write, Delete }
+/// }
+///
+/// let permissions = EnumSet::<Permission>::try_from_json(&json!("all")).unwrap();
+/// assert!(permissions.contains(Permission::Delete));
+/// assert_eq!(permissions.as_json(), json!(["Read", "Write", "Delete"]));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnumSet<T: Ord> {
+    values: BTreeSet<T>,
+}
+
+impl<T: FeattleEnum> EnumSet<T>
+where
+    <T as FromStr>::Err: Error + Send + Sync + 'static,
+{
+    pub fn new(values: BTreeSet<T>) -> Self {
+        EnumSet { values }
+    }
+
+    /// Create the set with all the variants
+    pub fn all() -> Self {
+        let values = T::VARIANTS
+            .iter()
+            .map(|variant| {
+                variant
+                    .parse()
+                    .expect("the variants of a feattle_enum must parse")
+            })
+            .collect();
+        EnumSet { values }
+    }
+
+    /// Create the empty set
+    pub fn none() -> Self {
+        EnumSet {
+            values: BTreeSet::new(),
+        }
+    }
+
+    pub fn contains(&self, value: T) -> bool {
+        self.values.contains(&value)
+    }
+
+    pub fn values(&self) -> &BTreeSet<T> {
+        &self.values
+    }
+}
+
+impl<T: Ord> Default for EnumSet<T> {
+    fn default() -> Self {
+        EnumSet {
+            values: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: FeattleEnum> FeattleValue for EnumSet<T>
+where
+    <T as FromStr>::Err: Error + Send + Sync + 'static,
+{
+    fn as_json(&self) -> Value {
+        self.values.as_json()
+    }
+    fn overview(&self) -> String {
+        self.values.overview()
+    }
+    fn try_from_json(value: &Value) -> Result<Self, FromJsonError> {
+        match value.as_str() {
+            Some("all") => Ok(EnumSet::all()),
+            Some("none") => Ok(EnumSet::none()),
+            Some(_) => Err(FromJsonError::WrongKind {
+                actual: "String",
+                expected: "Array, \"all\" or \"none\"",
+            }),
+            None => Ok(EnumSet::new(BTreeSet::try_from_json(value)?)),
+        }
+    }
+    fn serialized_format() -> SerializedFormat {
+        let f = BTreeSet::<T>::serialized_format();
+        SerializedFormat {
+            kind: f.kind,
+            tag: format!("Enum{}", f.tag),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn rollout() {
+        let rollout = Rollout::try_from_json(&json!(50)).unwrap();
+        assert_eq!(rollout.percentage(), 50.0);
+        assert_eq!(rollout.as_json(), json!(50.0));
+        assert_eq!(rollout.overview(), "50%");
+        assert!(Rollout::try_from_json(&json!(-1)).is_err());
+        assert!(Rollout::try_from_json(&json!(100.5)).is_err());
+        assert!(Rollout::try_from_json(&json!("50")).is_err());
+
+        let context = |user_key: &str| EvaluationContext::new(Some(user_key.to_owned()));
+        let enabled_users: Vec<_> = (0..10)
+            .filter(|i| rollout.evaluate(&context(&format!("user-{}", i))) == json!(true))
+            .collect();
+        assert_eq!(enabled_users, [1, 6, 7, 8]);
+
+        // Increasing the rollout keeps the previous users
+        let larger = Rollout::new(75.0).unwrap();
+        for i in enabled_users {
+            assert!(larger.is_enabled_for(&format!("user-{}", i)));
+        }
+
+        let no_user = EvaluationContext::new(None);
+        assert_eq!(rollout.evaluate(&no_user), json!(false));
+        assert_eq!(Rollout::new(100.0).unwrap().evaluate(&no_user), json!(true));
+        assert_eq!(
+            Rollout::new(0.0).unwrap().evaluate(&context("a")),
+            json!(false)
+        );
+    }
+
+    #[test]
+    fn lat_lng() {
+        let value = LatLng::try_from_json(&json!({"lat": -23.5, "lng": 180})).unwrap();
+        assert_eq!((value.lat(), value.lng()), (-23.5, 180.0));
+        assert_eq!(value.as_json(), json!({"lat": -23.5, "lng": 180.0}));
+        assert_eq!(value.overview(), "(-23.5, 180)");
+        assert!(LatLng::try_from_json(&json!({"lat": 90, "lng": -180})).is_ok());
+
+        assert!(LatLng::try_from_json(&json!({"lat": 90.1, "lng": 0})).is_err());
+        assert!(LatLng::try_from_json(&json!({"lat": -90.1, "lng": 0})).is_err());
+        assert!(LatLng::try_from_json(&json!({"lat": 0, "lng": 180.1})).is_err());
+        assert!(LatLng::try_from_json(&json!({"lat": 0, "lng": -180.1})).is_err());
+        assert!(LatLng::try_from_json(&json!({"lat": 0})).is_err());
+        assert!(LatLng::try_from_json(&json!([0, 0])).is_err());
+        assert_eq!(LatLng::new(0.0, 200.0), None);
+    }
+
+    #[test]
+    fn toggle() {
+        let on = Toggle::try_from_json(&json!({"enabled": true, "note": null})).unwrap();
+        assert_eq!(on, Toggle::on());
+        assert_eq!(on.as_json(), json!({"enabled": true, "note": null}));
+        assert_eq!(Toggle::try_from_json(&on.as_json()).unwrap(), on);
+        assert_eq!(on.overview(), "on");
+        assert_eq!(on.evaluate(&EvaluationContext::new(None)), json!(true));
+
+        let off = Toggle::try_from_json(&json!({"enabled": false, "note": "incident X"})).unwrap();
+        assert_eq!(off, Toggle::off("incident X"));
+        assert_eq!(
+            off.as_json(),
+            json!({"enabled": false, "note": "incident X"})
+        );
+        assert_eq!(Toggle::try_from_json(&off.as_json()).unwrap(), off);
+        assert_eq!(off.overview(), "off (incident X)");
+        assert_eq!(off.evaluate(&EvaluationContext::new(None)), json!(false));
+
+        let off = Toggle::try_from_json(&json!({"enabled": false})).unwrap();
+        assert_eq!(off, Toggle::default());
+        assert_eq!(off.overview(), "off");
+
+        assert!(Toggle::try_from_json(&json!({"note": "incident X"})).is_err());
+        assert!(Toggle::try_from_json(&json!({"enabled": true, "note": 17})).is_err());
+        assert!(Toggle::try_from_json(&json!(true)).is_err());
+    }
+
+    #[test]
+    fn one_of() {
+        struct Colors;
+
+        impl OneOfChoices for Colors {
+            fn choices() -> Vec<String> {
+                vec!["red".to_owned(), "green".to_owned()]
+            }
+        }
+
+        let value = OneOf::<Colors>::try_from_json(&json!("green")).unwrap();
+        assert_eq!(value.value(), "green");
+        assert_eq!(value.as_json(), json!("green"));
+        assert_eq!(value.overview(), "green");
+        assert_eq!(OneOf::new("green"), Some(value));
+
+        assert!(OneOf::<Colors>::try_from_json(&json!("blue")).is_err());
+        assert!(OneOf::<Colors>::try_from_json(&json!("Red")).is_err());
+        assert!(OneOf::<Colors>::try_from_json(&json!(1)).is_err());
+        assert_eq!(OneOf::<Colors>::new("blue"), None);
+
+        let format = OneOf::<Colors>::serialized_format();
+        assert_eq!(
+            format.kind,
+            SerializedFormatKind::String(StringFormatKind::Choices(vec![
+                "red".to_owned(),
+                "green".to_owned()
+            ]))
+        );
+        assert_eq!(format.tag, "OneOf {red, green}");
+    }
+
+    #[test]
+    fn enum_set() {
+        crate::feattle_enum! {
+            enum Permission { Read, Write, Delete }
+        }
+        let parse = |value| EnumSet::<Permission>::try_from_json(&value);
+
+        let all = parse(json!("all")).unwrap();
+        assert_eq!(all, EnumSet::all());
+        assert_eq!(all.values().len(), 3);
+        assert_eq!(all.as_json(), json!(["Read", "Write", "Delete"]));
+        assert_eq!(all.overview(), "[Read, Write, Delete]");
+
+        let none = parse(json!("none")).unwrap();
+        assert!(none.values().is_empty());
+        assert_eq!(none.as_json(), json!([]));
+
+        let explicit = parse(json!(["Delete", "Read"])).unwrap();
+        assert!(explicit.contains(Permission::Read));
+        assert!(!explicit.contains(Permission::Write));
+        assert_eq!(explicit.as_json(), json!(["Read", "Delete"]));
+
+        assert!(parse(json!("All")).is_err());
+        assert!(parse(json!(["Execute"])).is_err());
+        assert!(parse(json!(1)).is_err());
+        assert_eq!(
+            EnumSet::<Permission>::serialized_format().tag,
+            "EnumSet<enum {Read, Write, Delete}>"
+        );
+    }
+}
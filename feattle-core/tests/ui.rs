@@ -0,0 +1,18 @@
+/// The messages of the compiler change between Rust versions, so this is ignored by default and
+/// only run by the CI, with the stable toolchain. Use `TRYBUILD=overwrite` to update the expected
+/// output.
+#[cfg(feature = "unique-keys")]
+#[test]
+#[ignore]
+fn colliding_keys() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/colliding_keys.rs");
+}
+
+/// See [`colliding_keys()`]
+#[test]
+#[ignore]
+fn invalid_expiry_date() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/invalid_expiry_date.rs");
+}
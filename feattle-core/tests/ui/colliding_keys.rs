@@ -0,0 +1,11 @@
+use feattle_core::feattles;
+
+feattles! {
+    struct Config {
+        foo: bool,
+        fooBar: bool,
+        foo_bar: bool,
+    }
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use feattle_core::feattles;
+
+feattles! {
+    struct Config {
+        #[expires = "2024-13-01"]
+        new_checkout: bool,
+    }
+}
+
+fn main() {}
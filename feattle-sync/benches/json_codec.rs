@@ -0,0 +1,61 @@
+//! Measure the (de)serialization of large current values with [`JsonCodec`].
+//!
+//! Run with `cargo bench -p feattle-sync --bench json_codec`. To compare another implementation,
+//! add it to the list in `codecs()`, so that both appear side by side in the reports.
+
+use chrono::Utc;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use feattle_core::persist::{CurrentValue, CurrentValues};
+use feattle_sync::{JsonCodec, SerdeJson};
+use serde_json::{json, Value};
+use std::hint::black_box;
+
+fn large_current_values() -> CurrentValues {
+    let feattles = (0..100)
+        .map(|i| {
+            let value: Value = (0..1_000)
+                .map(|j| (format!("key-{}", j), json!([i, j, "some string"])))
+                .collect::<serde_json::Map<_, _>>()
+                .into();
+            let current_value = CurrentValue {
+                modified_at: Utc::now(),
+                modified_by: "someone".to_owned(),
+                value,
+                staging: None,
+            };
+            (format!("feattle-{}", i), current_value)
+        })
+        .collect();
+    CurrentValues {
+        version: 1,
+        date: Utc::now(),
+        feattles,
+        revision: None,
+    }
+}
+
+fn bench<C: JsonCodec>(c: &mut Criterion, name: &str, codec: C, values: &CurrentValues) {
+    let contents = codec.serialize(values).unwrap();
+    let mut group = c.benchmark_group(name);
+    group.sample_size(20);
+    group.throughput(Throughput::Bytes(contents.len() as u64));
+    group.bench_function("serialize", |b| {
+        b.iter(|| codec.serialize(black_box(values)).unwrap())
+    });
+    group.bench_function("deserialize", |b| {
+        b.iter_batched(
+            || contents.clone(),
+            |contents| -> CurrentValues { codec.deserialize(black_box(contents)).unwrap() },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn codecs(c: &mut Criterion) {
+    let values = large_current_values();
+    bench(c, "serde_json", SerdeJson, &values);
+}
+
+criterion_group!(benches, codecs);
+criterion_main!(benches);
@@ -1,18 +1,29 @@
+use crate::{JsonCodec, SerdeJson};
 use async_trait::async_trait;
+use aws_sdk_s3::error::SdkError;
 use aws_sdk_s3::operation::get_object::GetObjectError;
 use aws_sdk_s3::primitives::ByteStream;
 use aws_sdk_s3::Client;
 use aws_types::SdkConfig;
-use feattle_core::persist::{CurrentValues, Persist, ValueHistory};
+use feattle_core::persist::{CurrentValues, PendingChanges, Persist, ValueHistory};
 use feattle_core::BoxError;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use std::collections::hash_map::RandomState;
+use std::error::Error;
 use std::fmt;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+use tokio::time;
 
 /// Persist the data in an [AWS S3](https://aws.amazon.com/s3/) bucket.
 ///
 /// To use it, make sure to activate the cargo feature `"aws_sdk_s3"` in your `Cargo.toml`.
 ///
+/// It does not support [`feattle_core::persist::CurrentValues::revision`], so concurrent writers
+/// may overwrite each other's changes: the last save wins.
+///
 /// # Example
 /// ```
 /// use std::sync::Arc;
@@ -41,68 +52,216 @@ use std::fmt;
 /// }
 /// ```
 #[derive(Clone)]
-pub struct S3 {
+pub struct S3<C = SerdeJson> {
     client: Client,
     bucket: String,
     prefix: String,
+    timeout: Option<Duration>,
+    max_retries: u32,
+    codec: C,
 }
 
-impl fmt::Debug for S3 {
+impl<C> fmt::Debug for S3<C> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("S3")
             .field("client", &"S3Client")
             .field("bucket", &self.bucket)
             .field("prefix", &self.prefix)
+            .field("timeout", &self.timeout)
+            .field("max_retries", &self.max_retries)
             .finish()
     }
 }
 
 impl S3 {
     pub fn new(config: &SdkConfig, bucket: String, prefix: String) -> Self {
+        Self::from_client(Client::new(config), bucket, prefix)
+    }
+
+    /// Create a new instance with a pre-configured client, for example with custom retry or
+    /// timeout settings from [`aws_sdk_s3::Config`].
+    pub fn from_client(client: Client, bucket: String, prefix: String) -> Self {
         S3 {
-            client: Client::new(config),
+            client,
             bucket,
             prefix,
+            timeout: None,
+            max_retries: 0,
+            codec: SerdeJson,
         }
     }
+}
+
+impl<C: JsonCodec> S3<C> {
+    /// Use another implementation to (de)serialize the data. Read more at [`JsonCodec`].
+    pub fn with_codec<C2: JsonCodec>(self, codec: C2) -> S3<C2> {
+        S3 {
+            client: self.client,
+            bucket: self.bucket,
+            prefix: self.prefix,
+            timeout: self.timeout,
+            max_retries: self.max_retries,
+            codec,
+        }
+    }
+
+    /// Limit how long each attempt to read or write an object can take. By default, there is no
+    /// limit other than the ones configured in the client, so a stalled call may block
+    /// [`feattle_core::Feattles::reload()`] indefinitely.
+    pub fn timeout(&mut self, value: Duration) -> &mut Self {
+        self.timeout = Some(value);
+        self
+    }
+
+    /// When an attempt times out or fails with a transient error (like a network failure, a
+    /// throttled request or a server error), retry it up to this many times. Before each retry,
+    /// it waits with an exponential backoff with jitter, starting at about 100ms and capped at
+    /// about 10s. By default, failed calls are not retried by this struct, besides the retries done
+    /// by the client itself.
+    pub fn max_retries(&mut self, value: u32) -> &mut Self {
+        self.max_retries = value;
+        self
+    }
 
     async fn save<T: Serialize>(&self, name: &str, value: T) -> Result<(), BoxError> {
         let key = format!("{}{}", self.prefix, name);
-        let contents = serde_json::to_vec(&value)?;
-        self.client
-            .put_object()
-            .bucket(self.bucket.clone())
-            .key(key)
-            .body(ByteStream::from(contents))
-            .send()
-            .await?;
-
-        Ok(())
+        let contents = self.codec.serialize(&value)?;
+        self.retrying(&key, || {
+            let request = self
+                .client
+                .put_object()
+                .bucket(self.bucket.clone())
+                .key(key.clone())
+                .body(ByteStream::from(contents.clone()));
+            async move {
+                request.send().await.map_err(AttemptError::from_sdk)?;
+                Ok(())
+            }
+        })
+        .await
     }
 
     async fn load<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>, BoxError> {
         let key = format!("{}{}", self.prefix, name);
-        let get_object = self
-            .client
-            .get_object()
-            .bucket(self.bucket.clone())
-            .key(key)
-            .send()
-            .await
-            .map_err(|x| x.into_service_error());
-        match get_object {
-            Err(GetObjectError::NoSuchKey(_)) => Ok(None),
-            Ok(response) => {
-                let contents = response.body.collect().await?.to_vec();
-                Ok(Some(serde_json::from_slice(&contents)?))
+        self.retrying(&key, || {
+            let request = self
+                .client
+                .get_object()
+                .bucket(self.bucket.clone())
+                .key(key.clone());
+            async move {
+                let response = match request.send().await {
+                    Ok(response) => response,
+                    Err(error)
+                        if matches!(
+                            error.as_service_error(),
+                            Some(GetObjectError::NoSuchKey(_))
+                        ) =>
+                    {
+                        return Ok(None)
+                    }
+                    Err(error) => return Err(AttemptError::from_sdk(error)),
+                };
+                // The body is streamed, so failing to read it is a network error
+                let contents = response
+                    .body
+                    .collect()
+                    .await
+                    .map_err(|error| AttemptError::new(error, true))?
+                    .to_vec();
+                let value = self
+                    .codec
+                    .deserialize(contents)
+                    .map_err(|error| AttemptError::new(error, false))?;
+                Ok(Some(value))
             }
-            Err(error) => Err(error.into()),
+        })
+        .await
+    }
+
+    /// Run the operation, applying the configured timeout to each attempt and retrying on
+    /// timeouts and transient errors
+    async fn retrying<T, F, Fut>(&self, key: &str, mut operation: F) -> Result<T, BoxError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, AttemptError>>,
+    {
+        let mut retries = 0;
+        loop {
+            let result = match self.timeout {
+                None => operation().await,
+                Some(timeout) => time::timeout(timeout, operation())
+                    .await
+                    .map_err(|error| AttemptError::new(error, true))
+                    .and_then(|result| result),
+            };
+
+            match result {
+                Ok(value) => return Ok(value),
+                Err(error) if error.transient && retries < self.max_retries => {
+                    retries += 1;
+                    let delay = backoff(retries);
+                    log::warn!(
+                        "Failed to access {} in S3, will retry in {:?} ({}/{}): {}",
+                        key,
+                        delay,
+                        retries,
+                        self.max_retries,
+                        error.error
+                    );
+                    time::sleep(delay).await;
+                }
+                Err(error) => return Err(error.error),
+            }
+        }
+    }
+}
+
+/// The error of a single attempt to access S3
+struct AttemptError {
+    error: BoxError,
+    /// Whether the attempt may succeed if retried
+    transient: bool,
+}
+
+impl AttemptError {
+    fn new(error: impl Into<BoxError>, transient: bool) -> Self {
+        AttemptError {
+            error: error.into(),
+            transient,
         }
     }
+
+    /// Classify an error returned by the client: timeouts, network failures, throttled requests
+    /// and server errors are transient, the rest (like access denied) is not
+    fn from_sdk<E: Error + Send + Sync + 'static>(error: SdkError<E>) -> Self {
+        let transient = match &error {
+            SdkError::TimeoutError(_) | SdkError::ResponseError(_) => true,
+            SdkError::DispatchFailure(failure) => failure.is_io() || failure.is_timeout(),
+            SdkError::ServiceError(_) => error.raw_response().is_some_and(|response| {
+                let status = response.status().as_u16();
+                status == 429 || status >= 500
+            }),
+            _ => false,
+        };
+        AttemptError::new(error, transient)
+    }
+}
+
+/// The delay before the given retry (starting at 1): an exponential backoff with "equal jitter",
+/// that is, a random duration between half and all of the exponential delay
+fn backoff(retry: u32) -> Duration {
+    const BASE: Duration = Duration::from_millis(100);
+    const MAX: Duration = Duration::from_secs(10);
+
+    let delay = BASE.saturating_mul(1 << (retry - 1).min(16)).min(MAX);
+    // Avoid pulling a dependency only to get some randomness
+    let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    delay / 2 + delay.mul_f64(random / 2.0)
 }
 
 #[async_trait]
-impl Persist for S3 {
+impl<C: JsonCodec> Persist for S3<C> {
     async fn save_current(&self, value: &CurrentValues) -> Result<(), BoxError> {
         self.save("current.json", value).await
     }
@@ -118,13 +277,68 @@ impl Persist for S3 {
     async fn load_history(&self, key: &str) -> Result<Option<ValueHistory>, BoxError> {
         self.load(&format!("history-{}.json", key)).await
     }
+
+    async fn save_pending(&self, value: &PendingChanges) -> Result<(), BoxError> {
+        self.save("pending.json", value).await
+    }
+
+    async fn load_pending(&self) -> Result<Option<PendingChanges>, BoxError> {
+        self.load("pending.json").await
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::tests::test_persistence;
+    use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
     use aws_sdk_s3::types::{Delete, ObjectIdentifier};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn hung_call_times_out() {
+        // A fake S3 server that accepts connections but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let connections_2 = connections.clone();
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                connections_2.fetch_add(1, Ordering::SeqCst);
+                sockets.push(socket);
+            }
+        });
+
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .endpoint_url(format!("http://{}", address))
+            .credentials_provider(Credentials::new("id", "secret", None, None, "test"))
+            .force_path_style(true)
+            .build();
+        let client = Client::from_conf(config);
+        let mut persistence = S3::from_client(client, "bucket".to_owned(), "prefix/".to_owned());
+        persistence
+            .timeout(Duration::from_millis(100))
+            .max_retries(1);
+
+        let error = persistence.load_current().await.unwrap_err();
+        assert!(error.is::<time::error::Elapsed>());
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn backoff_grows_with_jitter() {
+        let first = backoff(1);
+        assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+        let third = backoff(3);
+        assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+        let last = backoff(100);
+        assert!(last >= Duration::from_secs(5) && last <= Duration::from_secs(10));
+    }
 
     #[tokio::test]
     async fn s3() {
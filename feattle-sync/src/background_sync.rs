@@ -1,8 +1,11 @@
+use crate::CircuitBreaker;
+use feattle_core::persist::ChangeStream;
 use feattle_core::{BoxError, Feattles};
+use std::future::poll_fn;
 use std::sync::{Arc, Weak};
 use std::time::Duration;
 use tokio::task::JoinHandle;
-use tokio::time::sleep;
+use tokio::time::{sleep, timeout};
 
 /// Spawn a tokio task to poll [`Feattles::reload()`] continuously
 ///
@@ -10,6 +13,10 @@ use tokio::time::sleep;
 /// [`Feattles::reload()`] method is called. This type would do so regularly for you, until the
 /// [`Feattles`] instance is dropped.
 ///
+/// If the persistence layer can push notifications of changes, with
+/// [`feattle_core::persist::Persist::subscribe_changes()`], each notification will trigger a
+/// reload right away, instead of waiting for the next poll.
+///
 /// # Example
 /// ```
 /// # #[tokio::main]
@@ -36,6 +43,7 @@ pub struct BackgroundSync<F> {
     ok_interval: Duration,
     err_interval: Duration,
     feattles: Weak<F>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
 }
 
 impl<F> BackgroundSync<F> {
@@ -46,6 +54,7 @@ impl<F> BackgroundSync<F> {
             ok_interval: Duration::from_secs(30),
             err_interval: Duration::from_secs(60),
             feattles: Arc::downgrade(feattles),
+            circuit_breaker: None,
         }
     }
 
@@ -69,6 +78,15 @@ impl<F> BackgroundSync<F> {
         self.err_interval = value;
         self
     }
+
+    /// Stop reloading when the values change too often, as detected by the given
+    /// [`CircuitBreaker`]. By default, there is no circuit breaker.
+    ///
+    /// Note that only the reloads done by this background sync are gated by the breaker.
+    pub fn circuit_breaker(&mut self, value: Arc<CircuitBreaker>) -> &mut Self {
+        self.circuit_breaker = Some(value);
+        self
+    }
 }
 
 impl<F: Feattles + Sync + Send + 'static> BackgroundSync<F> {
@@ -80,7 +98,7 @@ impl<F: Feattles + Sync + Send + 'static> BackgroundSync<F> {
     pub fn spawn(self) -> JoinHandle<()> {
         tokio::spawn(async move {
             while let Some(feattles) = self.feattles.upgrade() {
-                match feattles.reload().await {
+                match self.reload(&feattles).await {
                     Ok(()) => {
                         log::debug!("Feattles updated");
                         sleep(self.ok_interval).await;
@@ -110,8 +128,9 @@ impl<F: Feattles + Sync + Send + 'static> BackgroundSync<F> {
     /// Operational logs are generated with the crate [`log`].
     pub async fn start(self) -> Option<BoxError> {
         let feattles = self.feattles.upgrade()?;
+        let mut changes = feattles.persistence().subscribe_changes();
 
-        let first_error = feattles.reload().await.err();
+        let first_error = self.reload(&feattles).await.err();
         let first_sleep = match &first_error {
             Some(err) => {
                 log::warn!("Failed to sync Feattles: {:?}", err);
@@ -124,17 +143,17 @@ impl<F: Feattles + Sync + Send + 'static> BackgroundSync<F> {
         };
 
         tokio::spawn(async move {
-            sleep(first_sleep).await;
+            wait(first_sleep, &mut changes).await;
 
             while let Some(feattles) = self.feattles.upgrade() {
-                match feattles.reload().await {
+                match self.reload(&feattles).await {
                     Ok(()) => {
                         log::debug!("Feattles updated");
-                        sleep(self.ok_interval).await;
+                        wait(self.ok_interval, &mut changes).await;
                     }
                     Err(err) => {
                         log::warn!("Failed to sync Feattles: {:?}", err);
-                        sleep(self.err_interval).await;
+                        wait(self.err_interval, &mut changes).await;
                     }
                 }
             }
@@ -144,15 +163,55 @@ impl<F: Feattles + Sync + Send + 'static> BackgroundSync<F> {
 
         first_error
     }
+
+    async fn reload(&self, feattles: &F) -> Result<(), BoxError> {
+        let circuit_breaker = match &self.circuit_breaker {
+            None => return feattles.reload().await,
+            Some(circuit_breaker) => circuit_breaker,
+        };
+
+        if circuit_breaker.is_open() {
+            log::warn!("Skipped the reload of Feattles since the circuit breaker is open");
+            return Ok(());
+        }
+        let before = CircuitBreaker::snapshot(feattles);
+        feattles.reload().await?;
+        circuit_breaker.record(&before, &CircuitBreaker::snapshot(feattles));
+        Ok(())
+    }
+}
+
+/// Wait for the given interval or until the persistence layer notifies a change, whichever comes
+/// first. If the notifications end, fall back to polling.
+async fn wait(interval: Duration, changes: &mut Option<ChangeStream>) {
+    if let Some(stream) = changes {
+        match timeout(interval, poll_fn(|cx| stream.as_mut().poll_next(cx))).await {
+            Ok(Some(())) => log::debug!("Feattles changed, will reload now"),
+            Ok(None) => {
+                log::warn!("Stopped receiving changes to Feattles, will poll for them instead");
+                *changes = None;
+                sleep(interval).await;
+            }
+            Err(_) => {}
+        }
+    } else {
+        sleep(interval).await;
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use async_trait::async_trait;
-    use feattle_core::persist::{CurrentValues, Persist, ValueHistory};
+    use chrono::Utc;
+    use feattle_core::persist::{CurrentValue, CurrentValues, Persist, ValueHistory};
     use feattle_core::{feattles, BoxError, Feattles};
+    use futures_core::Stream;
     use parking_lot::Mutex;
+    use serde_json::json;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::sync::mpsc;
     use tokio::time;
     use tokio::time::Instant;
 
@@ -240,4 +299,157 @@ mod tests {
         }
         assert_eq!(persistence.call_intervals().len(), 4);
     }
+
+    struct NotifyingPersistence {
+        loads: Mutex<i32>,
+        changes: Mutex<Option<mpsc::UnboundedReceiver<()>>>,
+    }
+
+    struct ReceiverStream(mpsc::UnboundedReceiver<()>);
+
+    impl Stream for ReceiverStream {
+        type Item = ();
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<()>> {
+            self.0.poll_recv(cx)
+        }
+    }
+
+    #[async_trait]
+    impl Persist for NotifyingPersistence {
+        async fn save_current(&self, _value: &CurrentValues) -> Result<(), BoxError> {
+            unimplemented!()
+        }
+        async fn load_current(&self) -> Result<Option<CurrentValues>, BoxError> {
+            *self.loads.lock() += 1;
+            Ok(None)
+        }
+        async fn save_history(&self, _key: &str, _value: &ValueHistory) -> Result<(), BoxError> {
+            unimplemented!()
+        }
+        async fn load_history(&self, _key: &str) -> Result<Option<ValueHistory>, BoxError> {
+            unimplemented!()
+        }
+        fn subscribe_changes(&self) -> Option<ChangeStream> {
+            let receiver = self.changes.lock().take()?;
+            Some(Box::pin(ReceiverStream(receiver)))
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribe_changes() {
+        feattles! {
+            struct MyToggles { }
+        }
+
+        time::pause();
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let persistence = Arc::new(NotifyingPersistence {
+            loads: Mutex::new(0),
+            changes: Mutex::new(Some(receiver)),
+        });
+        let toggles = Arc::new(MyToggles::new(persistence.clone()));
+        BackgroundSync::new(&toggles).start().await;
+        assert_eq!(*persistence.loads.lock(), 1);
+
+        // A notification triggers a reload before the poll interval of 30s
+        let start = Instant::now();
+        sender.send(()).unwrap();
+        while *persistence.loads.lock() < 2 {
+            tokio::task::yield_now().await;
+            time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // Without notifications, it falls back to polling
+        drop(sender);
+        time::sleep(Duration::from_secs(15)).await;
+        assert_eq!(*persistence.loads.lock(), 2);
+        while *persistence.loads.lock() < 3 {
+            tokio::task::yield_now().await;
+            time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(start.elapsed() >= Duration::from_secs(30));
+    }
+
+    #[derive(Default)]
+    struct FlappingPersistence {
+        calls: Mutex<i32>,
+    }
+
+    #[async_trait]
+    impl Persist for FlappingPersistence {
+        async fn save_current(&self, _value: &CurrentValues) -> Result<(), BoxError> {
+            unimplemented!()
+        }
+        async fn load_current(&self) -> Result<Option<CurrentValues>, BoxError> {
+            // Flap between two values at each call
+            let mut calls = self.calls.lock();
+            *calls += 1;
+            let value = CurrentValue {
+                modified_at: Utc::now(),
+                modified_by: "someone".to_owned(),
+                value: json!(*calls % 2 == 0),
+                staging: None,
+            };
+            Ok(Some(CurrentValues {
+                version: *calls,
+                date: Utc::now(),
+                feattles: vec![("a".to_owned(), value)].into_iter().collect(),
+                revision: None,
+            }))
+        }
+        async fn save_history(&self, _key: &str, _value: &ValueHistory) -> Result<(), BoxError> {
+            unimplemented!()
+        }
+        async fn load_history(&self, _key: &str) -> Result<Option<ValueHistory>, BoxError> {
+            unimplemented!()
+        }
+    }
+
+    #[tokio::test]
+    async fn circuit_breaker() {
+        feattles! {
+            struct MyToggles { a: bool }
+        }
+
+        time::pause();
+
+        let persistence = Arc::new(FlappingPersistence::default());
+        let toggles = Arc::new(MyToggles::new(persistence.clone()));
+        let opened = Arc::new(Mutex::new(Vec::new()));
+        let mut breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+        let opened_clone = opened.clone();
+        breaker.on_open(move |changes| opened_clone.lock().push(changes));
+        let breaker = Arc::new(breaker);
+        let mut background_sync = BackgroundSync::new(&toggles);
+        background_sync
+            .interval(Duration::from_secs(10))
+            .circuit_breaker(breaker.clone());
+        background_sync.start().await;
+
+        // The first load is not a change, the next 4 reloads flap the value and open the breaker
+        while *persistence.calls.lock() < 5 {
+            tokio::task::yield_now().await;
+            time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(breaker.is_open());
+        assert_eq!(*opened.lock(), [4]);
+
+        // The values are frozen
+        let frozen = *toggles.a();
+        time::sleep(Duration::from_secs(100)).await;
+        assert_eq!(*persistence.calls.lock(), 5);
+        assert_eq!(*toggles.a(), frozen);
+
+        // Resume after reset
+        breaker.reset();
+        while *persistence.calls.lock() < 6 {
+            tokio::task::yield_now().await;
+            time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(*toggles.a(), !frozen);
+        assert!(!breaker.is_open());
+    }
 }
@@ -0,0 +1,155 @@
+use feattle_core::Feattles;
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Protect a [`crate::BackgroundSync`] from data that changes too often, for example when the
+/// persistence layer flaps between two configurations.
+///
+/// Each reload that changed the value of at least one feattle is recorded. When more than
+/// `max_changes` such reloads are recorded in the last `window`, the breaker opens: the background
+/// sync stops reloading, freezing the values in memory, an error is logged and the callback
+/// registered with [`CircuitBreaker::on_open()`] is called. The breaker stays open until
+/// [`CircuitBreaker::reset()`] is called, so that operators can investigate.
+///
+/// Only the reloads done by the [`crate::BackgroundSync`] are gated: the admin panel and any direct
+/// call to [`Feattles::reload()`] bypass the breaker. To keep the values frozen while it is open,
+/// use `AdminPanel::reload_on_read(false)` from the crate `feattle-ui` (edits made in the admin
+/// panel still reload the data).
+///
+/// # Example
+/// ```
+/// # #[tokio::main]
+/// # async fn main() {
+/// use feattle_core::{feattles, Feattles};
+/// use feattle_sync::{BackgroundSync, CircuitBreaker};
+/// use feattle_core::persist::NoPersistence;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// feattles! {
+///     struct MyToggles {
+///         a: bool,
+///     }
+/// }
+///
+/// // `NoPersistence` here is just a mock for the sake of the example
+/// let toggles = Arc::new(MyToggles::new(Arc::new(NoPersistence)));
+///
+/// let mut breaker = CircuitBreaker::new(10, Duration::from_secs(300));
+/// breaker.on_open(|changes| eprintln!("Feattles changed {} times, sync stopped", changes));
+/// let breaker = Arc::new(breaker);
+///
+/// let mut background_sync = BackgroundSync::new(&toggles);
+/// background_sync.circuit_breaker(breaker.clone());
+/// background_sync.start().await;
+/// assert!(!breaker.is_open());
+/// # }
+/// ```
+pub struct CircuitBreaker {
+    max_changes: usize,
+    window: Duration,
+    on_open: Option<Box<dyn Fn(usize) + Send + Sync>>,
+    state: Mutex<CircuitBreakerState>,
+}
+
+#[derive(Debug, Default)]
+struct CircuitBreakerState {
+    open: bool,
+    /// The moment of each reload that changed some value
+    changes: VecDeque<Instant>,
+}
+
+/// The values of the feattles, used to detect the changes made by a reload
+pub(crate) type Snapshot = Option<BTreeMap<String, Value>>;
+
+impl CircuitBreaker {
+    /// Create a new breaker that opens when more than `max_changes` reloads changed some value in
+    /// the last `window`.
+    pub fn new(max_changes: usize, window: Duration) -> Self {
+        CircuitBreaker {
+            max_changes,
+            window,
+            on_open: None,
+            state: Mutex::new(CircuitBreakerState::default()),
+        }
+    }
+
+    /// Register a callback to be called when the breaker opens, receiving the number of reloads
+    /// that changed some value in the window. It can be used to raise an alert.
+    pub fn on_open(&mut self, callback: impl Fn(usize) + Send + Sync + 'static) -> &mut Self {
+        self.on_open = Some(Box::new(callback));
+        self
+    }
+
+    /// Whether the breaker is open, that is, the background sync is stopped
+    pub fn is_open(&self) -> bool {
+        self.state.lock().unwrap().open
+    }
+
+    /// Close the breaker, forgetting the previous changes, so that the background sync resumes
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.open = false;
+        state.changes.clear();
+    }
+
+    pub(crate) fn snapshot<F: Feattles>(feattles: &F) -> Snapshot {
+        feattles.current_values().map(|current_values| {
+            current_values
+                .feattles
+                .iter()
+                .map(|(key, value)| (key.clone(), value.value.clone()))
+                .collect()
+        })
+    }
+
+    /// Record whether a reload changed some value, opening the breaker if needed
+    pub(crate) fn record(&self, before: &Snapshot, after: &Snapshot) {
+        let (before, after) = match (before, after) {
+            (Some(before), Some(after)) => (before, after),
+            _ => return,
+        };
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        if before != after {
+            state.changes.push_back(now);
+        }
+        while let Some(&instant) = state.changes.front() {
+            if now.duration_since(instant) > self.window {
+                state.changes.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let total = state.changes.len();
+        if !state.open && total > self.max_changes {
+            state.open = true;
+            drop(state);
+            log::error!(
+                "Feattles changed {} times in the last {:?}: the background sync will stop until \
+                the circuit breaker is reset",
+                total,
+                self.window
+            );
+            if let Some(on_open) = &self.on_open {
+                on_open(total);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for CircuitBreaker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CircuitBreaker")
+            .field("max_changes", &self.max_changes)
+            .field("window", &self.window)
+            .field("on_open", &self.on_open.as_ref().map(|_| "Fn(usize)"))
+            .field("state", &self.state)
+            .finish()
+    }
+}
@@ -1,16 +1,37 @@
+use crate::{JsonCodec, SerdeJson};
 use async_trait::async_trait;
 use feattle_core::persist::*;
 use feattle_core::BoxError;
+use futures_core::Stream;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::io::ErrorKind;
-use std::path::PathBuf;
-use tokio::fs::{create_dir_all, File};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use std::{io, process};
+use tokio::fs::{copy, create_dir_all, metadata, rename, File};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::time;
 
 /// Persist the data in the local filesystem, under a given directory.
 ///
-/// At every save action, if the directory does not exist, it will be created.
+/// At every save action, if the directory does not exist, it will be created. The files are first
+/// written to a temporary file and then renamed, so that a crash never leaves a partially written
+/// file behind.
+///
+/// Before overwriting `current.json`, its previous content is copied to `current.json.bak`, so
+/// that a bad write can be undone with [`Disk::recover_from_backup()`]. Older backups are rotated
+/// to `current.json.bak.2`, `current.json.bak.3` and so on, up to the number configured with
+/// [`Disk::backups()`].
+///
+/// It does not support [`feattle_core::persist::CurrentValues::revision`], so concurrent writers
+/// may overwrite each other's changes: the last save wins.
+///
+/// With [`Disk::watch()`], the modification time of `current.json` is checked periodically, so
+/// that a [`crate::BackgroundSync`] reloads right away when another process changes it.
 ///
 /// # Example
 /// ```
@@ -27,24 +48,100 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 /// let my_toggles = MyToggles::new(Arc::new(Disk::new("some/local/directory")));
 /// ```
 #[derive(Debug, Clone)]
-pub struct Disk {
+pub struct Disk<C = SerdeJson> {
     dir: PathBuf,
+    codec: C,
+    backups: usize,
+    watch: Option<Duration>,
 }
 
 impl Disk {
     pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
         let dir = dir.into();
-        Disk { dir }
+        Disk {
+            dir,
+            codec: SerdeJson,
+            backups: 1,
+            watch: None,
+        }
+    }
+}
+
+impl<C: JsonCodec> Disk<C> {
+    /// Use another implementation to (de)serialize the data. Read more at [`JsonCodec`].
+    pub fn with_codec<C2: JsonCodec>(self, codec: C2) -> Disk<C2> {
+        Disk {
+            dir: self.dir,
+            codec,
+            backups: self.backups,
+            watch: self.watch,
+        }
+    }
+
+    /// Keep this many previous versions of `current.json`. By default, only the last one is kept.
+    /// Use zero to disable the backups.
+    pub fn backups(mut self, value: usize) -> Self {
+        self.backups = value;
+        self
+    }
+
+    /// Check the modification time of `current.json` at this interval, notifying the changes
+    /// through [`Persist::subscribe_changes()`]. By default, the file is not watched.
+    ///
+    /// The check runs in a tokio task, that stops when the subscription is dropped.
+    pub fn watch(mut self, interval: Duration) -> Self {
+        self.watch = Some(interval);
+        self
+    }
+
+    /// Restore `current.json` from its most recent backup, returning `false` if there is no backup.
+    ///
+    /// The backups are left unchanged.
+    pub async fn recover_from_backup(&self) -> Result<bool, BoxError> {
+        match copy_atomically(&self.backup_path(1), &self.dir.join("current.json")).await {
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err.into()),
+            Ok(_) => Ok(true),
+        }
+    }
+
+    fn backup_path(&self, n: usize) -> PathBuf {
+        if n == 1 {
+            self.dir.join("current.json.bak")
+        } else {
+            self.dir.join(format!("current.json.bak.{}", n))
+        }
+    }
+
+    async fn rotate_backups(&self) -> Result<(), BoxError> {
+        if self.backups == 0 {
+            return Ok(());
+        }
+
+        for n in (1..self.backups).rev() {
+            match rename(self.backup_path(n), self.backup_path(n + 1)).await {
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                result => result?,
+            }
+        }
+
+        match copy_atomically(&self.dir.join("current.json"), &self.backup_path(1)).await {
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+            Ok(_) => Ok(()),
+        }
     }
 
     async fn save<T: Serialize>(&self, name: &str, value: T) -> Result<(), BoxError> {
         create_dir_all(&self.dir).await?;
 
-        let contents = serde_json::to_string(&value)?;
-        let mut file = File::create(self.dir.join(name)).await?;
-        file.write_all(contents.as_bytes())
-            .await
-            .map_err(Into::into)
+        let contents = self.codec.serialize(&value)?;
+        let path = self.dir.join(name);
+        let temp_path = temp_path(&path);
+        let mut file = File::create(&temp_path).await?;
+        file.write_all(&contents).await?;
+        file.sync_all().await?;
+        rename(temp_path, path).await.map_err(Into::into)
     }
 
     async fn load<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>, BoxError> {
@@ -52,17 +149,33 @@ impl Disk {
             Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
             Err(err) => Err(err.into()),
             Ok(mut file) => {
-                let mut contents = String::new();
-                file.read_to_string(&mut contents).await?;
-                Ok(Some(serde_json::from_str(&contents)?))
+                let mut contents = Vec::new();
+                file.read_to_end(&mut contents).await?;
+                Ok(Some(self.codec.deserialize(contents)?))
             }
         }
     }
 }
 
+/// A sibling of the given file, to be written and then renamed over it. The process id is used to
+/// avoid clashing with other processes sharing the same directory.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(format!(".{}.tmp", process::id()));
+    path.with_file_name(name)
+}
+
+/// Like [`copy()`], but the destination is replaced at once
+async fn copy_atomically(from: &Path, to: &Path) -> io::Result<()> {
+    let temp_path = temp_path(to);
+    copy(from, &temp_path).await?;
+    rename(temp_path, to).await
+}
+
 #[async_trait]
-impl Persist for Disk {
+impl<C: JsonCodec> Persist for Disk<C> {
     async fn save_current(&self, value: &CurrentValues) -> Result<(), BoxError> {
+        self.rotate_backups().await?;
         self.save("current.json", value).await
     }
 
@@ -77,6 +190,48 @@ impl Persist for Disk {
     async fn load_history(&self, key: &str) -> Result<Option<ValueHistory>, BoxError> {
         self.load(&format!("history-{}.json", key)).await
     }
+
+    async fn save_pending(&self, value: &PendingChanges) -> Result<(), BoxError> {
+        self.save("pending.json", value).await
+    }
+
+    async fn load_pending(&self) -> Result<Option<PendingChanges>, BoxError> {
+        self.load("pending.json").await
+    }
+
+    fn subscribe_changes(&self) -> Option<ChangeStream> {
+        let interval = self.watch?;
+        let path = self.dir.join("current.json");
+        let (sender, receiver) = mpsc::channel(1);
+
+        tokio::spawn(async move {
+            let modified = |path| async move { metadata(path).await?.modified() };
+            let mut last_modified = modified(&path).await.ok();
+
+            // Stop once the receiver is dropped
+            while time::timeout(interval, sender.closed()).await.is_err() {
+                let new_modified = modified(&path).await.ok();
+                if new_modified != last_modified {
+                    last_modified = new_modified;
+                    // A full channel already has a pending notification
+                    let _ = sender.try_send(());
+                }
+            }
+        });
+
+        Some(Box::pin(ModifiedStream(receiver)))
+    }
+}
+
+/// The notifications of changes to `current.json`, for [`Disk::watch()`]
+struct ModifiedStream(mpsc::Receiver<()>);
+
+impl Stream for ModifiedStream {
+    type Item = ();
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<()>> {
+        self.0.poll_recv(cx)
+    }
 }
 
 #[cfg(test)]
@@ -89,4 +244,129 @@ mod tests {
         let dir = tempfile::TempDir::new().unwrap();
         test_persistence(Disk::new(dir.path())).await;
     }
+
+    #[tokio::test]
+    async fn custom_codec() {
+        /// A codec that goes through `serde_json::Value` and writes pretty-printed JSON, so that
+        /// its output differs from the default codec
+        struct PrettyValueCodec;
+
+        impl JsonCodec for PrettyValueCodec {
+            fn serialize<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, BoxError> {
+                let value = serde_json::to_value(value)?;
+                Ok(serde_json::to_vec_pretty(&value)?)
+            }
+
+            fn deserialize<T: DeserializeOwned>(&self, contents: Vec<u8>) -> Result<T, BoxError> {
+                let value: serde_json::Value = serde_json::from_slice(&contents)?;
+                Ok(serde_json::from_value(value)?)
+            }
+        }
+
+        let dir = tempfile::TempDir::new().unwrap();
+        test_persistence(Disk::new(dir.path()).with_codec(PrettyValueCodec)).await;
+
+        // The custom codec is used to write the files, that can still be read by the default one
+        let default_dir = tempfile::TempDir::new().unwrap();
+        let custom_dir = tempfile::TempDir::new().unwrap();
+        let default = Disk::new(default_dir.path());
+        let custom = Disk::new(custom_dir.path()).with_codec(PrettyValueCodec);
+        let history = ValueHistory {
+            entries: vec![HistoryEntry::new(
+                serde_json::json!({"a": [1, 2.5, null], "b": "c"}),
+                "overview".to_owned(),
+                chrono::Utc::now(),
+                "someone".to_owned(),
+            )],
+        };
+        default.save_history("key", &history).await.unwrap();
+        custom.save_history("key", &history).await.unwrap();
+        let read = |dir: &tempfile::TempDir| std::fs::read(dir.path().join("history-key.json"));
+        let default_contents = read(&default_dir).unwrap();
+        let custom_contents = read(&custom_dir).unwrap();
+        assert!(!default_contents.contains(&b'\n'));
+        assert!(custom_contents.contains(&b'\n'));
+        assert_ne!(default_contents, custom_contents);
+
+        let expected = Some(history);
+        assert_eq!(custom.load_history("key").await.unwrap(), expected);
+        let cross_read = Disk::new(custom_dir.path());
+        assert_eq!(cross_read.load_history("key").await.unwrap(), expected);
+    }
+
+    async fn next_change(changes: &mut ChangeStream) -> Result<Option<()>, time::error::Elapsed> {
+        let next = std::future::poll_fn(|cx| changes.as_mut().poll_next(cx));
+        time::timeout(Duration::from_secs(1), next).await
+    }
+
+    #[tokio::test]
+    async fn watch() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let disk = Disk::new(dir.path()).watch(Duration::from_millis(10));
+        let mut changes = disk.subscribe_changes().unwrap();
+        // Let the task read the initial state, then write from another instance
+        time::sleep(Duration::from_millis(50)).await;
+        Disk::new(dir.path())
+            .save_current(&CurrentValues {
+                version: 1,
+                date: chrono::Utc::now(),
+                feattles: Default::default(),
+                revision: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(next_change(&mut changes).await.unwrap(), Some(()));
+
+        // No other change
+        next_change(&mut changes).await.unwrap_err();
+
+        // Not watched by default
+        assert!(Disk::new(dir.path()).subscribe_changes().is_none());
+    }
+
+    #[tokio::test]
+    async fn backups() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let disk = Disk::new(dir.path()).backups(2);
+        let values = |version| CurrentValues {
+            version,
+            date: chrono::Utc::now(),
+            feattles: Default::default(),
+            revision: None,
+        };
+
+        assert!(!disk.recover_from_backup().await.unwrap());
+        disk.save_current(&values(1)).await.unwrap();
+        assert!(!dir.path().join("current.json.bak").exists());
+
+        disk.save_current(&values(2)).await.unwrap();
+        disk.save_current(&values(3)).await.unwrap();
+        disk.save_current(&values(4)).await.unwrap();
+        let read = |name: &str| {
+            let contents = std::fs::read(dir.path().join(name)).unwrap();
+            serde_json::from_slice::<CurrentValues>(&contents)
+                .unwrap()
+                .version
+        };
+        assert_eq!(read("current.json.bak"), 3);
+        assert_eq!(read("current.json.bak.2"), 2);
+        assert!(!dir.path().join("current.json.bak.3").exists());
+
+        // No temporary file is left behind
+        let mut names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            ["current.json", "current.json.bak", "current.json.bak.2"]
+        );
+
+        // Simulate a corrupted write
+        std::fs::write(dir.path().join("current.json"), "{").unwrap();
+        disk.load_current().await.unwrap_err();
+        assert!(disk.recover_from_backup().await.unwrap());
+        assert_eq!(disk.load_current().await.unwrap().unwrap().version, 3);
+    }
 }
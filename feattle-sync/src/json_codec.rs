@@ -0,0 +1,56 @@
+use feattle_core::BoxError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// The JSON (de)serialization used by the persistence layers of this crate, like [`crate::Disk`].
+///
+/// By default, [`SerdeJson`] is used. Implement this trait to plug a faster implementation, like
+/// the crate `simd-json`, and pass it with the method `with_codec()` of the persistence layer.
+/// Implementations must produce JSON that the other ones can read, so that the persisted data
+/// stays compatible.
+///
+/// # Example
+/// ```
+/// use feattle_core::BoxError;
+/// use feattle_sync::{Disk, JsonCodec};
+/// use serde::de::DeserializeOwned;
+/// use serde::Serialize;
+///
+/// #[derive(Debug, Clone)]
+/// struct MyCodec;
+///
+/// impl JsonCodec for MyCodec {
+///     fn serialize<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, BoxError> {
+///         // Call a faster implementation here
+///         Ok(serde_json::to_vec(value)?)
+///     }
+///
+///     fn deserialize<T: DeserializeOwned>(&self, contents: Vec<u8>) -> Result<T, BoxError> {
+///         Ok(serde_json::from_slice(&contents)?)
+///     }
+/// }
+///
+/// let persistence = Disk::new("some/local/directory").with_codec(MyCodec);
+/// ```
+pub trait JsonCodec: Send + Sync + 'static {
+    /// Serialize the value as compact JSON
+    fn serialize<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, BoxError>;
+
+    /// Deserialize the value from JSON. The buffer is given by value, so that implementations
+    /// that parse in place can reuse it.
+    fn deserialize<T: DeserializeOwned>(&self, contents: Vec<u8>) -> Result<T, BoxError>;
+}
+
+/// The default [`JsonCodec`], using the crate `serde_json`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SerdeJson;
+
+impl JsonCodec for SerdeJson {
+    fn serialize<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, BoxError> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn deserialize<T: DeserializeOwned>(&self, contents: Vec<u8>) -> Result<T, BoxError> {
+        Ok(serde_json::from_slice(&contents)?)
+    }
+}
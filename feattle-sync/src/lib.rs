@@ -6,33 +6,52 @@
 //! concrete implementations: [`Disk`] and [`S3`]. Please refer to the
 //! [main package - `feattle`](https://crates.io/crates/feattle) for more information.
 //!
-//! It also provides a simple way to poll the persistence layer for updates in [`BackgroundSync`].
+//! It also provides a simple way to poll the persistence layer for updates in [`BackgroundSync`],
+//! optionally protected by a [`CircuitBreaker`], and the adapter [`ShardedHistory`], that splits
+//! the history of each feattle by month on top of any persistence layer. The JSON
+//! (de)serialization done by the persistence layers of this crate can be replaced by a faster
+//! implementation with [`JsonCodec`].
 //!
 //! # Optional features
 //!
 //! - **aws_sdk_s3**: provides [`S3`] to integrate with AWS' S3 using the crate `aws-sdk-s3` crate
 //! - **rusoto_s3**: provides [`RusotoS3`] to integrate with AWS' S3 using the crate `rusoto` crate
+//! - **object_store**: provides [`ObjectStore`] to integrate with AWS' S3, Google Cloud Storage,
+//!   Azure Blob Storage and others using the crate `object_store`
 
 #[cfg(feature = "aws_sdk_s3")]
 mod aws_sdk_s3;
 mod background_sync;
+mod circuit_breaker;
 mod disk;
+mod json_codec;
+#[cfg(feature = "object_store")]
+mod object_store;
 #[cfg(feature = "rusoto_s3")]
 mod rusoto_s3;
+mod sharded_history;
 
+#[cfg(feature = "object_store")]
+pub use crate::object_store::*;
 #[cfg(feature = "aws_sdk_s3")]
 pub use aws_sdk_s3::*;
 pub use background_sync::*;
+pub use circuit_breaker::*;
 pub use disk::*;
+pub use json_codec::*;
 #[cfg(feature = "rusoto_s3")]
 pub use rusoto_s3::*;
+pub use sharded_history::*;
 
 #[cfg(test)]
 pub mod tests {
     use chrono::Utc;
     use serde_json::json;
 
-    use feattle_core::persist::{CurrentValue, CurrentValues, HistoryEntry, Persist, ValueHistory};
+    use feattle_core::persist::{
+        CurrentValue, CurrentValues, HistoryEntry, PendingChange, PendingChanges, Persist,
+        ValueHistory,
+    };
 
     pub async fn test_persistence<P: Persist>(persistence: P) {
         // Empty state
@@ -46,6 +65,7 @@ pub mod tests {
                 modified_at: Utc::now(),
                 modified_by: "someone".to_owned(),
                 value: json!(17i32),
+                staging: Some(json!(18i32)),
             },
         )]
         .into_iter()
@@ -54,6 +74,7 @@ pub mod tests {
             version: 17,
             date: Utc::now(),
             feattles,
+            revision: None,
         };
         persistence.save_current(&current_values).await.unwrap();
         assert_eq!(
@@ -63,12 +84,12 @@ pub mod tests {
 
         // Save history and check if correctly saved
         let history = ValueHistory {
-            entries: vec![HistoryEntry {
-                value: json!(17i32),
-                value_overview: "overview".to_owned(),
-                modified_at: Utc::now(),
-                modified_by: "someone else".to_owned(),
-            }],
+            entries: vec![HistoryEntry::new(
+                json!(17i32),
+                "overview".to_owned(),
+                Utc::now(),
+                "someone else".to_owned(),
+            )],
         };
         persistence.save_history("key", &history).await.unwrap();
         assert_eq!(
@@ -76,5 +97,19 @@ pub mod tests {
             Some(history)
         );
         assert_eq!(persistence.load_history("key2").await.unwrap(), None);
+
+        // Save pending changes and check if correctly saved
+        assert_eq!(persistence.load_pending().await.unwrap(), None);
+        let mut pending = PendingChanges::default();
+        pending.changes.insert(
+            "key".to_owned(),
+            PendingChange {
+                value: json!(18i32),
+                proposed_at: Utc::now(),
+                proposed_by: "someone".to_owned(),
+            },
+        );
+        persistence.save_pending(&pending).await.unwrap();
+        assert_eq!(persistence.load_pending().await.unwrap(), Some(pending));
     }
 }
@@ -0,0 +1,139 @@
+use crate::{JsonCodec, SerdeJson};
+use async_trait::async_trait;
+use feattle_core::persist::{CurrentValues, PendingChanges, Persist, ValueHistory};
+use feattle_core::BoxError;
+use object_store::path::Path;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::sync::Arc;
+
+/// Persist the data in any storage supported by the crate [`object_store`], like AWS S3, Google
+/// Cloud Storage, Azure Blob Storage or the local filesystem.
+///
+/// The storage is configured with the builders provided by [`object_store`] and the objects are
+/// stored with the same names used by the S3 backends, under the given prefix.
+///
+/// To use it, make sure to activate the cargo feature `"object_store"` in your `Cargo.toml`.
+///
+/// It does not support [`feattle_core::persist::CurrentValues::revision`], so concurrent writers
+/// may overwrite each other's changes: the last save wins.
+///
+/// # Example
+/// ```
+/// use std::sync::Arc;
+/// use feattle_core::{feattles, Feattles};
+/// use feattle_sync::ObjectStore;
+/// use object_store::memory::InMemory;
+///
+/// feattles! {
+///     struct MyToggles {
+///         a: bool,
+///     }
+/// }
+///
+/// // Use `object_store::aws::AmazonS3Builder` or similar to connect to a real storage
+/// let store = Arc::new(InMemory::new());
+/// let persistence = Arc::new(ObjectStore::new(store, "some/prefix/".to_owned()));
+/// let my_toggles = MyToggles::new(persistence);
+/// ```
+#[derive(Clone)]
+pub struct ObjectStore<C = SerdeJson> {
+    store: Arc<dyn object_store::ObjectStore>,
+    prefix: String,
+    codec: C,
+}
+
+impl<C> fmt::Debug for ObjectStore<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ObjectStore")
+            .field("store", &self.store.to_string())
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
+
+impl ObjectStore {
+    pub fn new(store: Arc<dyn object_store::ObjectStore>, prefix: String) -> Self {
+        ObjectStore {
+            store,
+            prefix,
+            codec: SerdeJson,
+        }
+    }
+}
+
+impl<C: JsonCodec> ObjectStore<C> {
+    /// Use another implementation to (de)serialize the data. Read more at [`JsonCodec`].
+    pub fn with_codec<C2: JsonCodec>(self, codec: C2) -> ObjectStore<C2> {
+        ObjectStore {
+            store: self.store,
+            prefix: self.prefix,
+            codec,
+        }
+    }
+
+    async fn save<T: Serialize>(&self, name: &str, value: T) -> Result<(), BoxError> {
+        let path = Path::from(format!("{}{}", self.prefix, name));
+        let contents = self.codec.serialize(&value)?;
+        self.store.put(&path, contents.into()).await?;
+        Ok(())
+    }
+
+    async fn load<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>, BoxError> {
+        let path = Path::from(format!("{}{}", self.prefix, name));
+        match self.store.get(&path).await {
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Ok(response) => {
+                let contents = response.bytes().await?.to_vec();
+                Ok(Some(self.codec.deserialize(contents)?))
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl<C: JsonCodec> Persist for ObjectStore<C> {
+    async fn save_current(&self, value: &CurrentValues) -> Result<(), BoxError> {
+        self.save("current.json", value).await
+    }
+
+    async fn load_current(&self) -> Result<Option<CurrentValues>, BoxError> {
+        self.load("current.json").await
+    }
+
+    async fn save_history(&self, key: &str, value: &ValueHistory) -> Result<(), BoxError> {
+        self.save(&format!("history-{}.json", key), value).await
+    }
+
+    async fn load_history(&self, key: &str) -> Result<Option<ValueHistory>, BoxError> {
+        self.load(&format!("history-{}.json", key)).await
+    }
+
+    async fn save_pending(&self, value: &PendingChanges) -> Result<(), BoxError> {
+        self.save("pending.json", value).await
+    }
+
+    async fn load_pending(&self) -> Result<Option<PendingChanges>, BoxError> {
+        self.load("pending.json").await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_persistence;
+    use object_store::memory::InMemory;
+
+    #[tokio::test]
+    async fn in_memory() {
+        let store = Arc::new(InMemory::new());
+        test_persistence(ObjectStore::new(store.clone(), "some/prefix/".to_owned())).await;
+
+        let path = Path::from("some/prefix/current.json");
+        assert!(object_store::ObjectStore::head(&*store, &path)
+            .await
+            .is_ok());
+    }
+}
@@ -1,5 +1,6 @@
+use crate::{JsonCodec, SerdeJson};
 use async_trait::async_trait;
-use feattle_core::persist::{CurrentValues, Persist, ValueHistory};
+use feattle_core::persist::{CurrentValues, PendingChanges, Persist, ValueHistory};
 use feattle_core::BoxError;
 use rusoto_core::RusotoError;
 use rusoto_s3::{GetObjectError, GetObjectRequest, PutObjectRequest, S3Client, S3};
@@ -14,6 +15,9 @@ use tokio::time;
 ///
 /// To use it, make sure to activate the cargo feature `"rusoto_s3"` in your `Cargo.toml`.
 ///
+/// It does not support [`feattle_core::persist::CurrentValues::revision`], so concurrent writers
+/// may overwrite each other's changes: the last save wins.
+///
 /// # Example
 /// ```
 /// use std::sync::Arc;
@@ -42,14 +46,15 @@ use tokio::time;
 /// let my_toggles = MyToggles::new(persistence);
 /// ```
 #[derive(Clone)]
-pub struct RusotoS3 {
+pub struct RusotoS3<C = SerdeJson> {
     client: S3Client,
     bucket: String,
     prefix: String,
     timeout: Duration,
+    codec: C,
 }
 
-impl fmt::Debug for RusotoS3 {
+impl<C> fmt::Debug for RusotoS3<C> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("S3")
             .field("client", &"S3Client")
@@ -66,14 +71,28 @@ impl RusotoS3 {
             bucket,
             prefix,
             timeout,
+            codec: SerdeJson,
+        }
+    }
+}
+
+impl<C: JsonCodec> RusotoS3<C> {
+    /// Use another implementation to (de)serialize the data. Read more at [`JsonCodec`].
+    pub fn with_codec<C2: JsonCodec>(self, codec: C2) -> RusotoS3<C2> {
+        RusotoS3 {
+            client: self.client,
+            bucket: self.bucket,
+            prefix: self.prefix,
+            timeout: self.timeout,
+            codec,
         }
     }
 
     async fn save<T: Serialize>(&self, name: &str, value: T) -> Result<(), BoxError> {
         let key = format!("{}{}", self.prefix, name);
-        let contents = serde_json::to_string(&value)?;
+        let contents = self.codec.serialize(&value)?;
         let put_future = self.client.put_object(PutObjectRequest {
-            body: Some(contents.into_bytes().into()),
+            body: Some(contents.into()),
             bucket: self.bucket.clone(),
             key,
             ..Default::default()
@@ -95,9 +114,9 @@ impl RusotoS3 {
             Ok(response) => match response.body {
                 None => Ok(None),
                 Some(body) => {
-                    let mut contents = String::new();
-                    body.into_async_read().read_to_string(&mut contents).await?;
-                    Ok(Some(serde_json::from_str(&contents)?))
+                    let mut contents = Vec::new();
+                    body.into_async_read().read_to_end(&mut contents).await?;
+                    Ok(Some(self.codec.deserialize(contents)?))
                 }
             },
             Err(error) => Err(error.into()),
@@ -106,7 +125,7 @@ impl RusotoS3 {
 }
 
 #[async_trait]
-impl Persist for RusotoS3 {
+impl<C: JsonCodec> Persist for RusotoS3<C> {
     async fn save_current(&self, value: &CurrentValues) -> Result<(), BoxError> {
         self.save("current.json", value).await
     }
@@ -122,6 +141,14 @@ impl Persist for RusotoS3 {
     async fn load_history(&self, key: &str) -> Result<Option<ValueHistory>, BoxError> {
         self.load(&format!("history-{}.json", key)).await
     }
+
+    async fn save_pending(&self, value: &PendingChanges) -> Result<(), BoxError> {
+        self.save("pending.json", value).await
+    }
+
+    async fn load_pending(&self) -> Result<Option<PendingChanges>, BoxError> {
+        self.load("pending.json").await
+    }
 }
 
 #[cfg(test)]
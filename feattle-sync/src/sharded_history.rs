@@ -0,0 +1,295 @@
+use async_trait::async_trait;
+use feattle_core::persist::*;
+use feattle_core::BoxError;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Wrap another persistence layer, splitting the history of each feattle into one object per
+/// month.
+///
+/// With a plain backend, the whole history of a feattle is stored in a single object, that grows
+/// without bounds and is fully rewritten at every change. With this adapter, only the shard of the
+/// current month is rewritten when a new entry is appended, bounding the cost of each write.
+///
+/// The history of the feattle `key` is stored by the inner persistence layer under the history
+/// keys `"{key}-{YYYYMM}"`, one for each month with changes, so that [`crate::Disk`] will write the
+/// files `history-{key}-{YYYYMM}.json`, for example. Since the inner layers have no way to list
+/// the stored objects, the months with changes are kept in an index, under the history key
+/// `"{key}-index"`. Feattle keys can't contain `-`, so these never collide with each other. The
+/// current values are stored by the inner layer as usual.
+///
+/// When a new entry is appended by [`feattle_core::Feattles::update()`], only the index and the
+/// latest shard are read, with [`Persist::load_history_tail()`]. The whole history is only read by
+/// [`Persist::load_history()`], to show it.
+///
+/// Note that history written by the inner layer directly, before it was wrapped by this adapter,
+/// will not be read.
+///
+/// # Example
+/// ```
+/// use std::sync::Arc;
+/// use feattle_core::{feattles, Feattles};
+/// use feattle_sync::{Disk, ShardedHistory};
+///
+/// feattles! {
+///     struct MyToggles {
+///         a: bool,
+///     }
+/// }
+///
+/// let persistence = ShardedHistory::new(Disk::new("some/local/directory"));
+/// let my_toggles = MyToggles::new(Arc::new(persistence));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ShardedHistory<P> {
+    inner: P,
+}
+
+impl<P: Persist> ShardedHistory<P> {
+    /// Wrap the given persistence layer, that will store the shards
+    pub fn new(inner: P) -> Self {
+        ShardedHistory { inner }
+    }
+
+    /// Return a reference to the wrapped persistence layer
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    async fn load_index(&self, key: &str) -> Result<BTreeSet<String>, BoxError> {
+        let history = self.inner.load_history(&index_key(key)).await?;
+        match history.and_then(|history| history.entries.into_iter().next_back()) {
+            None => Ok(BTreeSet::new()),
+            Some(entry) => Ok(serde_json::from_value::<ShardIndex>(entry.value)?.shards),
+        }
+    }
+
+    /// Save the index, attributing it to the history entry that created the last shard
+    async fn save_index(
+        &self,
+        key: &str,
+        shards: BTreeSet<String>,
+        created_by: &HistoryEntry,
+    ) -> Result<(), BoxError> {
+        let index = ShardIndex { shards };
+        let entry = HistoryEntry::new(
+            serde_json::to_value(&index)?,
+            format!("{} shards", index.shards.len()),
+            created_by.modified_at,
+            created_by.modified_by.clone(),
+        );
+        let history = ValueHistory {
+            entries: vec![entry],
+        };
+        self.inner.save_history(&index_key(key), &history).await
+    }
+
+    async fn load_shard(&self, key: &str, shard: &str) -> Result<Option<ValueHistory>, BoxError> {
+        self.inner.load_history(&shard_key(key, shard)).await
+    }
+}
+
+/// The months with changes of a feattle, in the format `YYYYMM`. Since the inner layer can only
+/// store history, it's saved as the value of a single history entry.
+#[derive(Debug, Serialize, Deserialize)]
+struct ShardIndex {
+    shards: BTreeSet<String>,
+}
+
+/// The history key of the index of the given feattle
+fn index_key(key: &str) -> String {
+    format!("{}-index", key)
+}
+
+/// The history key of one shard, named after its month, of the given feattle
+fn shard_key(key: &str, shard: &str) -> String {
+    format!("{}-{}", key, shard)
+}
+
+#[async_trait]
+impl<P: Persist> Persist for ShardedHistory<P> {
+    async fn save_current(&self, value: &CurrentValues) -> Result<(), BoxError> {
+        self.inner.save_current(value).await
+    }
+
+    async fn save_current_with_revision(
+        &self,
+        value: &CurrentValues,
+    ) -> Result<Option<String>, BoxError> {
+        self.inner.save_current_with_revision(value).await
+    }
+
+    async fn load_current(&self) -> Result<Option<CurrentValues>, BoxError> {
+        self.inner.load_current().await
+    }
+
+    async fn save_history(&self, key: &str, value: &ValueHistory) -> Result<(), BoxError> {
+        let mut shards: BTreeMap<String, ValueHistory> = BTreeMap::new();
+        for entry in &value.entries {
+            let shard = entry.modified_at.format("%Y%m").to_string();
+            shards.entry(shard).or_default().entries.push(entry.clone());
+        }
+
+        // History is append-only, so older shards are considered final and are not rewritten, and
+        // `value` may only hold the tail returned by `load_history_tail()`. The latest known shard
+        // is always rewritten, so that a rollback of the last entry is honored.
+        let index = self.load_index(key).await?;
+        let last_indexed = index.iter().next_back().cloned();
+        let mut to_write: BTreeSet<_> = shards
+            .keys()
+            .filter(|&shard| !index.contains(shard) || Some(shard) >= last_indexed.as_ref())
+            .cloned()
+            .collect();
+        to_write.extend(last_indexed);
+
+        for shard in &to_write {
+            let history = shards.remove(shard).unwrap_or_default();
+            self.inner
+                .save_history(&shard_key(key, shard), &history)
+                .await?;
+        }
+
+        if let Some(last_entry) = value.entries.last() {
+            if !to_write.is_subset(&index) {
+                let new_index = index.union(&to_write).cloned().collect();
+                self.save_index(key, new_index, last_entry).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn load_history(&self, key: &str) -> Result<Option<ValueHistory>, BoxError> {
+        let index = self.load_index(key).await?;
+        if index.is_empty() {
+            return Ok(None);
+        }
+
+        let mut history = ValueHistory::default();
+        for shard in index {
+            if let Some(shard) = self.load_shard(key, &shard).await? {
+                history.entries.extend(shard.entries);
+            }
+        }
+        Ok(Some(history))
+    }
+
+    async fn load_history_tail(&self, key: &str) -> Result<Option<ValueHistory>, BoxError> {
+        let index = self.load_index(key).await?;
+        if index.is_empty() {
+            return Ok(None);
+        }
+
+        // The latest shard may be empty after a rollback, so look for the latest entries, that
+        // are needed to chain the next ones
+        for shard in index.iter().rev() {
+            if let Some(shard) = self.load_shard(key, shard).await? {
+                if !shard.entries.is_empty() {
+                    return Ok(Some(shard));
+                }
+            }
+        }
+        Ok(Some(ValueHistory::default()))
+    }
+
+    async fn save_pending(&self, value: &PendingChanges) -> Result<(), BoxError> {
+        self.inner.save_pending(value).await
+    }
+
+    async fn load_pending(&self) -> Result<Option<PendingChanges>, BoxError> {
+        self.inner.load_pending().await
+    }
+
+    fn subscribe_changes(&self) -> Option<ChangeStream> {
+        self.inner.subscribe_changes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_persistence;
+    use crate::Disk;
+    use chrono::{DateTime, Utc};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn sharded_history() {
+        let dir = tempfile::TempDir::new().unwrap();
+        test_persistence(ShardedHistory::new(Disk::new(dir.path()))).await;
+    }
+
+    #[tokio::test]
+    async fn shards_by_month() {
+        fn entry(value: i32, modified_at: &str) -> HistoryEntry {
+            HistoryEntry::new(
+                json!(value),
+                value.to_string(),
+                modified_at.parse::<DateTime<Utc>>().unwrap(),
+                "someone".to_owned(),
+            )
+        }
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let persistence = ShardedHistory::new(Disk::new(dir.path()));
+
+        let mut history = ValueHistory {
+            entries: vec![entry(1, "2024-01-10T10:00:00Z")],
+        };
+        persistence.save_history("key", &history).await.unwrap();
+        history.entries.push(entry(2, "2024-02-20T10:00:00Z"));
+        persistence.save_history("key", &history).await.unwrap();
+
+        let jan = persistence
+            .inner()
+            .load_history("key-202401")
+            .await
+            .unwrap();
+        assert_eq!(jan.unwrap().entries, history.entries[..1]);
+        let feb = persistence
+            .inner()
+            .load_history("key-202402")
+            .await
+            .unwrap();
+        assert_eq!(feb.unwrap().entries, history.entries[1..]);
+        assert!(dir.path().join("history-key-202401.json").exists());
+        assert!(dir.path().join("history-key-202402.json").exists());
+        assert!(dir.path().join("history-key-index.json").exists());
+
+        assert_eq!(
+            persistence.load_history("key").await.unwrap(),
+            Some(history.clone())
+        );
+        assert_eq!(
+            persistence
+                .load_history_tail("key")
+                .await
+                .unwrap()
+                .unwrap()
+                .entries,
+            history.entries[1..]
+        );
+
+        // Appending to the tail keeps the older shards
+        let mut tail = persistence.load_history_tail("key").await.unwrap().unwrap();
+        tail.entries.push(entry(3, "2024-02-21T10:00:00Z"));
+        persistence.save_history("key", &tail).await.unwrap();
+        history.entries.push(entry(3, "2024-02-21T10:00:00Z"));
+        assert_eq!(
+            persistence.load_history("key").await.unwrap(),
+            Some(history.clone())
+        );
+
+        // Rolling back the last entries empties their shard, so the tail is the previous one
+        history.entries.truncate(1);
+        persistence.save_history("key", &history).await.unwrap();
+        assert_eq!(
+            persistence.load_history("key").await.unwrap(),
+            Some(history.clone())
+        );
+        assert_eq!(
+            persistence.load_history_tail("key").await.unwrap(),
+            Some(history)
+        );
+    }
+}
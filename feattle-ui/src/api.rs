@@ -1,19 +1,63 @@
 //! Describes the schema of the JSON API
+use chrono::{DateTime, Utc};
 use feattle_core::last_reload::LastReload;
-use feattle_core::persist::ValueHistory;
+use feattle_core::persist::{CurrentValues, HistoryEntry, PendingChange, ValueHistory};
 use feattle_core::FeattleDefinition;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::BTreeMap;
 
 /// The first version of the API. This is still unstable while this crate is in `0.x`
 pub mod v1 {
     use super::*;
 
+    /// Select a page of the list of feattles. Pages start at `1` and have at most
+    /// [`ListFeattlesQuery::MAX_PER_PAGE`] feattles. Without `per_page`, all the feattles are
+    /// listed in a single page, unless there are more than `MAX_PER_PAGE` of them: the remaining
+    /// ones are then in the next pages, as reported by [`ListFeattlesResponse::page_count`].
+    ///
+    /// If `tag` is given, only the feattles declared with that tag are listed.
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    pub struct ListFeattlesQuery {
+        #[serde(default)]
+        pub page: Option<usize>,
+        #[serde(default)]
+        pub per_page: Option<usize>,
+        #[serde(default)]
+        pub tag: Option<String>,
+    }
+
+    impl ListFeattlesQuery {
+        pub const MAX_PER_PAGE: usize = 1000;
+    }
+
     #[derive(Debug, Clone, Serialize)]
     pub struct ListFeattlesResponse {
+        /// The feattles in the requested page
         pub definitions: Vec<FeattleDefinition>,
         pub last_reload: LastReload,
         pub reload_failed: bool,
+        /// The total number of feattles, in all pages
+        pub total: usize,
+        pub page: usize,
+        pub per_page: usize,
+        /// The number of pages, that may be more than one even if `per_page` was not requested
+        pub page_count: usize,
+        /// The tag used to filter the feattles, if any
+        pub tag: Option<String>,
+    }
+
+    /// The version of the current values and when they were last reloaded, as described by
+    /// [`LastReload`]. The fields are `None` if that information is not available.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct VersionResponse {
+        pub version: Option<i32>,
+        pub version_date: Option<DateTime<Utc>>,
+        pub reload_date: Option<DateTime<Utc>>,
+        pub reload_failed: bool,
+        /// The keys whose value in memory differs from the persisted one, as returned by
+        /// [`feattle_core::Feattles::persisted_vs_memory_divergence()`]
+        pub diverging_keys: Vec<String>,
     }
 
     #[derive(Debug, Clone, Serialize)]
@@ -22,14 +66,157 @@ pub mod v1 {
         pub history: ValueHistory,
         pub last_reload: LastReload,
         pub reload_failed: bool,
+        /// The change waiting for approval, if any
+        pub pending: Option<PendingChange>,
     }
 
     #[derive(Debug, Clone, Deserialize)]
     pub struct EditFeattleRequest {
         pub value: Value,
+        /// The user, that is ignored when [`crate::AdminPanel::user_header()`] is set
+        #[serde(default)]
         pub modified_by: String,
     }
 
     #[derive(Debug, Clone, Serialize)]
     pub struct EditFeattleResponse {}
+
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct ProposeFeattleRequest {
+        pub value: Value,
+        /// The user, that is ignored when [`crate::AdminPanel::user_header()`] is set
+        #[serde(default)]
+        pub proposed_by: String,
+    }
+
+    #[derive(Debug, Clone, Serialize)]
+    pub struct ProposeFeattleResponse {}
+
+    /// Approve or reject the pending change of a feattle
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct ReviewFeattleRequest {
+        /// The user, that is ignored when [`crate::AdminPanel::user_header()`] is set
+        #[serde(default)]
+        pub reviewed_by: String,
+    }
+
+    #[derive(Debug, Clone, Serialize)]
+    pub struct ReviewFeattleResponse {}
+
+    /// Set or remove (with `null`) the staging value of a feattle
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct StagingFeattleRequest {
+        pub value: Option<Value>,
+        /// The user, that is ignored when [`crate::AdminPanel::user_header()`] is set
+        #[serde(default)]
+        pub modified_by: String,
+    }
+
+    #[derive(Debug, Clone, Serialize)]
+    pub struct StagingFeattleResponse {}
+
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct PromoteFeattleRequest {
+        /// The user, that is ignored when [`crate::AdminPanel::user_header()`] is set
+        #[serde(default)]
+        pub modified_by: String,
+    }
+
+    #[derive(Debug, Clone, Serialize)]
+    pub struct PromoteFeattleResponse {}
+
+    /// The context in which to evaluate a feattle. When `at` is not given, the current time is
+    /// used.
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct EvaluateFeattleRequest {
+        #[serde(default)]
+        pub user_key: Option<String>,
+        #[serde(default)]
+        pub at: Option<DateTime<Utc>>,
+    }
+
+    #[derive(Debug, Clone, Serialize)]
+    pub struct EvaluateFeattleResponse {
+        pub value: Value,
+        pub reload_failed: bool,
+    }
+
+    /// Select two entries of the history of a feattle, by their position: `0` is the oldest one.
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct DiffFeattleQuery {
+        pub from: usize,
+        pub to: usize,
+    }
+
+    #[derive(Debug, Clone, Serialize)]
+    pub struct DiffFeattleResponse {
+        pub from: HistoryEntry,
+        pub to: HistoryEntry,
+        pub changes: Vec<JsonChange>,
+    }
+
+    /// Select the user whose changes are listed. The user must match exactly.
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct ChangesByUserQuery {
+        pub user: String,
+    }
+
+    #[derive(Debug, Clone, Serialize)]
+    pub struct ChangesByUserResponse {
+        /// The history entries created by the user for each feattle, from the oldest to the most
+        /// recent. Feattles that were never modified by the user are absent.
+        pub changes: BTreeMap<String, Vec<HistoryEntry>>,
+    }
+
+    /// The value of each feattle in this environment and in the other one, configured with
+    /// [`crate::AdminPanel::compare_with()`].
+    #[derive(Debug, Clone, Serialize)]
+    pub struct CompareResponse {
+        /// The label of the other environment
+        pub other_label: String,
+        pub feattles: Vec<CompareEntry>,
+        pub reload_failed: bool,
+    }
+
+    /// The value of a single feattle in both environments.
+    ///
+    /// `value` is the current value in this environment, or `None` if the key is only persisted in
+    /// the other one. `other_value` is the value persisted in the other environment, or the
+    /// default value if it was never modified there.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct CompareEntry {
+        pub key: String,
+        pub value: Option<Value>,
+        pub other_value: Value,
+        pub different: bool,
+        /// The changes from `value` to `other_value`
+        pub changes: Vec<JsonChange>,
+    }
+
+    /// All the persisted data: the current values and the history of each feattle.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct FullBackup {
+        pub current_values: Option<CurrentValues>,
+        pub histories: BTreeMap<String, ValueHistory>,
+    }
+
+    /// A single difference between two JSON values. The `path` is a
+    /// [JSON Pointer](https://datatracker.ietf.org/doc/html/rfc6901) to the changed location.
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    #[serde(tag = "kind")]
+    pub enum JsonChange {
+        Added {
+            path: String,
+            value: Value,
+        },
+        Removed {
+            path: String,
+            value: Value,
+        },
+        Changed {
+            path: String,
+            old: Value,
+            new: Value,
+        },
+    }
 }
@@ -1,12 +1,15 @@
 use crate::api::v1;
+use crate::request_log::{log_request, ModifiedBy};
 use crate::{AdminPanel, RenderError, RenderedPage};
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
 use axum::response::{IntoResponse, Redirect, Response};
-use axum::{routing, Form, Json, Router};
+use axum::{routing, Extension, Form, Json, Router};
 use feattle_core::{Feattles, UpdateError};
 use serde::Deserialize;
 use std::sync::Arc;
+use std::time::Instant;
 
 #[derive(Debug, Deserialize)]
 struct EditFeattleForm {
@@ -18,9 +21,24 @@ struct EditFeattleForm {
 /// To use it, make sure to activate the cargo feature `"axum"` in your `Cargo.toml`.
 ///
 /// The router will answer to the web UI under "/" and a JSON API under "/api/v1/" (see more at [`v1`]):
-/// - GET /api/v1/feattles
+/// - GET /api/v1/feattles?page={page}&per_page={per_page}&tag={tag}
+/// - GET /api/v1/version
+/// - GET /api/v1/compare
+/// - GET /api/v1/export.env
+/// - GET /api/v1/changes?user={user}
 /// - GET /api/v1/feattle/{key}
 /// - POST /api/v1/feattle/{key}
+/// - POST /api/v1/feattle/{key}/propose
+/// - POST /api/v1/feattle/{key}/approve
+/// - POST /api/v1/feattle/{key}/reject
+/// - POST /api/v1/feattle/{key}/staging
+/// - POST /api/v1/feattle/{key}/promote
+/// - GET /api/v1/feattle/{key}/diff?from={from}&to={to}
+/// - POST /api/v1/feattle/{key}/evaluate
+///
+/// Since this returns the router, the server is run by the caller. Use
+/// `axum::serve(..).with_graceful_shutdown(signal)` to drain the in-flight requests when shutting
+/// down, as shown below.
 ///
 /// # Example
 /// ```no_run
@@ -45,7 +63,13 @@ struct EditFeattleForm {
 /// let router = axum_router(admin_panel);
 ///
 /// let listener = TcpListener::bind(("127.0.0.1", 3031)).await?;
-/// tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+/// tokio::spawn(
+///     axum::serve(listener, router.into_make_service())
+///         .with_graceful_shutdown(async {
+///             tokio::signal::ctrl_c().await.ok();
+///         })
+///         .into_future(),
+/// );
 ///
 /// # Ok(())
 /// # }
@@ -56,14 +80,50 @@ where
 {
     async fn list_feattles<F: Feattles + Sync>(
         State(admin_panel): State<Arc<AdminPanel<F>>>,
+        Query(query): Query<v1::ListFeattlesQuery>,
     ) -> impl IntoResponse {
-        admin_panel.list_feattles().await
+        admin_panel.list_feattles(query).await
     }
 
     async fn list_feattles_api_v1<F: Feattles + Sync>(
         State(admin_panel): State<Arc<AdminPanel<F>>>,
+        Query(query): Query<v1::ListFeattlesQuery>,
+    ) -> impl IntoResponse {
+        admin_panel.list_feattles_api_v1(query).await.map(Json)
+    }
+
+    async fn compare<F: Feattles + Sync>(
+        State(admin_panel): State<Arc<AdminPanel<F>>>,
+    ) -> impl IntoResponse {
+        admin_panel.compare().await
+    }
+
+    async fn compare_api_v1<F: Feattles + Sync>(
+        State(admin_panel): State<Arc<AdminPanel<F>>>,
+    ) -> impl IntoResponse {
+        admin_panel.compare_api_v1().await.map(Json)
+    }
+
+    async fn version_api_v1<F: Feattles + Sync>(
+        State(admin_panel): State<Arc<AdminPanel<F>>>,
+    ) -> impl IntoResponse {
+        admin_panel.version_api_v1().await.map(Json)
+    }
+
+    async fn changes_by_user_api_v1<F: Feattles + Sync>(
+        State(admin_panel): State<Arc<AdminPanel<F>>>,
+        Query(query): Query<v1::ChangesByUserQuery>,
     ) -> impl IntoResponse {
-        admin_panel.list_feattles_api_v1().await.map(Json)
+        admin_panel
+            .changes_by_user_api_v1(&query.user)
+            .await
+            .map(Json)
+    }
+
+    async fn export_env<F: Feattles + Sync>(
+        State(admin_panel): State<Arc<AdminPanel<F>>>,
+    ) -> impl IntoResponse {
+        admin_panel.export_env().await
     }
 
     async fn show_feattle<F: Feattles + Sync>(
@@ -83,21 +143,150 @@ where
     async fn edit_feattle<F: Feattles + Sync>(
         State(admin_panel): State<Arc<AdminPanel<F>>>,
         Path(key): Path<String>,
+        headers: HeaderMap,
         Form(form): Form<EditFeattleForm>,
-    ) -> impl IntoResponse {
-        admin_panel
-            .edit_feattle(&key, &form.value_json, "admin".to_owned())
+    ) -> Result<impl IntoResponse, RenderError> {
+        let modified_by = admin_panel.request_user(header(&headers), "admin".to_owned())?;
+        let result = admin_panel
+            .edit_feattle(&key, &form.value_json, modified_by.clone())
             .await
-            .map(|_| Redirect::to("/"))
+            .map(|_| Redirect::to("/"));
+        Ok((Extension(ModifiedBy(modified_by)), result))
     }
 
     async fn edit_feattle_api_v1<F: Feattles + Sync>(
         State(admin_panel): State<Arc<AdminPanel<F>>>,
         Path(key): Path<String>,
-        Json(request): Json<v1::EditFeattleRequest>,
+        headers: HeaderMap,
+        Json(mut request): Json<v1::EditFeattleRequest>,
+    ) -> Result<impl IntoResponse, RenderError> {
+        request.modified_by = admin_panel.request_user(header(&headers), request.modified_by)?;
+        let modified_by = ModifiedBy(request.modified_by.clone());
+        let result = admin_panel
+            .edit_feattle_api_v1(&key, request)
+            .await
+            .map(Json);
+        Ok((Extension(modified_by), result))
+    }
+
+    async fn approve_feattle<F: Feattles + Sync>(
+        State(admin_panel): State<Arc<AdminPanel<F>>>,
+        Path(key): Path<String>,
+        headers: HeaderMap,
+    ) -> Result<impl IntoResponse, RenderError> {
+        let approved_by = admin_panel.request_user(header(&headers), "admin".to_owned())?;
+        let result = admin_panel
+            .approve_feattle(&key, approved_by.clone())
+            .await
+            .map(|_| Redirect::to("/"));
+        Ok((Extension(ModifiedBy(approved_by)), result))
+    }
+
+    async fn reject_feattle<F: Feattles + Sync>(
+        State(admin_panel): State<Arc<AdminPanel<F>>>,
+        Path(key): Path<String>,
+        headers: HeaderMap,
+    ) -> Result<impl IntoResponse, RenderError> {
+        let rejected_by = admin_panel.request_user(header(&headers), "admin".to_owned())?;
+        admin_panel.reject_feattle(&key, rejected_by).await?;
+        Ok(Redirect::to("/"))
+    }
+
+    async fn promote_feattle<F: Feattles + Sync>(
+        State(admin_panel): State<Arc<AdminPanel<F>>>,
+        Path(key): Path<String>,
+        headers: HeaderMap,
+    ) -> Result<impl IntoResponse, RenderError> {
+        let modified_by = admin_panel.request_user(header(&headers), "admin".to_owned())?;
+        let result = admin_panel
+            .promote_feattle(&key, modified_by.clone())
+            .await
+            .map(|_| Redirect::to("/"));
+        Ok((Extension(ModifiedBy(modified_by)), result))
+    }
+
+    async fn staging_feattle_api_v1<F: Feattles + Sync>(
+        State(admin_panel): State<Arc<AdminPanel<F>>>,
+        Path(key): Path<String>,
+        headers: HeaderMap,
+        Json(mut request): Json<v1::StagingFeattleRequest>,
+    ) -> Result<impl IntoResponse, RenderError> {
+        request.modified_by = admin_panel.request_user(header(&headers), request.modified_by)?;
+        let modified_by = ModifiedBy(request.modified_by.clone());
+        let result = admin_panel
+            .staging_feattle_api_v1(&key, request)
+            .await
+            .map(Json);
+        Ok((Extension(modified_by), result))
+    }
+
+    async fn promote_feattle_api_v1<F: Feattles + Sync>(
+        State(admin_panel): State<Arc<AdminPanel<F>>>,
+        Path(key): Path<String>,
+        headers: HeaderMap,
+        Json(mut request): Json<v1::PromoteFeattleRequest>,
+    ) -> Result<impl IntoResponse, RenderError> {
+        request.modified_by = admin_panel.request_user(header(&headers), request.modified_by)?;
+        let modified_by = ModifiedBy(request.modified_by.clone());
+        let result = admin_panel
+            .promote_feattle_api_v1(&key, request)
+            .await
+            .map(Json);
+        Ok((Extension(modified_by), result))
+    }
+
+    async fn propose_feattle_api_v1<F: Feattles + Sync>(
+        State(admin_panel): State<Arc<AdminPanel<F>>>,
+        Path(key): Path<String>,
+        headers: HeaderMap,
+        Json(mut request): Json<v1::ProposeFeattleRequest>,
+    ) -> Result<impl IntoResponse, RenderError> {
+        request.proposed_by = admin_panel.request_user(header(&headers), request.proposed_by)?;
+        let response = admin_panel.propose_feattle_api_v1(&key, request).await?;
+        Ok(Json(response))
+    }
+
+    async fn approve_feattle_api_v1<F: Feattles + Sync>(
+        State(admin_panel): State<Arc<AdminPanel<F>>>,
+        Path(key): Path<String>,
+        headers: HeaderMap,
+        Json(mut request): Json<v1::ReviewFeattleRequest>,
+    ) -> Result<impl IntoResponse, RenderError> {
+        request.reviewed_by = admin_panel.request_user(header(&headers), request.reviewed_by)?;
+        let approved_by = ModifiedBy(request.reviewed_by.clone());
+        let result = admin_panel
+            .approve_feattle_api_v1(&key, request)
+            .await
+            .map(Json);
+        Ok((Extension(approved_by), result))
+    }
+
+    async fn reject_feattle_api_v1<F: Feattles + Sync>(
+        State(admin_panel): State<Arc<AdminPanel<F>>>,
+        Path(key): Path<String>,
+        headers: HeaderMap,
+        Json(mut request): Json<v1::ReviewFeattleRequest>,
+    ) -> Result<impl IntoResponse, RenderError> {
+        request.reviewed_by = admin_panel.request_user(header(&headers), request.reviewed_by)?;
+        let response = admin_panel.reject_feattle_api_v1(&key, request).await?;
+        Ok(Json(response))
+    }
+
+    async fn diff_feattle_api_v1<F: Feattles + Sync>(
+        State(admin_panel): State<Arc<AdminPanel<F>>>,
+        Path(key): Path<String>,
+        Query(query): Query<v1::DiffFeattleQuery>,
+    ) -> impl IntoResponse {
+        admin_panel.diff_feattle_api_v1(&key, query).await.map(Json)
+    }
+
+    async fn evaluate_feattle_api_v1<F: Feattles + Sync>(
+        State(admin_panel): State<Arc<AdminPanel<F>>>,
+        Path(key): Path<String>,
+        Json(request): Json<v1::EvaluateFeattleRequest>,
     ) -> impl IntoResponse {
         admin_panel
-            .edit_feattle_api_v1(&key, request)
+            .evaluate_feattle_api_v1(&key, request)
             .await
             .map(Json)
     }
@@ -109,15 +298,81 @@ where
         admin_panel.render_public_file(&file_name)
     }
 
-    Router::new()
+    let request_logging = admin_panel.request_logging;
+    let router = Router::new()
         .route("/", routing::get(list_feattles))
         .route("/api/v1/feattles", routing::get(list_feattles_api_v1))
+        .route("/api/v1/version", routing::get(version_api_v1))
+        .route("/compare", routing::get(compare))
+        .route("/api/v1/compare", routing::get(compare_api_v1))
+        .route("/api/v1/export.env", routing::get(export_env))
+        .route("/api/v1/changes", routing::get(changes_by_user_api_v1))
         .route("/feattle/:key", routing::get(show_feattle))
         .route("/api/v1/feattle/:key", routing::get(show_feattle_api_v1))
         .route("/feattle/:key/edit", routing::post(edit_feattle))
         .route("/api/v1/feattle/:key", routing::post(edit_feattle_api_v1))
+        .route("/feattle/:key/approve", routing::post(approve_feattle))
+        .route("/feattle/:key/reject", routing::post(reject_feattle))
+        .route("/feattle/:key/promote", routing::post(promote_feattle))
+        .route(
+            "/api/v1/feattle/:key/propose",
+            routing::post(propose_feattle_api_v1),
+        )
+        .route(
+            "/api/v1/feattle/:key/approve",
+            routing::post(approve_feattle_api_v1),
+        )
+        .route(
+            "/api/v1/feattle/:key/reject",
+            routing::post(reject_feattle_api_v1),
+        )
+        .route(
+            "/api/v1/feattle/:key/staging",
+            routing::post(staging_feattle_api_v1),
+        )
+        .route(
+            "/api/v1/feattle/:key/promote",
+            routing::post(promote_feattle_api_v1),
+        )
+        .route(
+            "/api/v1/feattle/:key/diff",
+            routing::get(diff_feattle_api_v1),
+        )
+        .route(
+            "/api/v1/feattle/:key/evaluate",
+            routing::post(evaluate_feattle_api_v1),
+        )
         .route("/public/:file_name", routing::get(render_public_file))
-        .with_state(admin_panel)
+        .with_state(admin_panel);
+
+    if request_logging {
+        router.layer(middleware::from_fn(log_requests))
+    } else {
+        router
+    }
+}
+
+/// Read a header of the request, as expected by [`AdminPanel::request_user()`]
+fn header(headers: &HeaderMap) -> impl FnOnce(&str) -> Option<String> + '_ {
+    move |name| Some(headers.get(name)?.to_str().ok()?.to_owned())
+}
+
+/// Log the request with [`log_request()`], like the warp server does, as explained in
+/// [`AdminPanel::request_logging()`]
+async fn log_requests(request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let modified_by = response.extensions().get::<ModifiedBy>();
+    log_request(
+        method.as_str(),
+        &path,
+        response.status().as_u16(),
+        start.elapsed(),
+        modified_by.map(|modified_by| modified_by.0.as_str()),
+    );
+    response
 }
 
 impl IntoResponse for RenderedPage {
@@ -129,9 +384,36 @@ impl IntoResponse for RenderedPage {
 impl IntoResponse for RenderError {
     fn into_response(self) -> Response {
         match self {
-            RenderError::NotFound | RenderError::Update(UpdateError::UnknownKey(_)) => {
+            RenderError::NotFound
+            | RenderError::Update(UpdateError::UnknownKey(_))
+            | RenderError::Update(UpdateError::NoPendingChange(_))
+            | RenderError::Update(UpdateError::NoStagingValue(_)) => {
                 StatusCode::NOT_FOUND.into_response()
             }
+            RenderError::Update(UpdateError::SelfApproval(key)) => (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "The pending change of {} must be approved by another user",
+                    key
+                ),
+            )
+                .into_response(),
+            RenderError::Unauthenticated => StatusCode::UNAUTHORIZED.into_response(),
+            RenderError::Update(UpdateError::ApprovalRequired(key)) => (
+                StatusCode::FORBIDDEN,
+                format!("The change of {} must be proposed and approved", key),
+            )
+                .into_response(),
+            RenderError::Update(UpdateError::ReadOnly(key)) => (
+                StatusCode::BAD_REQUEST,
+                format!("The key {} is derived and can't be updated", key),
+            )
+                .into_response(),
+            RenderError::Update(UpdateError::Rejected(reason)) => (
+                StatusCode::BAD_REQUEST,
+                format!("The update was rejected: {}", reason),
+            )
+                .into_response(),
             RenderError::Update(UpdateError::Parsing(err)) => (
                 StatusCode::BAD_REQUEST,
                 format!("Failed to parse: {:?}", err),
@@ -144,3 +426,108 @@ impl IntoResponse for RenderError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use feattle_core::feattles;
+    use feattle_core::persist::{InMemoryPersistence, NoPersistence};
+    use log::{Log, Metadata, Record};
+    use std::sync::Mutex;
+    use tower::ServiceExt;
+
+    static ACCESS_LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct AccessLogger;
+
+    impl Log for AccessLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.target() == "feattle_ui::access"
+        }
+
+        fn log(&self, record: &Record) {
+            if self.enabled(record.metadata()) {
+                ACCESS_LOGS.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    feattles! {
+        struct MyToggles { a: bool }
+    }
+
+    #[tokio::test]
+    async fn request_logging() {
+        log::set_logger(&AccessLogger).unwrap();
+        log::set_max_level(log::LevelFilter::Info);
+
+        let my_toggles = Arc::new(MyToggles::new(Arc::new(NoPersistence)));
+        let mut admin_panel = AdminPanel::new(my_toggles, "Project Panda - DEV".to_owned());
+        admin_panel.request_logging(true);
+        let router = axum_router(Arc::new(admin_panel));
+
+        let request = Request::post("/api/v1/feattle/a")
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"{"value": true, "modified_by": "somebody"}"#))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = Request::get("/api/v1/feattles")
+            .body(Body::empty())
+            .unwrap();
+        router.oneshot(request).await.unwrap();
+
+        let logs = ACCESS_LOGS.lock().unwrap();
+        assert_eq!(logs.len(), 2);
+        assert!(logs[0].starts_with("method=POST path=/api/v1/feattle/a status=200 duration_ms="));
+        assert!(logs[0].ends_with(r#"modified_by="somebody""#));
+        assert!(logs[1].starts_with("method=GET path=/api/v1/feattles status=200 duration_ms="));
+        assert!(!logs[1].contains("modified_by"));
+    }
+
+    #[tokio::test]
+    async fn user_header() {
+        let my_toggles = Arc::new(MyToggles::new(Arc::new(InMemoryPersistence::new())));
+        my_toggles.set_require_approval(true);
+        let mut admin_panel = AdminPanel::new(my_toggles.clone(), "label".to_owned());
+        admin_panel.user_header("X-User".to_owned());
+        let router = axum_router(Arc::new(admin_panel));
+
+        let post = |uri: &str, user: Option<&str>, content_type: &str, body: &'static str| {
+            let mut request = Request::post(uri).header("Content-Type", content_type);
+            if let Some(user) = user {
+                request = request.header("X-User", user);
+            }
+            let request = request.body(Body::from(body)).unwrap();
+            let router = router.clone();
+            async move { router.oneshot(request).await.unwrap().status() }
+        };
+        let form = "application/x-www-form-urlencoded";
+        let json = "application/json";
+
+        // The edits from the web UI are proposed by the user in the header
+        let status = post("/feattle/a/edit", None, form, "value_json=true").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let status = post("/feattle/a/edit", Some("alice"), form, "value_json=true").await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        assert!(!*my_toggles.a());
+        let pending = my_toggles.pending_updates().await.unwrap();
+        assert_eq!(pending.changes["a"].proposed_by, "alice");
+
+        // The direct edits are refused and the user in the body is ignored
+        let body = r#"{"value": true, "modified_by": "bob"}"#;
+        let status = post("/api/v1/feattle/a", Some("alice"), json, body).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let body = r#"{"reviewed_by": "bob"}"#;
+        let status = post("/api/v1/feattle/a/approve", Some("alice"), json, body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let status = post("/feattle/a/approve", Some("bob"), form, "").await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        assert!(*my_toggles.a());
+    }
+}
@@ -0,0 +1,122 @@
+use crate::api::v1::JsonChange;
+use serde_json::Value;
+
+/// Compute the structural differences between two JSON values.
+///
+/// Objects are compared key by key and arrays are compared position by position. Each change is
+/// located by a [JSON Pointer](https://datatracker.ietf.org/doc/html/rfc6901), where the empty
+/// string represents the whole value.
+pub fn json_diff(old: &Value, new: &Value) -> Vec<JsonChange> {
+    let mut changes = Vec::new();
+    diff_at(String::new(), old, new, &mut changes);
+    changes
+}
+
+fn diff_at(path: String, old: &Value, new: &Value, changes: &mut Vec<JsonChange>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (key, old_value) in old {
+                let item_path = child_path(&path, key);
+                match new.get(key) {
+                    None => changes.push(JsonChange::Removed {
+                        path: item_path,
+                        value: old_value.clone(),
+                    }),
+                    Some(new_value) => diff_at(item_path, old_value, new_value, changes),
+                }
+            }
+            for (key, new_value) in new {
+                if !old.contains_key(key) {
+                    changes.push(JsonChange::Added {
+                        path: child_path(&path, key),
+                        value: new_value.clone(),
+                    });
+                }
+            }
+        }
+        (Value::Array(old), Value::Array(new)) => {
+            for (i, old_value) in old.iter().enumerate() {
+                let item_path = child_path(&path, &i.to_string());
+                match new.get(i) {
+                    None => changes.push(JsonChange::Removed {
+                        path: item_path,
+                        value: old_value.clone(),
+                    }),
+                    Some(new_value) => diff_at(item_path, old_value, new_value, changes),
+                }
+            }
+            for (i, new_value) in new.iter().enumerate().skip(old.len()) {
+                changes.push(JsonChange::Added {
+                    path: child_path(&path, &i.to_string()),
+                    value: new_value.clone(),
+                });
+            }
+        }
+        (old, new) => {
+            if old != new {
+                changes.push(JsonChange::Changed {
+                    path,
+                    old: old.clone(),
+                    new: new.clone(),
+                });
+            }
+        }
+    }
+}
+
+fn child_path(path: &str, token: &str) -> String {
+    format!("{}/{}", path, token.replace('~', "~0").replace('/', "~1"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn map_diff() {
+        let old = json!({"a": 1, "b": [1, 2], "c/d": "x", "e": {"f": true}});
+        let new = json!({"a": 1, "b": [1, 3, 4], "e": {"f": false}, "g": null});
+
+        assert_eq!(
+            json_diff(&old, &new),
+            vec![
+                JsonChange::Changed {
+                    path: "/b/1".to_owned(),
+                    old: json!(2),
+                    new: json!(3),
+                },
+                JsonChange::Added {
+                    path: "/b/2".to_owned(),
+                    value: json!(4),
+                },
+                JsonChange::Removed {
+                    path: "/c~1d".to_owned(),
+                    value: json!("x"),
+                },
+                JsonChange::Changed {
+                    path: "/e/f".to_owned(),
+                    old: json!(true),
+                    new: json!(false),
+                },
+                JsonChange::Added {
+                    path: "/g".to_owned(),
+                    value: json!(null),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn scalar_diff() {
+        assert_eq!(json_diff(&json!(17), &json!(17)), vec![]);
+        assert_eq!(
+            json_diff(&json!(17), &json!([17])),
+            vec![JsonChange::Changed {
+                path: "".to_owned(),
+                old: json!(17),
+                new: json!([17]),
+            }]
+        );
+    }
+}
@@ -11,25 +11,37 @@
 //! # Optional features
 //!
 //! - **axum**: provides [`axum_router`] for a read-to-use integration with [`axum`]
-//! - **warp**: provides [`run_warp_server`] for a read-to-use integration with [`warp`]
+//! - **warp**: provides [`run_warp_server`] for a read-to-use integration with [`warp`] and
+//!   [`bind_warp_server_with_graceful_shutdown`] to stop it gracefully
 
 pub mod api;
 #[cfg(feature = "axum")]
 mod axum_ui;
+mod diff;
 mod pages;
+#[cfg(any(feature = "axum", feature = "warp"))]
+mod request_log;
 #[cfg(feature = "warp")]
 mod warp_ui;
 
 use crate::pages::{PageError, Pages};
-use feattle_core::{BoxError, Feattles, HistoryError, UpdateError};
+use chrono::Utc;
+use feattle_core::last_reload::LastReload;
+use feattle_core::persist::{CurrentValues, Persist};
+use feattle_core::{
+    env_var_name, BoxError, EvaluationContext, FeattleError, Feattles, HistoryError, UpdateError,
+};
+use futures::lock::Mutex;
 use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::api::v1;
 #[cfg(feature = "axum")]
 pub use axum_ui::axum_router;
 #[cfg(feature = "warp")]
-pub use warp_ui::run_warp_server;
+pub use warp_ui::{bind_warp_server_with_graceful_shutdown, run_warp_server};
 
 /// The administration panel, agnostic to the choice of web-framework.
 ///
@@ -53,7 +65,7 @@ pub use warp_ui::run_warp_server;
 /// let my_toggles = Arc::new(MyToggles::new(Arc::new(NoPersistence)));
 /// let admin_panel = AdminPanel::new(my_toggles, "Project Panda - DEV".to_owned());
 ///
-/// let home_content = admin_panel.list_feattles().await?;
+/// let home_content = admin_panel.list_feattles(Default::default()).await?;
 /// assert_eq!(home_content.content_type, "text/html; charset=utf-8");
 /// assert!(home_content.content.len() > 0);
 /// # Ok(())
@@ -62,6 +74,15 @@ pub use warp_ui::run_warp_server;
 pub struct AdminPanel<F> {
     feattles: Arc<F>,
     pages: Pages,
+    request_logging: bool,
+    reload_on_read: bool,
+    reload_coalescing: Option<Duration>,
+    /// When the last coalesced reload finished and whether it failed
+    last_coalesced_reload: Mutex<Option<(Instant, bool)>>,
+    /// The label and persistence layer of the environment to compare with
+    other_environment: Option<(String, Arc<dyn Persist>)>,
+    /// The name of the request header that identifies the user
+    user_header: Option<String>,
 }
 
 /// Represent a rendered page
@@ -94,6 +115,12 @@ pub enum RenderError {
     /// Failed to reload new version
     #[error("failed to reload new version")]
     Reload(#[source] BoxError),
+    /// Failed to load or save persisted data
+    #[error("failed to load or save persisted data")]
+    Persistence(#[source] BoxError),
+    /// The request does not identify the user, as required by [`AdminPanel::user_header()`]
+    #[error("the request does not identify the user")]
+    Unauthenticated,
 }
 
 impl From<PageError> for RenderError {
@@ -106,35 +133,244 @@ impl From<PageError> for RenderError {
     }
 }
 
+impl From<RenderError> for FeattleError {
+    fn from(error: RenderError) -> Self {
+        match error {
+            RenderError::History(error) => FeattleError::History(error),
+            RenderError::Update(error) => FeattleError::Update(error),
+            RenderError::Reload(error) | RenderError::Persistence(error) => {
+                FeattleError::Persistence(error)
+            }
+            error => FeattleError::Render(Box::new(error)),
+        }
+    }
+}
+
 impl<F: Feattles + Sync> AdminPanel<F> {
     /// Create a new UI provider for a given feattles and a user-visible label
     pub fn new(feattles: Arc<F>, label: String) -> Self {
         AdminPanel {
             feattles,
             pages: Pages::new(label),
+            request_logging: false,
+            reload_on_read: true,
+            reload_coalescing: None,
+            last_coalesced_reload: Mutex::new(None),
+            other_environment: None,
+            user_header: None,
         }
     }
 
+    /// Log each request handled by [`axum_router`] or [`run_warp_server`], with its method, path,
+    /// status and duration. By default, this is disabled.
+    ///
+    /// The logs are generated with the crate [`log`], at the level `INFO` and with the target
+    /// `"feattle_ui::access"`. For edits, the user that made the change is also logged.
+    ///
+    /// This is a plain log line, instead of the [`tracing`](https://docs.rs/tracing) spans of
+    /// `tower_http::trace::TraceLayer`, because it is shared by the warp server, that is not built
+    /// on `tower`, and because the feattle crates only depend on `log`. To get tracing spans, leave
+    /// this disabled and add a `TraceLayer` to the router returned by [`axum_router`].
+    pub fn request_logging(&mut self, enabled: bool) -> &mut Self {
+        self.request_logging = enabled;
+        self
+    }
+
+    /// Select whether the pages and their JSON-API equivalents call [`Feattles::reload()`] before
+    /// reading the data. By default, this is enabled.
+    ///
+    /// Disable it when a `BackgroundSync` from the crate `feattle-sync` is already keeping the data
+    /// fresh, so that the UI stays responsive when the persistence layer is slow or down. The pages
+    /// will then serve the data in memory and always report `reload_failed` as `false`. Note that
+    /// the page of a single feattle still loads its history from the persistence layer.
+    ///
+    /// Edits are not affected and always reload the data before applying the change.
+    pub fn reload_on_read(&mut self, enabled: bool) -> &mut Self {
+        self.reload_on_read = enabled;
+        self
+    }
+
+    /// Skip the call to [`Feattles::reload()`] done by the pages and their JSON-API equivalents if
+    /// another reload finished less than `window` ago, serving the current state instead.
+    /// Concurrent requests will also wait for a single reload, instead of each doing their own.
+    /// This protects the persistence layer from bursts of requests. By default, every request
+    /// reloads the data.
+    ///
+    /// Edits are not affected and always reload the data before applying the change.
+    pub fn reload_coalescing(&mut self, window: Duration) -> &mut Self {
+        self.reload_coalescing = Some(window);
+        self
+    }
+
+    /// Enable the comparison with another environment, like production, whose values are read
+    /// from the given persistence layer. See [`AdminPanel::compare()`].
+    pub fn compare_with(&mut self, label: String, persistence: Arc<dyn Persist>) -> &mut Self {
+        self.other_environment = Some((label, persistence));
+        self
+    }
+
+    /// Identify the user that makes each change with the given request header, like
+    /// `"X-Forwarded-User"` set by an authenticating proxy. This applies to the changes served by
+    /// [`axum_router`] and [`run_warp_server`], that fail with [`RenderError::Unauthenticated`]
+    /// when the header is missing.
+    ///
+    /// The user given in the body of the JSON-API requests, like
+    /// [`v1::EditFeattleRequest::modified_by`], is then ignored. By default, the JSON-API trusts
+    /// these fields and the forms of the web UI are submitted by the user `"admin"`, so the
+    /// changes proposed with the web UI can't be approved with it.
+    pub fn user_header(&mut self, name: String) -> &mut Self {
+        self.user_header = Some(name);
+        self
+    }
+
     /// Render the page that lists the current feattles values, together with navigation links to
     /// modify them. This page is somewhat the "home screen" of the UI.
     ///
+    /// The feattles are paginated and optionally filtered by tag, as selected by the query. The
+    /// page has navigation links that point to "/?page={{ page }}&per_page={{ per_page }}", followed
+    /// by "&tag={{ tag }}" when filtering.
+    ///
     /// To ensure fresh data is displayed, [`Feattles::reload()`] is called.
-    pub async fn list_feattles(&self) -> Result<RenderedPage, RenderError> {
-        let data = self.list_feattles_api_v1().await?;
-        Ok(self
-            .pages
-            .render_feattles(&data.definitions, data.last_reload, data.reload_failed)?)
+    pub async fn list_feattles(
+        &self,
+        query: v1::ListFeattlesQuery,
+    ) -> Result<RenderedPage, RenderError> {
+        let data = self.list_feattles_api_v1(query).await?;
+        Ok(self.pages.render_feattles(&data)?)
     }
 
     /// The JSON-API equivalent of [`AdminPanel::list_feattles()`].
     ///
+    /// The page size is capped by [`v1::ListFeattlesQuery::MAX_PER_PAGE`], even without
+    /// `per_page`, so clients should check `page_count` in the response to fetch the other pages.
+    ///
     /// To ensure fresh data is displayed, [`Feattles::reload()`] is called.
-    pub async fn list_feattles_api_v1(&self) -> Result<v1::ListFeattlesResponse, RenderError> {
-        let reload_failed = self.feattles.reload().await.is_err();
+    pub async fn list_feattles_api_v1(
+        &self,
+        query: v1::ListFeattlesQuery,
+    ) -> Result<v1::ListFeattlesResponse, RenderError> {
+        let reload_failed = self.reload().await;
+        let definitions = match &query.tag {
+            None => self.feattles.definitions(),
+            Some(tag) => self
+                .feattles
+                .definitions_matching(|definition| definition.tags.contains(tag)),
+        };
+
+        let total = definitions.len();
+        let (per_page, page_count) = page_size(query.per_page, total);
+        let page = query.page.unwrap_or(1).max(1);
+        let definitions = definitions
+            .into_iter()
+            .skip((page - 1).saturating_mul(per_page))
+            .take(per_page)
+            .collect();
+
         Ok(v1::ListFeattlesResponse {
-            definitions: self.feattles.definitions(),
+            definitions,
             last_reload: self.feattles.last_reload(),
             reload_failed,
+            total,
+            page,
+            per_page,
+            page_count,
+            tag: query.tag,
+        })
+    }
+
+    /// Return the version of the current values, without the feattles' definitions. This is cheap
+    /// and can be used for health checks or to monitor that all instances converged to the same
+    /// version. The keys whose persisted value could not be applied in memory are also reported,
+    /// so that a non-empty `diverging_keys` can be treated as unhealthy.
+    ///
+    /// To ensure fresh data is returned, [`Feattles::reload()`] is called.
+    pub async fn version_api_v1(&self) -> Result<v1::VersionResponse, RenderError> {
+        let reload_failed = self.reload().await;
+        let last_reload = self.feattles.last_reload();
+        let (version, version_date) = match last_reload {
+            LastReload::Data {
+                version,
+                version_date,
+                ..
+            } => (Some(version), Some(version_date)),
+            LastReload::Never | LastReload::NoData { .. } => (None, None),
+        };
+        Ok(v1::VersionResponse {
+            version,
+            version_date,
+            reload_date: last_reload.reload_date(),
+            reload_failed,
+            diverging_keys: self
+                .feattles
+                .persisted_vs_memory_divergence()
+                .into_iter()
+                .map(str::to_owned)
+                .collect(),
+        })
+    }
+
+    /// Render the page that compares the values of each feattle in this environment and in the
+    /// one configured with [`AdminPanel::compare_with()`], highlighting the differences. If no
+    /// other environment was configured, returns [`RenderError::NotFound`].
+    ///
+    /// To ensure fresh data is displayed, [`Feattles::reload()`] is called and the current values
+    /// of the other environment are loaded.
+    pub async fn compare(&self) -> Result<RenderedPage, RenderError> {
+        let data = self.compare_api_v1().await?;
+        Ok(self.pages.render_compare(&data)?)
+    }
+
+    /// The JSON-API equivalent of [`AdminPanel::compare()`].
+    pub async fn compare_api_v1(&self) -> Result<v1::CompareResponse, RenderError> {
+        let (other_label, other_persistence) = self
+            .other_environment
+            .as_ref()
+            .ok_or(RenderError::NotFound)?;
+
+        let reload_failed = self.reload().await;
+        let mut other_values = other_persistence
+            .load_current()
+            .await
+            .map_err(RenderError::Persistence)?
+            .map(|current_values| current_values.feattles)
+            .unwrap_or_default();
+
+        let entry = |key: String, value: Option<Value>, other_value: Value| {
+            let changes = diff::json_diff(value.as_ref().unwrap_or(&Value::Null), &other_value);
+            v1::CompareEntry {
+                key,
+                different: value.as_ref() != Some(&other_value),
+                value,
+                other_value,
+                changes,
+            }
+        };
+        let mut feattles: Vec<_> = self
+            .feattles
+            .definitions()
+            .into_iter()
+            .map(|definition| {
+                let other_value = other_values
+                    .remove(definition.key)
+                    .map(|current_value| current_value.value)
+                    .unwrap_or(definition.default);
+                entry(
+                    definition.key.to_owned(),
+                    Some(definition.value),
+                    other_value,
+                )
+            })
+            .collect();
+        feattles.extend(
+            other_values
+                .into_iter()
+                .map(|(key, current_value)| entry(key, None, current_value.value)),
+        );
+
+        Ok(v1::CompareResponse {
+            other_label: other_label.clone(),
+            feattles,
+            reload_failed,
         })
     }
 
@@ -148,6 +384,7 @@ impl<F: Feattles + Sync> AdminPanel<F> {
         Ok(self.pages.render_feattle(
             &data.definition,
             &data.history,
+            data.pending.as_ref(),
             data.last_reload,
             data.reload_failed,
         )?)
@@ -160,21 +397,103 @@ impl<F: Feattles + Sync> AdminPanel<F> {
         &self,
         key: &str,
     ) -> Result<v1::ShowFeattleResponse, RenderError> {
-        let reload_failed = self.feattles.reload().await.is_err();
+        let reload_failed = self.reload().await;
         let definition = self.feattles.definition(key).ok_or(RenderError::NotFound)?;
         let history = self.feattles.history(key).await?;
+        let pending = self
+            .feattles
+            .pending_updates()
+            .await
+            .map_err(RenderError::Persistence)?
+            .changes
+            .remove(key);
         Ok(v1::ShowFeattleResponse {
             definition,
             history,
             last_reload: self.feattles.last_reload(),
             reload_failed,
+            pending,
+        })
+    }
+
+    /// Return the effective value of a single feattle in the given context, as described by
+    /// [`feattle_core::FeattleValue::evaluate()`]. For most types, this is the current value
+    /// itself, but types like [`feattle_core::Rollout`] depend on the user.
+    ///
+    /// To ensure fresh data is used, [`Feattles::reload()`] is called.
+    pub async fn evaluate_feattle_api_v1(
+        &self,
+        key: &str,
+        request: v1::EvaluateFeattleRequest,
+    ) -> Result<v1::EvaluateFeattleResponse, RenderError> {
+        let reload_failed = self.reload().await;
+        let context = EvaluationContext {
+            user_key: request.user_key,
+            at: request.at.unwrap_or_else(Utc::now),
+        };
+        let value = self
+            .feattles
+            .evaluate(key, &context)
+            .ok_or(RenderError::NotFound)?;
+        Ok(v1::EvaluateFeattleResponse {
+            value,
+            reload_failed,
         })
     }
 
+    /// Compute the differences between the values of two entries in the history of a feattle,
+    /// given by their position in [`v1::ShowFeattleResponse::history`]. If any of them does not
+    /// exist, [`RenderError::NotFound`] is returned.
+    ///
+    /// The feattle page shows the same differences between each pair of consecutive entries.
+    pub async fn diff_feattle_api_v1(
+        &self,
+        key: &str,
+        query: v1::DiffFeattleQuery,
+    ) -> Result<v1::DiffFeattleResponse, RenderError> {
+        self.feattles.definition(key).ok_or(RenderError::NotFound)?;
+        let history = self.feattles.history(key).await?;
+        let get_entry = |seq: usize| history.entries.get(seq).ok_or(RenderError::NotFound);
+        let from = get_entry(query.from)?.clone();
+        let to = get_entry(query.to)?.clone();
+        let changes = diff::json_diff(&from.value, &to.value);
+        Ok(v1::DiffFeattleResponse { from, to, changes })
+    }
+
+    /// List the changes made by the given user to each feattle, as recorded in their histories.
+    /// This can be used to audit what an admin changed, for example. Only the entries whose
+    /// [`feattle_core::persist::HistoryEntry::modified_by`] is exactly `user` are returned.
+    ///
+    /// The history of every feattle is loaded, so this can be slow with many feattles.
+    pub async fn changes_by_user_api_v1(
+        &self,
+        user: &str,
+    ) -> Result<v1::ChangesByUserResponse, RenderError> {
+        let mut changes = BTreeMap::new();
+        for &key in self.feattles.keys() {
+            let mut entries: Vec<_> = self
+                .feattles
+                .history(key)
+                .await?
+                .entries
+                .into_iter()
+                .filter(|entry| entry.modified_by == user)
+                .collect();
+            if !entries.is_empty() {
+                entries.sort_by_key(|entry| entry.modified_at);
+                changes.insert(key.to_owned(), entries);
+            }
+        }
+        Ok(v1::ChangesByUserResponse { changes })
+    }
+
     /// Process a modification of a single feattle, given its key and the JSON representation of its
     /// future value. In case of success, the return is empty, so caller should usually redirect the
     /// user somewhere after.
     ///
+    /// If [`Feattles::requires_approval()`], the modification is proposed instead, like with
+    /// [`AdminPanel::propose_feattle_api_v1()`].
+    ///
     /// To ensure fresh data is displayed, [`Feattles::reload()`] is called. Unlike the other pages,
     /// if the reload fails, this operation will fail.
     pub async fn edit_feattle(
@@ -184,8 +503,16 @@ impl<F: Feattles + Sync> AdminPanel<F> {
         modified_by: String,
     ) -> Result<(), RenderError> {
         let value: Value = serde_json::from_str(value_json)?;
-        self.edit_feattle_api_v1(key, v1::EditFeattleRequest { value, modified_by })
-            .await?;
+        if self.feattles.requires_approval() {
+            let request = v1::ProposeFeattleRequest {
+                value,
+                proposed_by: modified_by,
+            };
+            self.propose_feattle_api_v1(key, request).await?;
+        } else {
+            self.edit_feattle_api_v1(key, v1::EditFeattleRequest { value, modified_by })
+                .await?;
+        }
         Ok(())
     }
 
@@ -199,9 +526,10 @@ impl<F: Feattles + Sync> AdminPanel<F> {
         request: v1::EditFeattleRequest,
     ) -> Result<v1::EditFeattleResponse, RenderError> {
         log::info!(
-            "Received edit request for key {} with value {}",
+            "Received edit request for key {} with value {} by {}",
             key,
-            request.value
+            request.value,
+            request.modified_by
         );
         self.feattles.reload().await.map_err(RenderError::Reload)?;
         self.feattles
@@ -210,22 +538,872 @@ impl<F: Feattles + Sync> AdminPanel<F> {
         Ok(v1::EditFeattleResponse {})
     }
 
+    /// Propose a change to a feattle, that will only be applied after another user approves it.
+    /// Read more at [`Feattles::propose_update()`].
+    pub async fn propose_feattle_api_v1(
+        &self,
+        key: &str,
+        request: v1::ProposeFeattleRequest,
+    ) -> Result<v1::ProposeFeattleResponse, RenderError> {
+        log::info!(
+            "Received proposal for key {} with value {} by {}",
+            key,
+            request.value,
+            request.proposed_by
+        );
+        self.feattles
+            .propose_update(key, request.value, request.proposed_by)
+            .await?;
+        Ok(v1::ProposeFeattleResponse {})
+    }
+
+    /// Process the submission of the form to approve the pending change of a feattle, shown by
+    /// [`AdminPanel::show_feattle()`]. The form submits to "/feattle/{{ key }}/approve" with the
+    /// POST method.
+    pub async fn approve_feattle(&self, key: &str, approved_by: String) -> Result<(), RenderError> {
+        self.approve_feattle_api_v1(
+            key,
+            v1::ReviewFeattleRequest {
+                reviewed_by: approved_by,
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// The JSON-API equivalent of [`AdminPanel::approve_feattle()`].
+    ///
+    /// Like [`AdminPanel::edit_feattle_api_v1()`], [`Feattles::reload()`] is called and if the
+    /// reload fails, this operation will fail.
+    pub async fn approve_feattle_api_v1(
+        &self,
+        key: &str,
+        request: v1::ReviewFeattleRequest,
+    ) -> Result<v1::ReviewFeattleResponse, RenderError> {
+        log::info!(
+            "Received approval for key {} by {}",
+            key,
+            request.reviewed_by
+        );
+        self.feattles.reload().await.map_err(RenderError::Reload)?;
+        self.feattles
+            .approve_update(key, request.reviewed_by)
+            .await?;
+        Ok(v1::ReviewFeattleResponse {})
+    }
+
+    /// Process the submission of the form to reject the pending change of a feattle, shown by
+    /// [`AdminPanel::show_feattle()`]. The form submits to "/feattle/{{ key }}/reject" with the
+    /// POST method.
+    pub async fn reject_feattle(&self, key: &str, rejected_by: String) -> Result<(), RenderError> {
+        self.reject_feattle_api_v1(
+            key,
+            v1::ReviewFeattleRequest {
+                reviewed_by: rejected_by,
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// The JSON-API equivalent of [`AdminPanel::reject_feattle()`].
+    pub async fn reject_feattle_api_v1(
+        &self,
+        key: &str,
+        request: v1::ReviewFeattleRequest,
+    ) -> Result<v1::ReviewFeattleResponse, RenderError> {
+        self.feattles
+            .reject_update(key, request.reviewed_by)
+            .await?;
+        Ok(v1::ReviewFeattleResponse {})
+    }
+
+    /// Set or remove the staging value of a feattle, that is only read by the instances in staging
+    /// mode. Read more at [`Feattles::update_staging()`].
+    ///
+    /// Like [`AdminPanel::edit_feattle_api_v1()`], [`Feattles::reload()`] is called and if the
+    /// reload fails, this operation will fail.
+    pub async fn staging_feattle_api_v1(
+        &self,
+        key: &str,
+        request: v1::StagingFeattleRequest,
+    ) -> Result<v1::StagingFeattleResponse, RenderError> {
+        log::info!(
+            "Received staging request for key {} with value {:?} by {}",
+            key,
+            request.value,
+            request.modified_by
+        );
+        self.feattles.reload().await.map_err(RenderError::Reload)?;
+        self.feattles
+            .update_staging(key, request.value, request.modified_by)
+            .await?;
+        Ok(v1::StagingFeattleResponse {})
+    }
+
+    /// Process the submission of the form to make the staging value of a feattle live, shown by
+    /// [`AdminPanel::show_feattle()`]. The form submits to "/feattle/{{ key }}/promote" with the
+    /// POST method.
+    pub async fn promote_feattle(&self, key: &str, modified_by: String) -> Result<(), RenderError> {
+        self.promote_feattle_api_v1(key, v1::PromoteFeattleRequest { modified_by })
+            .await?;
+        Ok(())
+    }
+
+    /// The JSON-API equivalent of [`AdminPanel::promote_feattle()`].
+    ///
+    /// Like [`AdminPanel::edit_feattle_api_v1()`], [`Feattles::reload()`] is called and if the
+    /// reload fails, this operation will fail.
+    pub async fn promote_feattle_api_v1(
+        &self,
+        key: &str,
+        request: v1::PromoteFeattleRequest,
+    ) -> Result<v1::PromoteFeattleResponse, RenderError> {
+        log::info!(
+            "Received promotion for key {} by {}",
+            key,
+            request.modified_by
+        );
+        self.feattles.reload().await.map_err(RenderError::Reload)?;
+        self.feattles
+            .promote_staging(key, request.modified_by)
+            .await?;
+        Ok(v1::PromoteFeattleResponse {})
+    }
+
+    /// Return the user that makes a change: the value of the header set with
+    /// [`AdminPanel::user_header()`], read with `header`, or the `claimed` user if none is set
+    #[cfg(any(feature = "axum", feature = "warp"))]
+    pub(crate) fn request_user(
+        &self,
+        header: impl FnOnce(&str) -> Option<String>,
+        claimed: String,
+    ) -> Result<String, RenderError> {
+        match &self.user_header {
+            None => Ok(claimed),
+            Some(name) => header(name)
+                .filter(|user| !user.is_empty())
+                .ok_or(RenderError::Unauthenticated),
+        }
+    }
+
+    /// Render the current values as lines of `FEATTLE_{KEY}={value}`, ready to be sourced by a
+    /// shell or used as the environment of another process. The variables are named by
+    /// [`feattle_core::env_var_name()`] and the values, including strings, are written as their
+    /// JSON representation, single-quoted when needed. They can be read back with
+    /// [`feattle_core::LayeredSource::with_env()`] and the prefix `"FEATTLE_"`.
+    ///
+    /// To ensure fresh data is returned, [`Feattles::reload()`] is called.
+    pub async fn export_env(&self) -> Result<RenderedPage, RenderError> {
+        self.reload().await;
+        let mut content = String::new();
+        for definition in self.feattles.definitions() {
+            content.push_str(&format!(
+                "{}={}\n",
+                env_var_name("FEATTLE_", definition.key),
+                shell_quote(&definition.value.to_string())
+            ));
+        }
+        Ok(RenderedPage {
+            content_type: "text/plain; charset=utf-8".to_owned(),
+            content: content.into_bytes(),
+        })
+    }
+
+    /// Read all the persisted data: the current values and the history of each feattle, including
+    /// the keys that are not declared anymore but still have a current value. This can be used to
+    /// make a backup or to clone an environment, with [`AdminPanel::restore()`].
+    ///
+    /// The revision of the current values is specific to the storage, so it's not included.
+    pub async fn full_backup(&self) -> Result<v1::FullBackup, RenderError> {
+        let persistence = self.feattles.persistence();
+        let mut current_values = persistence
+            .load_current()
+            .await
+            .map_err(RenderError::Persistence)?;
+        if let Some(current_values) = &mut current_values {
+            current_values.revision = None;
+        }
+
+        let mut keys: BTreeSet<&str> = self.feattles.keys().iter().copied().collect();
+        if let Some(current_values) = &current_values {
+            keys.extend(current_values.feattles.keys().map(String::as_str));
+        }
+
+        let mut histories = BTreeMap::new();
+        for key in keys {
+            let history = persistence
+                .load_history(key)
+                .await
+                .map_err(RenderError::Persistence)?;
+            if let Some(history) = history {
+                histories.insert(key.to_owned(), history);
+            }
+        }
+
+        Ok(v1::FullBackup {
+            current_values,
+            histories,
+        })
+    }
+
+    /// Write back the data produced by [`AdminPanel::full_backup()`] and reload the feattles.
+    ///
+    /// The data is written directly to the persistence layer, so the original modification dates
+    /// and authors are preserved and no new history entry is created. Histories of keys that are
+    /// not present in the backup are kept untouched. The current values are overwritten even if
+    /// they were modified in the meantime, by saving them with the revision that is currently
+    /// stored.
+    pub async fn restore(&self, backup: &v1::FullBackup) -> Result<(), RenderError> {
+        let persistence = self.feattles.persistence();
+        for (key, history) in &backup.histories {
+            persistence
+                .save_history(key, history)
+                .await
+                .map_err(RenderError::Persistence)?;
+        }
+        if let Some(current_values) = &backup.current_values {
+            let stored_values = persistence
+                .load_current()
+                .await
+                .map_err(RenderError::Persistence)?;
+            let current_values = CurrentValues {
+                revision: stored_values.and_then(|stored_values| stored_values.revision),
+                ..current_values.clone()
+            };
+            persistence
+                .save_current_with_revision(&current_values)
+                .await
+                .map_err(RenderError::Persistence)?;
+        }
+        self.feattles.reload().await.map_err(RenderError::Reload)?;
+        Ok(())
+    }
+
     /// Renders a public file with the given path. The pages include public files like
     /// "/public/some/path.js", but this method should be called with only the "some/path.js" part.
     pub fn render_public_file(&self, path: &str) -> Result<RenderedPage, RenderError> {
         Ok(self.pages.render_public_file(path)?)
     }
+
+    /// Reload the data, as configured by [`AdminPanel::reload_on_read()`] and
+    /// [`AdminPanel::reload_coalescing()`], returning whether it failed
+    async fn reload(&self) -> bool {
+        if !self.reload_on_read {
+            return false;
+        }
+        let window = match self.reload_coalescing {
+            None => return self.feattles.reload().await.is_err(),
+            Some(window) => window,
+        };
+
+        let mut last_reload = self.last_coalesced_reload.lock().await;
+        if let Some((finished_at, failed)) = *last_reload {
+            if finished_at.elapsed() < window {
+                return failed;
+            }
+        }
+        let failed = self.feattles.reload().await.is_err();
+        *last_reload = Some((Instant::now(), failed));
+        failed
+    }
+}
+
+/// Return the number of feattles per page and the number of pages. Without `per_page`, all the
+/// feattles are in a single page, but that is also capped by [`v1::ListFeattlesQuery::MAX_PER_PAGE`]
+/// and the extra pages are counted in the returned `page_count`.
+fn page_size(per_page: Option<usize>, total: usize) -> (usize, usize) {
+    let per_page = per_page
+        .unwrap_or(total)
+        .clamp(1, v1::ListFeattlesQuery::MAX_PER_PAGE);
+    (per_page, total.div_ceil(per_page).max(1))
+}
+
+/// Quote the value with single quotes if it contains any character that is special to the shell
+fn shell_quote(value: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-.,:/@%+".contains(c);
+    if !value.is_empty() && value.chars().all(is_safe) {
+        value.to_owned()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use async_trait::async_trait;
+    use feattle_core::persist::{CurrentValues, InMemoryPersistence, Persist, ValueHistory};
     use feattle_core::{feattles, Feattles};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     feattles! {
         struct MyToggles { a: bool, b: i32 }
     }
 
+    /// Count how many times the current values were loaded
+    #[derive(Default)]
+    struct CountingPersistence {
+        inner: InMemoryPersistence,
+        loads: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Persist for CountingPersistence {
+        async fn save_current(&self, value: &CurrentValues) -> Result<(), BoxError> {
+            self.inner.save_current(value).await
+        }
+
+        async fn save_current_with_revision(
+            &self,
+            value: &CurrentValues,
+        ) -> Result<Option<String>, BoxError> {
+            self.inner.save_current_with_revision(value).await
+        }
+
+        async fn load_current(&self) -> Result<Option<CurrentValues>, BoxError> {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+            tokio::task::yield_now().await;
+            self.inner.load_current().await
+        }
+
+        async fn save_history(&self, key: &str, value: &ValueHistory) -> Result<(), BoxError> {
+            self.inner.save_history(key, value).await
+        }
+
+        async fn load_history(&self, key: &str) -> Result<Option<ValueHistory>, BoxError> {
+            self.inner.load_history(key).await
+        }
+    }
+
+    #[tokio::test]
+    async fn pagination() {
+        feattles! {
+            struct ManyToggles { a: bool, b: bool, c: bool, d: bool, e: bool }
+        }
+
+        let toggles = Arc::new(ManyToggles::new(Arc::new(InMemoryPersistence::new())));
+        let admin_panel = AdminPanel::new(toggles, "label".to_owned());
+        let list = |page, per_page| {
+            admin_panel.list_feattles_api_v1(v1::ListFeattlesQuery {
+                page: Some(page),
+                per_page: Some(per_page),
+                tag: None,
+            })
+        };
+        let keys = |response: &v1::ListFeattlesResponse| {
+            response
+                .definitions
+                .iter()
+                .map(|definition| definition.key)
+                .collect::<Vec<_>>()
+        };
+
+        let response = list(2, 2).await.unwrap();
+        assert_eq!(keys(&response), ["c", "d"]);
+        assert_eq!(response.total, 5);
+        assert_eq!(response.page, 2);
+        assert_eq!(response.per_page, 2);
+        assert_eq!(response.page_count, 3);
+
+        let response = list(3, 2).await.unwrap();
+        assert_eq!(keys(&response), ["e"]);
+        let response = list(4, 2).await.unwrap();
+        assert!(response.definitions.is_empty());
+
+        let response = list(1, usize::MAX).await.unwrap();
+        assert_eq!(response.per_page, v1::ListFeattlesQuery::MAX_PER_PAGE);
+        assert_eq!(response.page_count, 1);
+
+        let response = admin_panel
+            .list_feattles_api_v1(Default::default())
+            .await
+            .unwrap();
+        assert_eq!(keys(&response), ["a", "b", "c", "d", "e"]);
+        assert_eq!(response.page, 1);
+        assert_eq!(response.page_count, 1);
+
+        let max = v1::ListFeattlesQuery::MAX_PER_PAGE;
+        assert_eq!(page_size(None, 0), (1, 1));
+        assert_eq!(page_size(None, max), (max, 1));
+        assert_eq!(page_size(None, 2 * max + 1), (max, 3));
+        assert_eq!(page_size(Some(0), 5), (1, 5));
+
+        let page = admin_panel
+            .list_feattles(v1::ListFeattlesQuery {
+                page: Some(2),
+                per_page: Some(2),
+                tag: None,
+            })
+            .await
+            .unwrap();
+        let html = String::from_utf8(page.content).unwrap();
+        assert!(html.contains("Page 2 of 3"));
+        assert!(html.contains("/?page=3&amp;per_page=2"));
+    }
+
+    #[tokio::test]
+    async fn tag_filter() {
+        feattles! {
+            struct TaggedToggles {
+                #[tags("billing", "experimental")]
+                a: bool,
+                #[tags("eu-only")]
+                b: bool,
+                #[tags("billing")]
+                c: bool,
+                d: bool,
+            }
+        }
+
+        let toggles = Arc::new(TaggedToggles::new(Arc::new(InMemoryPersistence::new())));
+        let admin_panel = AdminPanel::new(toggles, "label".to_owned());
+        let query = |tag: &str, per_page| v1::ListFeattlesQuery {
+            page: None,
+            per_page,
+            tag: Some(tag.to_owned()),
+        };
+
+        let response = admin_panel
+            .list_feattles_api_v1(query("billing", None))
+            .await
+            .unwrap();
+        let keys: Vec<_> = response.definitions.iter().map(|d| d.key).collect();
+        assert_eq!(keys, ["a", "c"]);
+        assert_eq!(response.total, 2);
+        assert_eq!(response.definitions[0].tags, ["billing", "experimental"]);
+
+        let response = admin_panel
+            .list_feattles_api_v1(query("unknown", None))
+            .await
+            .unwrap();
+        assert!(response.definitions.is_empty());
+
+        let page = admin_panel
+            .list_feattles(query("billing", Some(1)))
+            .await
+            .unwrap();
+        let html = String::from_utf8(page.content).unwrap();
+        assert!(html.contains(r#"<a href="/?tag=experimental" class="link">experimental</a>"#));
+        assert!(html.contains("/?page=2&amp;per_page=1&amp;tag=billing"));
+    }
+
+    #[tokio::test]
+    async fn compare() {
+        let persistence = Arc::new(InMemoryPersistence::new());
+        let my_toggles = Arc::new(MyToggles::new(persistence));
+        let mut admin_panel = AdminPanel::new(my_toggles.clone(), "dev".to_owned());
+        admin_panel.compare().await.unwrap_err();
+
+        let other_persistence = Arc::new(InMemoryPersistence::new());
+        let other_toggles = MyToggles::new(other_persistence.clone());
+        admin_panel.compare_with("prod".to_owned(), other_persistence.clone());
+
+        // Set "a" in both and "b" only here
+        my_toggles.reload().await.unwrap();
+        other_toggles.reload().await.unwrap();
+        my_toggles
+            .update("a", json!(true), "user".to_owned())
+            .await
+            .unwrap();
+        my_toggles
+            .update("b", json!(17), "user".to_owned())
+            .await
+            .unwrap();
+        other_toggles
+            .update("a", json!(true), "user".to_owned())
+            .await
+            .unwrap();
+        // A key unknown to this environment
+        let mut other_values = other_persistence.load_current().await.unwrap().unwrap();
+        let mut c = other_values.feattles["a"].clone();
+        c.value = json!("c");
+        other_values.feattles.insert("c".to_owned(), c);
+        other_persistence.save_current(&other_values).await.unwrap();
+
+        let response = admin_panel.compare_api_v1().await.unwrap();
+        assert_eq!(response.other_label, "prod");
+        let entries: Vec<_> = response
+            .feattles
+            .iter()
+            .map(|entry| {
+                (
+                    entry.key.as_str(),
+                    entry.value.clone(),
+                    entry.other_value.clone(),
+                    entry.different,
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("a", Some(json!(true)), json!(true), false),
+                ("b", Some(json!(17)), json!(0), true),
+                ("c", None, json!("c"), true),
+            ]
+        );
+        assert_eq!(
+            response.feattles[1].changes,
+            [v1::JsonChange::Changed {
+                path: String::new(),
+                old: json!(17),
+                new: json!(0),
+            }]
+        );
+
+        let page = admin_panel.compare().await.unwrap();
+        let html = String::from_utf8(page.content).unwrap();
+        assert!(html.contains("Compare with prod"));
+        assert!(html.contains("2 feattle(s) have different values"));
+    }
+
+    #[tokio::test]
+    async fn pending_changes() {
+        let my_toggles = Arc::new(MyToggles::new(Arc::new(InMemoryPersistence::new())));
+        let admin_panel = AdminPanel::new(my_toggles.clone(), "label".to_owned());
+        my_toggles.reload().await.unwrap();
+
+        let propose = |value| {
+            admin_panel.propose_feattle_api_v1(
+                "b",
+                v1::ProposeFeattleRequest {
+                    value,
+                    proposed_by: "alice".to_owned(),
+                },
+            )
+        };
+
+        // Propose -> approve
+        propose(json!(17)).await.unwrap();
+        let response = admin_panel.show_feattle_api_v1("b").await.unwrap();
+        assert_eq!(response.pending.unwrap().value, json!(17));
+        let page = admin_panel.show_feattle("b").await.unwrap();
+        let html = String::from_utf8(page.content).unwrap();
+        assert!(html.contains("Proposed by alice"));
+        assert!(html.contains(r#"action="/feattle/b/approve""#));
+
+        admin_panel
+            .approve_feattle("b", "alice".to_owned())
+            .await
+            .unwrap_err();
+        admin_panel
+            .approve_feattle("b", "admin".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(*my_toggles.b(), 17);
+        let response = admin_panel.show_feattle_api_v1("b").await.unwrap();
+        assert!(response.pending.is_none());
+        let entry = response.history.entries.last().unwrap();
+        assert_eq!(entry.modified_by, "alice");
+        assert_eq!(entry.approved_by.as_deref(), Some("admin"));
+
+        // Propose -> reject
+        propose(json!(18)).await.unwrap();
+        admin_panel
+            .reject_feattle("b", "admin".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(*my_toggles.b(), 17);
+        let page = admin_panel.show_feattle("b").await.unwrap();
+        let html = String::from_utf8(page.content).unwrap();
+        assert!(!html.contains("Pending change"));
+    }
+
+    #[tokio::test]
+    async fn staging() {
+        let persistence = Arc::new(InMemoryPersistence::new());
+        let my_toggles = Arc::new(MyToggles::new(persistence.clone()));
+        let admin_panel = AdminPanel::new(my_toggles.clone(), "label".to_owned());
+        let canary = MyToggles::new(persistence);
+        canary.set_staging_mode(true);
+
+        admin_panel
+            .staging_feattle_api_v1(
+                "b",
+                v1::StagingFeattleRequest {
+                    value: Some(json!(17)),
+                    modified_by: "alice".to_owned(),
+                },
+            )
+            .await
+            .unwrap();
+        canary.reload().await.unwrap();
+        assert_eq!(*my_toggles.b(), 0);
+        assert_eq!(*canary.b(), 17);
+        let response = admin_panel.show_feattle_api_v1("b").await.unwrap();
+        assert_eq!(response.definition.staging, Some(json!(17)));
+        let page = admin_panel.show_feattle("b").await.unwrap();
+        let html = String::from_utf8(page.content).unwrap();
+        assert!(html.contains(r#"action="/feattle/b/promote""#));
+
+        admin_panel
+            .promote_feattle("b", "admin".to_owned())
+            .await
+            .unwrap();
+        canary.reload().await.unwrap();
+        assert_eq!(*my_toggles.b(), 17);
+        assert_eq!(*canary.b(), 17);
+        let response = admin_panel.show_feattle_api_v1("b").await.unwrap();
+        assert_eq!(response.definition.staging, None);
+        assert!(matches!(
+            admin_panel.promote_feattle("b", "admin".to_owned()).await,
+            Err(RenderError::Update(UpdateError::NoStagingValue(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn version() {
+        let persistence = Arc::new(InMemoryPersistence::new());
+        let my_toggles = Arc::new(MyToggles::new(persistence.clone()));
+        let admin_panel = AdminPanel::new(my_toggles.clone(), "label".to_owned());
+
+        let response = admin_panel.version_api_v1().await.unwrap();
+        assert_eq!(response.version, None);
+        assert_eq!(response.version_date, None);
+        assert!(response.reload_date.is_some());
+        assert!(!response.reload_failed);
+
+        my_toggles
+            .update("a", json!(true), "user".to_owned())
+            .await
+            .unwrap();
+        let response = admin_panel.version_api_v1().await.unwrap();
+        assert_eq!(response.version, Some(1));
+        assert_eq!(
+            response.version_date,
+            Some(persistence.load_current().await.unwrap().unwrap().date)
+        );
+        assert!(!response.reload_failed);
+        assert!(response.diverging_keys.is_empty());
+
+        let mut current = persistence.load_current().await.unwrap().unwrap();
+        current.feattles.get_mut("a").unwrap().value = json!("not a bool");
+        persistence.save_current(&current).await.unwrap();
+        let response = admin_panel.version_api_v1().await.unwrap();
+        assert!(!response.reload_failed);
+        assert_eq!(response.diverging_keys, ["a"]);
+    }
+
+    #[tokio::test]
+    async fn reload_on_read() {
+        let persistence = Arc::new(CountingPersistence::default());
+        let my_toggles = Arc::new(MyToggles::new(persistence.clone()));
+        my_toggles.reload().await.unwrap();
+        let mut admin_panel = AdminPanel::new(my_toggles.clone(), "label".to_owned());
+        admin_panel.reload_on_read(false);
+        assert_eq!(persistence.loads.load(Ordering::SeqCst), 1);
+
+        admin_panel.list_feattles(Default::default()).await.unwrap();
+        admin_panel.show_feattle("a").await.unwrap();
+        let response = admin_panel.version_api_v1().await.unwrap();
+        assert!(!response.reload_failed);
+        assert_eq!(persistence.loads.load(Ordering::SeqCst), 1);
+
+        // Edits still reload
+        admin_panel
+            .edit_feattle("a", "true", "user".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(persistence.loads.load(Ordering::SeqCst), 2);
+        let response = admin_panel.show_feattle_api_v1("a").await.unwrap();
+        assert_eq!(response.definition.value, json!(true));
+        assert_eq!(persistence.loads.load(Ordering::SeqCst), 2);
+        assert!(*my_toggles.a() && *my_toggles.b() == 0);
+    }
+
+    #[tokio::test]
+    async fn reload_coalescing() {
+        let persistence = Arc::new(CountingPersistence::default());
+        let my_toggles = Arc::new(MyToggles::new(persistence.clone()));
+        let mut admin_panel = AdminPanel::new(my_toggles, "label".to_owned());
+        admin_panel.reload_coalescing(Duration::from_secs(60));
+
+        let pages = futures::future::join_all(
+            (0..5).map(|_| admin_panel.list_feattles(Default::default())),
+        )
+        .await;
+        for page in pages {
+            page.unwrap();
+        }
+        assert_eq!(persistence.loads.load(Ordering::SeqCst), 1);
+
+        // Edits are not coalesced
+        admin_panel
+            .edit_feattle("a", "true", "user".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(persistence.loads.load(Ordering::SeqCst), 2);
+        admin_panel.show_feattle("a").await.unwrap();
+        assert_eq!(persistence.loads.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn bool_toggle() {
+        let my_toggles = Arc::new(MyToggles::new(Arc::new(InMemoryPersistence::new())));
+        let admin_panel = AdminPanel::new(my_toggles.clone(), "label".to_owned());
+
+        let page = admin_panel.show_feattle("a").await.unwrap();
+        let html = String::from_utf8(page.content).unwrap();
+        assert!(html.contains("custom-switch"));
+        assert!(html.contains(r#"type="checkbox""#));
+        assert!(!html.contains("checked>"));
+
+        my_toggles
+            .update("a", json!(true), "user".to_owned())
+            .await
+            .unwrap();
+        let page = admin_panel.show_feattle("a").await.unwrap();
+        let html = String::from_utf8(page.content).unwrap();
+        assert!(html.contains("checked>"));
+
+        let page = admin_panel.show_feattle("b").await.unwrap();
+        let html = String::from_utf8(page.content).unwrap();
+        assert!(!html.contains("custom-switch"));
+    }
+
+    #[tokio::test]
+    async fn none_toggle() {
+        feattles! {
+            struct OptionalToggles { c: Option<i32> }
+        }
+
+        let my_toggles = Arc::new(OptionalToggles::new(Arc::new(InMemoryPersistence::new())));
+        let admin_panel = AdminPanel::new(my_toggles.clone(), "label".to_owned());
+        assert!(my_toggles.c().is_none());
+
+        let page = admin_panel.show_feattle("c").await.unwrap();
+        let html = String::from_utf8(page.content).unwrap();
+        assert!(html.contains("Set to none"));
+        assert!(html.contains(r#"id="none_value""#));
+        assert!(html.contains("checked>"));
+
+        my_toggles
+            .update("c", json!(17), "user".to_owned())
+            .await
+            .unwrap();
+        let page = admin_panel.show_feattle("c").await.unwrap();
+        let html = String::from_utf8(page.content).unwrap();
+        assert!(html.contains("Set to none"));
+        assert!(!html.contains("checked>"));
+
+        let my_toggles = Arc::new(MyToggles::new(Arc::new(InMemoryPersistence::new())));
+        let admin_panel = AdminPanel::new(my_toggles, "label".to_owned());
+        let page = admin_panel.show_feattle("b").await.unwrap();
+        let html = String::from_utf8(page.content).unwrap();
+        assert!(!html.contains("Set to none"));
+    }
+
+    #[tokio::test]
+    async fn feattle_error() {
+        async fn show(
+            admin_panel: &AdminPanel<MyToggles>,
+            key: &str,
+        ) -> Result<RenderedPage, FeattleError> {
+            Ok(admin_panel.show_feattle(key).await?)
+        }
+
+        async fn edit(admin_panel: &AdminPanel<MyToggles>) -> Result<(), FeattleError> {
+            admin_panel
+                .edit_feattle("a", "17", "user".to_owned())
+                .await?;
+            Ok(())
+        }
+
+        let my_toggles = Arc::new(MyToggles::new(Arc::new(InMemoryPersistence::new())));
+        let admin_panel = AdminPanel::new(my_toggles, "label".to_owned());
+        show(&admin_panel, "a").await.unwrap();
+        assert!(matches!(
+            show(&admin_panel, "c").await,
+            Err(FeattleError::Render(_))
+        ));
+        assert!(matches!(
+            edit(&admin_panel).await,
+            Err(FeattleError::Update(UpdateError::Parsing(_)))
+        ));
+        let error = RenderError::History(HistoryError::UnknownKey("c".to_owned()));
+        assert!(matches!(
+            FeattleError::from(error),
+            FeattleError::History(HistoryError::UnknownKey(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn examples() {
+        feattles! {
+            struct ExampleToggles {
+                #[example = r#"["a", "b"]"#]
+                #[example = "[]"]
+                names: Vec<String>,
+            }
+        }
+
+        let my_toggles = Arc::new(ExampleToggles::new(Arc::new(InMemoryPersistence::new())));
+        let admin_panel = AdminPanel::new(my_toggles.clone(), "label".to_owned());
+        assert!(my_toggles.names().is_empty());
+        let examples = [r#"["a", "b"]"#, "[]"];
+        assert_eq!(my_toggles.definition("names").unwrap().examples, examples);
+
+        let response = admin_panel.show_feattle_api_v1("names").await.unwrap();
+        assert_eq!(response.definition.examples, examples);
+
+        let page = admin_panel.show_feattle("names").await.unwrap();
+        let html = String::from_utf8(page.content).unwrap();
+        assert!(html.contains(r#"data-example="[&quot;a&quot;, &quot;b&quot;]""#));
+        assert!(html.contains(r#"data-example="[]""#));
+    }
+
+    #[tokio::test]
+    async fn json_schema() {
+        feattle_core::feattle_enum! {enum Color { Red, Green, Blue }}
+        feattles! {
+            struct ChoicesToggles { color: Color = Color::Green }
+        }
+
+        let my_toggles = Arc::new(ChoicesToggles::new(Arc::new(InMemoryPersistence::new())));
+        let admin_panel = AdminPanel::new(my_toggles.clone(), "label".to_owned());
+        assert_eq!(*my_toggles.color(), Color::Green);
+
+        let page = admin_panel.show_feattle("color").await.unwrap();
+        let html = String::from_utf8(page.content).unwrap();
+        assert!(html.contains("<summary>JSON Schema</summary>"));
+        assert!(html.contains("&quot;enum&quot;: ["));
+        assert!(html.contains("&quot;Green&quot;"));
+    }
+
+    #[tokio::test]
+    async fn optional_object() {
+        use feattle_core::LatLng;
+
+        feattles! {
+            struct ObjectToggles { location: Option<LatLng> }
+        }
+
+        let my_toggles = Arc::new(ObjectToggles::new(Arc::new(InMemoryPersistence::new())));
+        let admin_panel = AdminPanel::new(my_toggles.clone(), "label".to_owned());
+
+        // The editor of the inner object is built from a `null` value
+        let page = admin_panel.show_feattle("location").await.unwrap();
+        let html = String::from_utf8(page.content).unwrap();
+        assert!(html.contains(r#"data-value="null""#));
+        assert!(html.contains("&quot;tag&quot;:&quot;Optional&quot;"));
+        assert!(html.contains("&quot;tag&quot;:&quot;Object&quot;"));
+
+        admin_panel
+            .edit_feattle(
+                "location",
+                r#"{"lat": -23.55, "lng": -46.63}"#,
+                "user".to_owned(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(*my_toggles.location(), LatLng::new(-23.55, -46.63));
+        let page = admin_panel.show_feattle("location").await.unwrap();
+        let html = String::from_utf8(page.content).unwrap();
+        assert!(html.contains("&quot;lat&quot;:-23.55"));
+    }
+
     #[tokio::test]
     async fn test() {
         use feattle_core::persist::NoPersistence;
@@ -239,7 +1417,7 @@ mod tests {
         ));
 
         // Just check the methods return
-        admin_panel.list_feattles().await.unwrap();
+        admin_panel.list_feattles(Default::default()).await.unwrap();
         admin_panel.show_feattle("a").await.unwrap();
         admin_panel.show_feattle("non-existent").await.unwrap_err();
         admin_panel.render_public_file("script.js").unwrap();
@@ -252,5 +1430,176 @@ mod tests {
             .edit_feattle("a", "17", "user".to_owned())
             .await
             .unwrap_err();
+        admin_panel
+            .diff_feattle_api_v1("a", v1::DiffFeattleQuery { from: 0, to: 1 })
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn evaluate_feattle() {
+        use feattle_core::Rollout;
+
+        feattles! {
+            struct RolloutToggles { rollout: Rollout }
+        }
+
+        let toggles = Arc::new(RolloutToggles::new(Arc::new(InMemoryPersistence::new())));
+        toggles.reload().await.unwrap();
+        let admin_panel = AdminPanel::new(toggles, "Project Panda - DEV".to_owned());
+        admin_panel
+            .edit_feattle("rollout", "50", "somebody".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(*admin_panel.feattles.rollout(), Rollout::new(50.0).unwrap());
+
+        let evaluate = |user_key: Option<&str>| {
+            let request = v1::EvaluateFeattleRequest {
+                user_key: user_key.map(str::to_owned),
+                at: None,
+            };
+            admin_panel.evaluate_feattle_api_v1("rollout", request)
+        };
+        assert_eq!(evaluate(Some("user-0")).await.unwrap().value, json!(false));
+        assert_eq!(evaluate(Some("user-1")).await.unwrap().value, json!(true));
+        assert_eq!(evaluate(None).await.unwrap().value, json!(false));
+
+        // Other types evaluate to their value
+        let my_toggles = Arc::new(MyToggles::new(Arc::new(InMemoryPersistence::new())));
+        let admin_panel = AdminPanel::new(my_toggles, "Project Panda - DEV".to_owned());
+        let request = v1::EvaluateFeattleRequest {
+            user_key: Some("user-0".to_owned()),
+            at: None,
+        };
+        let response = admin_panel.evaluate_feattle_api_v1("b", request.clone());
+        assert_eq!(response.await.unwrap().value, json!(0));
+        let response = admin_panel.evaluate_feattle_api_v1("non-existent", request);
+        assert!(matches!(response.await, Err(RenderError::NotFound)));
+    }
+
+    #[tokio::test]
+    #[allow(dead_code)]
+    async fn export_env() {
+        feattles! {
+            struct EnvToggles {
+                enabled: bool,
+                greeting: String = "hello, it's me".to_owned(),
+                code: String = "17".to_owned(),
+                ids: Vec<i32> = vec![1, 2],
+                database: DatabaseToggles {
+                    pool_size: i32 = 10,
+                },
+            }
+        }
+
+        let toggles = Arc::new(EnvToggles::new(Arc::new(InMemoryPersistence::new())));
+        let admin_panel = AdminPanel::new(toggles, "Env".to_owned());
+
+        let page = admin_panel.export_env().await.unwrap();
+        assert_eq!(page.content_type, "text/plain; charset=utf-8");
+        assert_eq!(
+            String::from_utf8(page.content).unwrap(),
+            "FEATTLE_ENABLED=false\n\
+            FEATTLE_GREETING='\"hello, it'\\''s me\"'\n\
+            FEATTLE_CODE='\"17\"'\n\
+            FEATTLE_IDS='[1,2]'\n\
+            FEATTLE_DATABASE_POOL_SIZE=10\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn changes_by_user() {
+        let my_toggles = Arc::new(MyToggles::new(Arc::new(InMemoryPersistence::new())));
+        my_toggles.reload().await.unwrap();
+        let admin_panel = AdminPanel::new(my_toggles.clone(), "label".to_owned());
+        for (key, value, user) in [
+            ("a", json!(true), "alice"),
+            ("b", json!(1), "bob"),
+            ("b", json!(2), "alice"),
+            ("a", json!(false), "alice"),
+            ("b", json!(3), "alice2"),
+        ] {
+            my_toggles
+                .update(key, value, user.to_owned())
+                .await
+                .unwrap();
+        }
+        assert!(!*my_toggles.a() && *my_toggles.b() == 3);
+
+        let changes = admin_panel
+            .changes_by_user_api_v1("alice")
+            .await
+            .unwrap()
+            .changes;
+        let values: Vec<_> = changes
+            .iter()
+            .map(|(key, entries)| {
+                let values: Vec<_> = entries.iter().map(|entry| entry.value.clone()).collect();
+                (key.as_str(), values)
+            })
+            .collect();
+        assert_eq!(
+            values,
+            [
+                ("a", vec![json!(true), json!(false)]),
+                ("b", vec![json!(2)])
+            ]
+        );
+
+        let changes = admin_panel
+            .changes_by_user_api_v1("bob")
+            .await
+            .unwrap()
+            .changes;
+        assert_eq!(changes.keys().collect::<Vec<_>>(), ["b"]);
+        assert!(admin_panel
+            .changes_by_user_api_v1("carol")
+            .await
+            .unwrap()
+            .changes
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn full_backup() {
+        let persistence = Arc::new(InMemoryPersistence::new());
+        let my_toggles = Arc::new(MyToggles::new(persistence.clone()));
+        my_toggles.reload().await.unwrap();
+        let admin_panel = AdminPanel::new(my_toggles.clone(), "Project Panda - DEV".to_owned());
+        admin_panel
+            .edit_feattle("a", "true", "somebody".to_owned())
+            .await
+            .unwrap();
+        admin_panel
+            .edit_feattle("b", "17", "somebody".to_owned())
+            .await
+            .unwrap();
+        admin_panel
+            .edit_feattle("b", "27", "somebody else".to_owned())
+            .await
+            .unwrap();
+
+        let backup = admin_panel.full_backup().await.unwrap();
+        assert_eq!(backup.current_values.as_ref().unwrap().version, 3);
+        assert_eq!(backup.histories["a"].entries.len(), 1);
+        assert_eq!(backup.histories["b"].entries.len(), 2);
+
+        // Round-trip through JSON, then restore into an empty storage
+        let backup: v1::FullBackup =
+            serde_json::from_str(&serde_json::to_string(&backup).unwrap()).unwrap();
+        persistence.clear();
+        let cleared = MyToggles::new(persistence.clone());
+        cleared.reload().await.unwrap();
+        assert!(!*cleared.a());
+        let admin_panel = AdminPanel::new(Arc::new(cleared), "Project Panda - DEV".to_owned());
+        admin_panel.restore(&backup).await.unwrap();
+
+        assert_eq!(admin_panel.full_backup().await.unwrap(), backup);
+        assert!(*admin_panel.feattles.a());
+        assert_eq!(*admin_panel.feattles.b(), 27);
+        assert_eq!(
+            admin_panel.feattles.definitions()[1].modified_by.as_deref(),
+            Some("somebody else")
+        );
     }
 }
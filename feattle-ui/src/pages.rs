@@ -1,8 +1,10 @@
+use crate::api::v1::{CompareResponse, JsonChange, ListFeattlesResponse};
+use crate::diff::json_diff;
 use crate::RenderedPage;
 use chrono::{DateTime, Utc};
 use feattle_core::last_reload::LastReload;
-use feattle_core::persist::ValueHistory;
-use feattle_core::FeattleDefinition;
+use feattle_core::persist::{PendingChange, ValueHistory};
+use feattle_core::{ExpiryStatus, FeattleDefinition, SerializedFormatKind};
 use handlebars::Handlebars;
 use serde_json::json;
 use std::collections::BTreeMap;
@@ -49,6 +51,7 @@ impl Pages {
         register_template!("layout");
         register_template!("feattles");
         register_template!("feattle");
+        register_template!("compare");
 
         let mut public_files = BTreeMap::new();
         macro_rules! insert_public_file {
@@ -81,13 +84,10 @@ impl Pages {
         })
     }
 
-    pub fn render_feattles(
-        &self,
-        definitions: &[FeattleDefinition],
-        last_reload: LastReload,
-        reload_failed: bool,
-    ) -> PageResult {
-        let feattles: Vec<_> = definitions
+    pub fn render_feattles(&self, data: &ListFeattlesResponse) -> PageResult {
+        let last_reload = data.last_reload;
+        let feattles: Vec<_> = data
+            .definitions
             .iter()
             .map(|definition| {
                 json!({
@@ -96,6 +96,12 @@ impl Pages {
                     "description": definition.description,
                     "value_overview": definition.value_overview,
                     "last_modification": last_modification(definition, last_reload),
+                    "expiry": expiry(definition),
+                    "read_only": definition.read_only,
+                    "tags": definition.tags.iter().map(|tag| json!({
+                        "tag": tag,
+                        "query": url_encode(tag),
+                    })).collect::<Vec<_>>(),
                 })
             })
             .collect();
@@ -119,7 +125,15 @@ impl Pages {
                  "label": self.label,
                  "last_reload": last_reload_str,
                  "version": version,
-                 "reload_failed": reload_failed,
+                 "reload_failed": data.reload_failed,
+                 "paginated": data.page_count > 1,
+                 "page": data.page,
+                 "page_count": data.page_count,
+                 "per_page": data.per_page,
+                 "previous_page": Some(data.page - 1).filter(|&page| page >= 1),
+                 "next_page": Some(data.page + 1).filter(|&page| page <= data.page_count),
+                 "tag": data.tag,
+                 "tag_query": data.tag.as_deref().map(url_encode),
             }),
         ))
     }
@@ -128,18 +142,28 @@ impl Pages {
         &self,
         definition: &FeattleDefinition,
         history: &ValueHistory,
+        pending: Option<&PendingChange>,
         last_reload: LastReload,
         reload_failed: bool,
     ) -> PageResult {
         let history = history
             .entries
             .iter()
-            .map(|entry| -> Result<_, PageError> {
+            .enumerate()
+            .map(|(i, entry)| -> Result<_, PageError> {
+                let changes: Vec<_> = match i.checked_sub(1) {
+                    None => vec![],
+                    Some(previous) => json_diff(&history.entries[previous].value, &entry.value)
+                        .iter()
+                        .map(change_string)
+                        .collect(),
+                };
                 Ok(json!({
                     "modified_at": date_string(entry.modified_at),
                     "modified_by": entry.modified_by,
                     "value_overview": entry.value_overview,
                     "value_json": serde_json::to_string(&entry.value)?,
+                    "changes": changes,
                 }))
             })
             .collect::<Result<Vec<_>, _>>()?;
@@ -152,8 +176,24 @@ impl Pages {
                 "description": definition.description,
                 "value_overview": definition.value_overview,
                 "last_modification": last_modification(definition, last_reload),
+                "expiry": expiry(definition),
                 "format_json": serde_json::to_string(&definition.format.kind)?,
+                "schema_json": serde_json::to_string_pretty(&definition.format.kind.json_schema())?,
                 "value_json": serde_json::to_string(&definition.value)?,
+                "is_bool": definition.format.kind == SerializedFormatKind::Bool,
+                "bool_value": definition.value.as_bool().unwrap_or(false),
+                "is_optional": matches!(definition.format.kind, SerializedFormatKind::Optional(_)),
+                "is_none": definition.value.is_null(),
+                "read_only": definition.read_only,
+                "examples": definition.examples,
+                "pending": pending.map(|pending| -> Result<_, PageError> {
+                    Ok(json!({
+                        "value_json": serde_json::to_string(&pending.value)?,
+                        "proposed_at": date_string(pending.proposed_at),
+                        "proposed_by": pending.proposed_by,
+                    }))
+                }).transpose()?,
+                "staging_json": definition.staging.as_ref().map(serde_json::to_string).transpose()?,
                 "label": self.label,
                 "history": history,
                 "reload_failed": reload_failed,
@@ -161,6 +201,33 @@ impl Pages {
         ))
     }
 
+    pub fn render_compare(&self, data: &CompareResponse) -> PageResult {
+        let feattles = data
+            .feattles
+            .iter()
+            .map(|entry| -> Result<_, PageError> {
+                Ok(json!({
+                    "key": entry.key,
+                    "value_json": entry.value.as_ref().map(serde_json::to_string).transpose()?,
+                    "other_value_json": serde_json::to_string(&entry.other_value)?,
+                    "different": entry.different,
+                    "changes": entry.changes.iter().map(change_string).collect::<Vec<_>>(),
+                }))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Self::convert_html(self.handlebars.render(
+            "compare",
+            &json!({
+                "label": self.label,
+                "other_label": data.other_label,
+                "feattles": feattles,
+                "different_count": data.feattles.iter().filter(|entry| entry.different).count(),
+                "reload_failed": data.reload_failed,
+            }),
+        ))
+    }
+
     fn convert_html(rendered: Result<String, handlebars::RenderError>) -> PageResult {
         let content = rendered?;
         Ok(RenderedPage {
@@ -182,6 +249,46 @@ fn last_modification(definition: &FeattleDefinition, last_reload: LastReload) ->
     }
 }
 
+fn expiry(definition: &FeattleDefinition) -> Option<String> {
+    let expires = definition.expires?;
+    Some(match definition.expiry_status {
+        None => format!("expires on {}", expires),
+        Some(ExpiryStatus::ExpiresSoon) => format!("expires soon, on {}", expires),
+        Some(ExpiryStatus::Expired) => format!("expired on {}, consider removing it", expires),
+    })
+}
+
+fn change_string(change: &JsonChange) -> String {
+    let path = |path: &str| {
+        if path.is_empty() {
+            "(value)".to_owned()
+        } else {
+            path.to_owned()
+        }
+    };
+    match change {
+        JsonChange::Added { path: p, value } => format!("+ {}: {}", path(p), value),
+        JsonChange::Removed { path: p, value } => format!("- {}: {}", path(p), value),
+        JsonChange::Changed { path: p, old, new } => {
+            format!("~ {}: {} → {}", path(p), old, new)
+        }
+    }
+}
+
+/// Percent-encode a value to be used in a query string
+fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+                (byte as char).to_string()
+            } else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect()
+}
+
 fn date_string(datetime: DateTime<Utc>) -> String {
     datetime.format("%Y-%m-%d %H:%M:%S %Z").to_string()
 }
@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+/// The response extension used by the edit handlers to report who made the change
+#[cfg(any(feature = "axum", feature = "warp"))]
+#[derive(Debug, Clone)]
+pub struct ModifiedBy(pub String);
+
+/// Emit the access log line for a single request, with the target `feattle_ui::access`
+pub fn log_request(
+    method: &str,
+    path: &str,
+    status: u16,
+    duration: Duration,
+    modified_by: Option<&str>,
+) {
+    match modified_by {
+        None => log::info!(
+            target: "feattle_ui::access",
+            "method={} path={} status={} duration_ms={}",
+            method,
+            path,
+            status,
+            duration.as_millis()
+        ),
+        Some(modified_by) => log::info!(
+            target: "feattle_ui::access",
+            "method={} path={} status={} duration_ms={} modified_by={:?}",
+            method,
+            path,
+            status,
+            duration.as_millis(),
+            modified_by
+        ),
+    }
+}
@@ -1,12 +1,18 @@
 use crate::api::v1;
+use crate::request_log::{log_request, ModifiedBy};
 use crate::{AdminPanel, RenderError, RenderedPage};
 use feattle_core::{Feattles, UpdateError};
 use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::future::Future;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use warp::filters::path;
-use warp::http::{StatusCode, Uri};
+use std::time::Instant;
+use warp::body::BodyDeserializeError;
+use warp::filters::path::{self, FullPath};
+use warp::http::{HeaderMap, Method, StatusCode, Uri};
 use warp::reject::Reject;
+use warp::reply::Response;
 use warp::{reject, reply, Filter, Rejection, Reply};
 
 #[derive(Debug)]
@@ -23,9 +29,20 @@ struct EditFeattleForm {
 /// To use it, make sure to activate the cargo feature `"warp"` in your `Cargo.toml`.
 ///
 /// This will host the web UI under "/" and a JSON API under "/api/v1/" (see more at [`v1`]):
-/// - GET /api/v1/feattles
+/// - GET /api/v1/feattles?page={page}&per_page={per_page}&tag={tag}
+/// - GET /api/v1/version
+/// - GET /api/v1/compare
+/// - GET /api/v1/export.env
+/// - GET /api/v1/changes?user={user}
 /// - GET /api/v1/feattle/{key}
 /// - POST /api/v1/feattle/{key}
+/// - POST /api/v1/feattle/{key}/propose
+/// - POST /api/v1/feattle/{key}/approve
+/// - POST /api/v1/feattle/{key}/reject
+/// - POST /api/v1/feattle/{key}/staging
+/// - POST /api/v1/feattle/{key}/promote
+/// - GET /api/v1/feattle/{key}/diff?from={from}&to={to}
+/// - POST /api/v1/feattle/{key}/evaluate
 ///
 /// # Example
 /// ```no_run
@@ -54,24 +71,137 @@ pub async fn run_warp_server<F>(
 ) where
     F: Feattles + Sync + Send + 'static,
 {
+    warp::serve(routes(admin_panel)).run(addr).await;
+}
+
+/// Bind the given admin panel using [`warp`] framework, like [`run_warp_server`], but stop
+/// accepting new connections when the `signal` future completes.
+///
+/// This returns the bound address and the future that runs the server. This future only resolves
+/// after the signal completes and all in-flight requests were answered, so that the shutdown is
+/// graceful.
+///
+/// To use it, make sure to activate the cargo feature `"warp"` in your `Cargo.toml`.
+///
+/// # Panics
+/// Panics if it fails to bind to the given address.
+///
+/// # Example
+/// ```no_run
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use feattle_ui::{AdminPanel, bind_warp_server_with_graceful_shutdown};
+/// use feattle_core::{feattles, Feattles};
+/// use feattle_core::persist::NoPersistence;
+/// use std::sync::Arc;
+///
+/// feattles! {
+///     struct MyToggles { a: bool, b: i32 }
+/// }
+///
+/// // `NoPersistence` here is just a mock for the sake of the example
+/// let my_toggles = Arc::new(MyToggles::new(Arc::new(NoPersistence)));
+/// let admin_panel = Arc::new(AdminPanel::new(my_toggles, "Project Panda - DEV".to_owned()));
+///
+/// let (_addr, server) = bind_warp_server_with_graceful_shutdown(
+///     admin_panel,
+///     ([127, 0, 0, 1], 3030),
+///     async {
+///         tokio::signal::ctrl_c().await.ok();
+///     },
+/// );
+/// server.await;
+/// # Ok(())
+/// # }
+/// ```
+pub fn bind_warp_server_with_graceful_shutdown<F>(
+    admin_panel: Arc<AdminPanel<F>>,
+    addr: impl Into<SocketAddr> + 'static,
+    signal: impl Future<Output = ()> + Send + 'static,
+) -> (SocketAddr, impl Future<Output = ()> + 'static)
+where
+    F: Feattles + Sync + Send + 'static,
+{
+    warp::serve(routes(admin_panel)).bind_with_graceful_shutdown(addr, signal)
+}
+
+fn routes<F>(
+    admin_panel: Arc<AdminPanel<F>>,
+) -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone + Send + Sync + 'static
+where
+    F: Feattles + Sync + Send + 'static,
+{
+    let request_logging = admin_panel.request_logging;
     let admin_panel = warp::any().map(move || admin_panel.clone());
 
     let list_feattles = warp::path::end()
+        .and(warp::get())
+        .and(admin_panel.clone())
+        .and(warp::query())
+        .and_then(
+            |admin_panel: Arc<AdminPanel<F>>, query: v1::ListFeattlesQuery| async move {
+                admin_panel
+                    .list_feattles(query)
+                    .await
+                    .map_err(to_rejection)
+                    .map(to_reply)
+            },
+        );
+
+    let list_feattles_api = warp::path!("feattles")
+        .and(warp::get())
+        .and(admin_panel.clone())
+        .and(warp::query())
+        .and_then(
+            |admin_panel: Arc<AdminPanel<F>>, query: v1::ListFeattlesQuery| async move {
+                to_json_result(admin_panel.list_feattles_api_v1(query).await)
+            },
+        );
+
+    let compare = warp::path!("compare")
         .and(warp::get())
         .and(admin_panel.clone())
         .and_then(|admin_panel: Arc<AdminPanel<F>>| async move {
             admin_panel
-                .list_feattles()
+                .compare()
                 .await
                 .map_err(to_rejection)
                 .map(to_reply)
         });
 
-    let list_feattles_api = warp::path!("feattles")
+    let compare_api = warp::path!("compare")
         .and(warp::get())
         .and(admin_panel.clone())
         .and_then(|admin_panel: Arc<AdminPanel<F>>| async move {
-            to_json_result(admin_panel.list_feattles_api_v1().await)
+            to_json_result(admin_panel.compare_api_v1().await)
+        });
+
+    let version_api = warp::path!("version")
+        .and(warp::get())
+        .and(admin_panel.clone())
+        .and_then(|admin_panel: Arc<AdminPanel<F>>| async move {
+            to_json_result(admin_panel.version_api_v1().await)
+        });
+
+    let changes_by_user_api = warp::path!("changes")
+        .and(warp::get())
+        .and(admin_panel.clone())
+        .and(warp::query())
+        .and_then(
+            |admin_panel: Arc<AdminPanel<F>>, query: v1::ChangesByUserQuery| async move {
+                to_json_result(admin_panel.changes_by_user_api_v1(&query.user).await)
+            },
+        );
+
+    let export_env_api = warp::path!("export.env")
+        .and(warp::get())
+        .and(admin_panel.clone())
+        .and_then(|admin_panel: Arc<AdminPanel<F>>| async move {
+            admin_panel
+                .export_env()
+                .await
+                .map_err(to_rejection)
+                .map(to_reply)
         });
 
     let show_feattle = warp::path!("feattle" / String)
@@ -95,27 +225,217 @@ pub async fn run_warp_server<F>(
     let edit_feattle = warp::path!("feattle" / String / "edit")
         .and(warp::post())
         .and(admin_panel.clone())
+        .and(warp::header::headers_cloned())
         .and(warp::body::form())
         .and_then(
-            |key: String, admin_panel: Arc<AdminPanel<F>>, form: EditFeattleForm| async move {
-                admin_panel
-                    .edit_feattle(&key, &form.value_json, "admin".to_owned())
-                    .await
-                    .map_err(to_rejection)
-                    .map(|_| warp::redirect(Uri::from_static("/")))
+            |key: String,
+             admin_panel: Arc<AdminPanel<F>>,
+             headers: HeaderMap,
+             form: EditFeattleForm| async move {
+                let modified_by =
+                    match admin_panel.request_user(header(&headers), "admin".to_owned()) {
+                        Ok(user) => user,
+                        Err(err) => return to_error_reply(err),
+                    };
+                let result = admin_panel
+                    .edit_feattle(&key, &form.value_json, modified_by.clone())
+                    .await;
+                with_modified_by(to_redirect_result(result), modified_by)
+            },
+        );
+
+    let edit_feattle_api = warp::path!("feattle" / String)
+        .and(warp::post())
+        .and(admin_panel.clone())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::json())
+        .and_then(
+            |key: String,
+             admin_panel: Arc<AdminPanel<F>>,
+             headers: HeaderMap,
+             mut request: v1::EditFeattleRequest| async move {
+                let modified_by =
+                    match admin_panel.request_user(header(&headers), request.modified_by) {
+                        Ok(user) => user,
+                        Err(err) => return to_error_reply(err),
+                    };
+                request.modified_by = modified_by.clone();
+                let result = admin_panel.edit_feattle_api_v1(&key, request).await;
+                with_modified_by(to_json_result(result), modified_by)
+            },
+        );
+
+    let approve_feattle = warp::path!("feattle" / String / "approve")
+        .and(warp::post())
+        .and(admin_panel.clone())
+        .and(warp::header::headers_cloned())
+        .and_then(
+            |key: String, admin_panel: Arc<AdminPanel<F>>, headers: HeaderMap| async move {
+                let approved_by =
+                    match admin_panel.request_user(header(&headers), "admin".to_owned()) {
+                        Ok(user) => user,
+                        Err(err) => return to_error_reply(err),
+                    };
+                let result = admin_panel.approve_feattle(&key, approved_by.clone()).await;
+                with_modified_by(to_redirect_result(result), approved_by)
+            },
+        );
+
+    let reject_feattle = warp::path!("feattle" / String / "reject")
+        .and(warp::post())
+        .and(admin_panel.clone())
+        .and(warp::header::headers_cloned())
+        .and_then(
+            |key: String, admin_panel: Arc<AdminPanel<F>>, headers: HeaderMap| async move {
+                let result = async {
+                    let rejected_by =
+                        admin_panel.request_user(header(&headers), "admin".to_owned())?;
+                    admin_panel.reject_feattle(&key, rejected_by).await
+                }
+                .await;
+                to_redirect_result(result)
             },
         );
 
-    let edit_feattle_api =
-        warp::path!("feattle" / String)
+    let promote_feattle = warp::path!("feattle" / String / "promote")
+        .and(warp::post())
+        .and(admin_panel.clone())
+        .and(warp::header::headers_cloned())
+        .and_then(
+            |key: String, admin_panel: Arc<AdminPanel<F>>, headers: HeaderMap| async move {
+                let modified_by =
+                    match admin_panel.request_user(header(&headers), "admin".to_owned()) {
+                        Ok(user) => user,
+                        Err(err) => return to_error_reply(err),
+                    };
+                let result = admin_panel.promote_feattle(&key, modified_by.clone()).await;
+                with_modified_by(to_redirect_result(result), modified_by)
+            },
+        );
+
+    let propose_feattle_api = warp::path!("feattle" / String / "propose")
+        .and(warp::post())
+        .and(admin_panel.clone())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::json())
+        .and_then(
+            |key: String,
+             admin_panel: Arc<AdminPanel<F>>,
+             headers: HeaderMap,
+             mut request: v1::ProposeFeattleRequest| async move {
+                let result = async {
+                    request.proposed_by =
+                        admin_panel.request_user(header(&headers), request.proposed_by)?;
+                    admin_panel.propose_feattle_api_v1(&key, request).await
+                }
+                .await;
+                to_json_result(result)
+            },
+        );
+
+    let approve_feattle_api = warp::path!("feattle" / String / "approve")
+        .and(warp::post())
+        .and(admin_panel.clone())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::json())
+        .and_then(
+            |key: String,
+             admin_panel: Arc<AdminPanel<F>>,
+             headers: HeaderMap,
+             mut request: v1::ReviewFeattleRequest| async move {
+                let approved_by =
+                    match admin_panel.request_user(header(&headers), request.reviewed_by) {
+                        Ok(user) => user,
+                        Err(err) => return to_error_reply(err),
+                    };
+                request.reviewed_by = approved_by.clone();
+                let result = admin_panel.approve_feattle_api_v1(&key, request).await;
+                with_modified_by(to_json_result(result), approved_by)
+            },
+        );
+
+    let reject_feattle_api = warp::path!("feattle" / String / "reject")
+        .and(warp::post())
+        .and(admin_panel.clone())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::json())
+        .and_then(
+            |key: String,
+             admin_panel: Arc<AdminPanel<F>>,
+             headers: HeaderMap,
+             mut request: v1::ReviewFeattleRequest| async move {
+                let result = async {
+                    request.reviewed_by =
+                        admin_panel.request_user(header(&headers), request.reviewed_by)?;
+                    admin_panel.reject_feattle_api_v1(&key, request).await
+                }
+                .await;
+                to_json_result(result)
+            },
+        );
+
+    let staging_feattle_api = warp::path!("feattle" / String / "staging")
+        .and(warp::post())
+        .and(admin_panel.clone())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::json())
+        .and_then(
+            |key: String,
+             admin_panel: Arc<AdminPanel<F>>,
+             headers: HeaderMap,
+             mut request: v1::StagingFeattleRequest| async move {
+                let modified_by =
+                    match admin_panel.request_user(header(&headers), request.modified_by) {
+                        Ok(user) => user,
+                        Err(err) => return to_error_reply(err),
+                    };
+                request.modified_by = modified_by.clone();
+                let result = admin_panel.staging_feattle_api_v1(&key, request).await;
+                with_modified_by(to_json_result(result), modified_by)
+            },
+        );
+
+    let promote_feattle_api = warp::path!("feattle" / String / "promote")
+        .and(warp::post())
+        .and(admin_panel.clone())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::json())
+        .and_then(
+            |key: String,
+             admin_panel: Arc<AdminPanel<F>>,
+             headers: HeaderMap,
+             mut request: v1::PromoteFeattleRequest| async move {
+                let modified_by =
+                    match admin_panel.request_user(header(&headers), request.modified_by) {
+                        Ok(user) => user,
+                        Err(err) => return to_error_reply(err),
+                    };
+                request.modified_by = modified_by.clone();
+                let result = admin_panel.promote_feattle_api_v1(&key, request).await;
+                with_modified_by(to_json_result(result), modified_by)
+            },
+        );
+
+    let diff_feattle_api = warp::path!("feattle" / String / "diff")
+        .and(warp::get())
+        .and(admin_panel.clone())
+        .and(warp::query())
+        .and_then(
+            |key: String, admin_panel: Arc<AdminPanel<F>>, query: v1::DiffFeattleQuery| async move {
+                to_json_result(admin_panel.diff_feattle_api_v1(&key, query).await)
+            },
+        );
+
+    let evaluate_feattle_api =
+        warp::path!("feattle" / String / "evaluate")
             .and(warp::post())
             .and(admin_panel.clone())
             .and(warp::body::json())
             .and_then(
                 |key: String,
                  admin_panel: Arc<AdminPanel<F>>,
-                 request: v1::EditFeattleRequest| async move {
-                    to_json_result(admin_panel.edit_feattle_api_v1(&key, request).await)
+                 request: v1::EvaluateFeattleRequest| async move {
+                    to_json_result(admin_panel.evaluate_feattle_api_v1(&key, request).await)
                 },
             );
 
@@ -131,19 +451,56 @@ pub async fn run_warp_server<F>(
             },
         );
 
-    let api = path::path("api")
-        .and(path::path("v1"))
-        .and(list_feattles_api.or(show_feattle_api).or(edit_feattle_api));
-
-    warp::serve(
-        list_feattles
-            .or(show_feattle)
-            .or(edit_feattle)
-            .or(public_files)
-            .or(api),
-    )
-    .run(addr)
-    .await;
+    let api = path::path("api").and(path::path("v1")).and(
+        list_feattles_api
+            .or(version_api)
+            .or(compare_api)
+            .or(export_env_api)
+            .or(changes_by_user_api)
+            .or(show_feattle_api)
+            .or(edit_feattle_api)
+            .or(propose_feattle_api)
+            .or(approve_feattle_api)
+            .or(reject_feattle_api)
+            .or(staging_feattle_api)
+            .or(promote_feattle_api)
+            .or(diff_feattle_api)
+            .or(evaluate_feattle_api),
+    );
+
+    let routes = list_feattles
+        .or(compare)
+        .or(show_feattle)
+        .or(edit_feattle)
+        .or(approve_feattle)
+        .or(reject_feattle)
+        .or(promote_feattle)
+        .or(public_files)
+        .or(api)
+        .map(Reply::into_response)
+        .recover(to_rejection_response)
+        .unify();
+
+    warp::any()
+        .map(Instant::now)
+        .and(warp::method())
+        .and(path::full())
+        .and(routes)
+        .map(
+            move |start: Instant, method: Method, path: FullPath, response: Response| {
+                if request_logging {
+                    let modified_by = response.extensions().get::<ModifiedBy>();
+                    log_request(
+                        method.as_str(),
+                        path.as_str(),
+                        response.status().as_u16(),
+                        start.elapsed(),
+                        modified_by.map(|modified_by| modified_by.0.as_str()),
+                    );
+                }
+                response
+            },
+        )
 }
 
 impl Reject for RequestError {}
@@ -166,13 +523,205 @@ fn to_json_result<T: Serialize>(
 ) -> Result<Box<dyn Reply>, Rejection> {
     match value {
         Ok(ok) => Ok(Box::new(reply::json(&ok))),
-        Err(RenderError::NotFound) | Err(RenderError::Update(UpdateError::UnknownKey(_))) => {
+        Err(err) => to_error_reply(err),
+    }
+}
+
+fn to_redirect_result(value: Result<(), RenderError>) -> Result<Box<dyn Reply>, Rejection> {
+    match value {
+        Ok(()) => Ok(Box::new(warp::redirect(Uri::from_static("/")))),
+        Err(err) => to_error_reply(err),
+    }
+}
+
+fn to_error_reply(error: RenderError) -> Result<Box<dyn Reply>, Rejection> {
+    match error {
+        RenderError::NotFound
+        | RenderError::Update(UpdateError::UnknownKey(_))
+        | RenderError::Update(UpdateError::NoPendingChange(_))
+        | RenderError::Update(UpdateError::NoStagingValue(_)) => {
             Ok(Box::new(StatusCode::NOT_FOUND))
         }
-        Err(RenderError::Update(UpdateError::Parsing(err))) => Ok(Box::new(reply::with_status(
+        RenderError::Unauthenticated => Ok(Box::new(StatusCode::UNAUTHORIZED)),
+        RenderError::Update(UpdateError::ApprovalRequired(key)) => {
+            Ok(Box::new(reply::with_status(
+                format!("The change of {} must be proposed and approved", key),
+                StatusCode::FORBIDDEN,
+            )))
+        }
+        RenderError::Update(UpdateError::SelfApproval(key)) => Ok(Box::new(reply::with_status(
+            format!(
+                "The pending change of {} must be approved by another user",
+                key
+            ),
+            StatusCode::BAD_REQUEST,
+        ))),
+        RenderError::Update(UpdateError::ReadOnly(key)) => Ok(Box::new(reply::with_status(
+            format!("The key {} is derived and can't be updated", key),
+            StatusCode::BAD_REQUEST,
+        ))),
+        RenderError::Update(UpdateError::Rejected(reason)) => Ok(Box::new(reply::with_status(
+            format!("The update was rejected: {}", reason),
+            StatusCode::BAD_REQUEST,
+        ))),
+        RenderError::Update(UpdateError::Parsing(err)) => Ok(Box::new(reply::with_status(
             format!("Failed to parse: {:?}", err),
             StatusCode::BAD_REQUEST,
         ))),
-        Err(err) => Err(reject::custom(RequestError(err))),
+        err => Err(reject::custom(RequestError(err))),
+    }
+}
+
+/// Add the user that made the change to the response, to be logged
+fn with_modified_by(
+    reply: Result<Box<dyn Reply>, Rejection>,
+    modified_by: String,
+) -> Result<Box<dyn Reply>, Rejection> {
+    let mut response = reply?.into_response();
+    response.extensions_mut().insert(ModifiedBy(modified_by));
+    Ok(Box::new(response))
+}
+
+/// Answer the requests that were rejected, with the same status codes used by [`warp`]
+async fn to_rejection_response(rejection: Rejection) -> Result<Response, Infallible> {
+    let status = if rejection.is_not_found() {
+        StatusCode::NOT_FOUND
+    } else if rejection.find::<RequestError>().is_some() {
+        StatusCode::INTERNAL_SERVER_ERROR
+    } else if rejection.find::<reject::PayloadTooLarge>().is_some() {
+        StatusCode::PAYLOAD_TOO_LARGE
+    } else if rejection.find::<reject::UnsupportedMediaType>().is_some() {
+        StatusCode::UNSUPPORTED_MEDIA_TYPE
+    } else if rejection.find::<reject::LengthRequired>().is_some() {
+        StatusCode::LENGTH_REQUIRED
+    } else if rejection.find::<BodyDeserializeError>().is_some()
+        || rejection.find::<reject::InvalidQuery>().is_some()
+        || rejection.find::<reject::InvalidHeader>().is_some()
+        || rejection.find::<reject::MissingHeader>().is_some()
+    {
+        StatusCode::BAD_REQUEST
+    } else if rejection.find::<reject::MethodNotAllowed>().is_some() {
+        StatusCode::METHOD_NOT_ALLOWED
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    Ok(status.into_response())
+}
+
+/// Read a header of the request, as expected by [`AdminPanel::request_user()`]
+fn header(headers: &HeaderMap) -> impl FnOnce(&str) -> Option<String> + '_ {
+    move |name| Some(headers.get(name)?.to_str().ok()?.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use feattle_core::feattles;
+    use feattle_core::persist::{CurrentValues, NoPersistence, Persist, ValueHistory};
+    use feattle_core::BoxError;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::sync::{oneshot, Notify, Semaphore};
+
+    feattles! {
+        struct MyToggles { a: bool }
+    }
+
+    /// Block every load until a permit is added to `release`
+    struct BlockingPersistence {
+        entered: Notify,
+        release: Semaphore,
+    }
+
+    #[async_trait]
+    impl Persist for BlockingPersistence {
+        async fn save_current(&self, _value: &CurrentValues) -> Result<(), BoxError> {
+            Ok(())
+        }
+
+        async fn load_current(&self) -> Result<Option<CurrentValues>, BoxError> {
+            self.entered.notify_one();
+            self.release.acquire().await?.forget();
+            Ok(None)
+        }
+
+        async fn save_history(&self, _key: &str, _value: &ValueHistory) -> Result<(), BoxError> {
+            Ok(())
+        }
+
+        async fn load_history(&self, _key: &str) -> Result<Option<ValueHistory>, BoxError> {
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn graceful_shutdown() {
+        let persistence = Arc::new(BlockingPersistence {
+            entered: Notify::new(),
+            release: Semaphore::new(0),
+        });
+        let my_toggles = Arc::new(MyToggles::new(persistence.clone()));
+        let admin_panel = Arc::new(AdminPanel::new(my_toggles, "label".to_owned()));
+
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel::<()>();
+        let (addr, server) =
+            bind_warp_server_with_graceful_shutdown(admin_panel, ([127, 0, 0, 1], 0), async {
+                shutdown_receiver.await.ok();
+            });
+        let server = tokio::spawn(server);
+
+        // Start a request that will be pending in the persistence layer
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                b"GET /api/v1/version HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        persistence.entered.notified().await;
+
+        // The server must wait for the in-flight request
+        shutdown_sender.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!server.is_finished());
+
+        persistence.release.add_permits(1);
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+        server.await.unwrap();
+        TcpStream::connect(addr).await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn modified_by() {
+        let my_toggles = Arc::new(MyToggles::new(Arc::new(NoPersistence)));
+        let admin_panel = Arc::new(AdminPanel::new(my_toggles, "label".to_owned()));
+        let routes = routes(admin_panel);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/v1/feattle/a")
+            .body(r#"{"value": true, "modified_by": "somebody"}"#)
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let modified_by = response.extensions().get::<ModifiedBy>().unwrap();
+        assert_eq!(modified_by.0, "somebody");
+
+        // The rejected requests are answered with the usual status codes
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/v1/feattle/a")
+            .body("{}")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(response.extensions().get::<ModifiedBy>().is_none());
+        let response = warp::test::request().path("/unknown").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 }
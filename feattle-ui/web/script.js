@@ -24,13 +24,19 @@ class FeattleEditor {
             this._prepareChoices(this.format.content.content)
         } else if (this.format.tag === 'Optional') {
             this._prepareOptional(this.format.content)
+        } else if (this.format.tag === 'Object') {
+            this._prepareObject(this.format.content)
         } else {
             this._prepareOther()
         }
     }
 
     _prepareBool() {
-        this.sourceEl = this._newSwitch(this.editorEl, 'Value', this.initialValue)
+        // The switch may be already rendered by the page
+        let renderedEl = this.editorEl.children('.custom-switch').find('input[type=checkbox]')
+        this.sourceEl = renderedEl.length ?
+            renderedEl :
+            this._newSwitch(this.editorEl, 'Value', this.initialValue)
         this.getValue = () => this.sourceEl.prop('checked')
     }
 
@@ -56,38 +62,24 @@ class FeattleEditor {
     }
 
     _prepareChoices(choices) {
-        let radioGroupId = String(Math.random())
-        this.sourceEl = $(choices.map(choice => {
-            let radioId = String(Math.random())
-            return $('<div>', {
-                'class': 'custom-control custom-radio',
-                append: [
-                    $('<input>', {
-                        type: 'radio',
-                        name: radioGroupId,
-                        id: radioId,
-                        'class': 'custom-control-input',
-                        value: choice,
-                        checked: choice === this.initialValue
-                    }),
-                    $('<label>', {
-                        'class': 'custom-control-label',
-                        'for': radioId,
-                        text: choice
-                    })
-                ]
-            }).get(0)
-        }))
+        this.sourceEl = $('<select>', {
+            'class': 'custom-select',
+            append: choices.map(choice => $('<option>', {
+                value: choice,
+                text: choice,
+                selected: choice === this.initialValue
+            }))
+        })
         this.editorEl.append(this.sourceEl)
-        this.getValue = () => $('input:checked', this.sourceEl).val()
+        this.getValue = () => this.sourceEl.val()
     }
 
     _prepareOptional(innerFormat) {
-        this.switchEl = this._newSwitch(
-            this.editorEl,
-            'Define some value',
-            this.initialValue !== null
-        )
+        // The checkbox may be already rendered by the page
+        let renderedEl = this.editorEl.children('.custom-checkbox').find('input[type=checkbox]')
+        this.noneEl = renderedEl.length ?
+            renderedEl :
+            this._newSwitch(this.editorEl, 'Set to none', this.initialValue === null, 'custom-checkbox')
 
         this.sourceEl = $('<div>', {
             'class': 'py-2',
@@ -99,18 +91,38 @@ class FeattleEditor {
         this.editorEl.append(this.sourceEl)
         this.innerEditor = new FeattleEditor(this.sourceEl)
 
-        this.switchEl.change(() => {
-            if (this.switchEl.prop('checked')) {
-                this.sourceEl.show()
-            } else {
-                this.sourceEl.hide()
-            }
+        let updateDisabled = () => this.innerEditor._setDisabled(this.noneEl.prop('checked'))
+        this.noneEl.change(updateDisabled)
+        updateDisabled()
+
+        this.getValue = () => this.noneEl.prop('checked') ? null : this.innerEditor.getValue()
+    }
+
+    _prepareObject(fields) {
+        // The object may be the inner value of a feattle set to none
+        let initialValue = this.initialValue || {}
+        let fieldEditors = fields.map(field => {
+            let fieldValue = field.name in initialValue ?
+                initialValue[field.name] :
+                this._defaultValue(field.kind)
+            let fieldEl = $('<div>', {
+                attr: {
+                    'data-format': JSON.stringify(field.kind),
+                    'data-value': JSON.stringify(fieldValue)
+                }
+            })
+            this.editorEl.append($('<div>', {
+                'class': 'form-group',
+                append: [$('<label>', {text: field.name}), fieldEl]
+            }))
+            return [field.name, new FeattleEditor(fieldEl)]
         })
-        if (this.initialValue === null) {
-            this.sourceEl.hide()
+        this.getValue = () => {
+            let value = {}
+            fieldEditors.forEach(([name, editor]) => value[name] = editor.getValue())
+            this._check(this.format, value)
+            return value
         }
-
-        this.getValue = () => this.switchEl.prop('checked') ? this.innerEditor.getValue() : null
     }
 
     _prepareOther() {
@@ -129,7 +141,35 @@ class FeattleEditor {
         }
     }
 
-    _newSwitch(rootEl, label, initialValue) {
+    _defaultValue(format) {
+        if (format.tag === 'Bool') {
+            return false
+        } else if (format.tag === 'Integer' || format.tag === 'Float') {
+            return 0
+        } else if (format.tag === 'String' && format.content.tag === 'Choices') {
+            return format.content.content[0]
+        } else if (format.tag === 'String') {
+            return ''
+        } else if (format.tag === 'List' || format.tag === 'Set') {
+            return []
+        } else if (format.tag === 'Map') {
+            return {}
+        } else if (format.tag === 'Object') {
+            let value = {}
+            format.content.forEach(field => value[field.name] = this._defaultValue(field.kind))
+            return value
+        } else {
+            return null
+        }
+    }
+
+    _setDisabled(disabled) {
+        this.editorEl.find('input, select').prop('disabled', disabled)
+        this.editorEl.find('.ace_editor').each((_, el) => ace.edit(el).setReadOnly(disabled))
+        this.editorEl.css('opacity', disabled ? 0.5 : '')
+    }
+
+    _newSwitch(rootEl, label, initialValue, controlClass = 'custom-switch') {
         let inputId = String(Math.random())
         let switchEl = $('<input>', {
             type: 'checkbox',
@@ -138,7 +178,7 @@ class FeattleEditor {
             checked: initialValue
         })
         rootEl.append($('<div>', {
-            'class': 'custom-control custom-switch',
+            'class': 'custom-control ' + controlClass,
             append: [
                 switchEl,
                 $('<label>', {
@@ -185,6 +225,31 @@ class FeattleEditor {
                 this._checkString(keyFormat, key)
                 this._check(elFormat, el)
             })
+        } else if (format.tag === 'Entries') {
+            assert(Array.isArray(value), () => `${value} is not an array`)
+            let keyFormat = format.content[0]
+            let elFormat = format.content[1]
+            value.forEach(entry => {
+                assert(
+                    Array.isArray(entry) && entry.length === 2,
+                    () => `${JSON.stringify(entry)} is not a [key, value] pair`
+                )
+                this._check(keyFormat, entry[0])
+                this._check(elFormat, entry[1])
+            })
+        } else if (format.tag === 'Tuple') {
+            assert(
+                Array.isArray(value) && value.length === format.content.length,
+                () => `${JSON.stringify(value)} is not an array of length ${format.content.length}`
+            )
+            format.content.forEach((elFormat, i) => this._check(elFormat, value[i]))
+        } else if (format.tag === 'Object') {
+            assert(
+                value !== null &&
+                typeof value === 'object' &&
+                !Array.isArray(value), () => `${value} is not an object`
+            )
+            format.content.forEach(field => this._check(field.kind, value[field.name]))
         } else {
             assert(false, () => 'Unknown data type')
         }
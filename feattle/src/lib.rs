@@ -110,7 +110,16 @@
 //! cargo features:
 //!
 //! - **uuid**: will add support for [`uuid::Uuid`].
+//! - **regex**: will add support for [`regex::Regex`].
+//! - **smallvec**: will add support for [`smallvec::SmallVec`].
+//! - **ordered-float**: will add support for [`ordered_float::OrderedFloat<f64>`].
+//! - **audit**: will add support for signing the history entries, to detect tampering
+//! - **tokio**: will add support for carrying a [`FeattleContext`] in a tokio task-local
+//! - **arc-swap**: will make the getters lock-free, at the cost of copying all values on changes
+//! - **strict-serde**: will fail to load persisted data with unknown fields
+//! - **unique-keys**: will reject keys that collide as environment variable names or in snake case
 //! - **rusoto_s3**: provides [`RusotoS3`] to integrate with AWS' S3
+//! - **object_store**: provides [`ObjectStore`] to integrate with S3, GCS, Azure and others
 //! - **aws_sdk_s3**: provides [`S3`] to integrate with AWS' S3
 //! - **warp**: provides [`run_warp_server`] for a read-to-use integration with [`warp`]
 //! - **axum**: provides [`axum_router`] for a read-to-use integration with [`axum`]